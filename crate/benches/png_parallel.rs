@@ -0,0 +1,34 @@
+//! Lossless PNG encode timing for a large screenshot-like image.
+//! Run `cargo bench --bench png_parallel` with and without `--features parallel`.
+
+use std::time::Instant;
+
+fn main() {
+    let (width, height) = (2560u32, 1600u32);
+    // Flat UI-like regions with text-like noise bands
+    let data: Vec<u8> = (0..width * height)
+        .flat_map(|i| {
+            let (x, y) = (i % width, i / width);
+            let v = if y % 40 < 12 { (i.wrapping_mul(2654435761) >> 24) as u8 } else { (x / 160 * 16) as u8 };
+            [v, v, 255 - v, 255]
+        })
+        .collect();
+    let config: nanopng_core::Config =
+        serde_json::from_str(r#"{"format":"Png","quality":80,"transparent":true,"lossless":true,"dithering":1.0,"chroma_subsampling":true}"#)
+            .unwrap();
+
+    let runs = 3;
+    let start = Instant::now();
+    let mut size = 0;
+    for _ in 0..runs {
+        size = nanopng_core::process(&data, width, height, &config).unwrap().data.len();
+    }
+    println!(
+        "lossless png {}x{} (parallel: {}): {:?}/run, {} bytes",
+        width,
+        height,
+        cfg!(feature = "parallel"),
+        start.elapsed() / runs,
+        size
+    );
+}
@@ -1,6 +1,9 @@
-use ravif::{BitDepth, Encoder, Img, RGBA8};
+use rav1e::prelude::PixelRange;
+use ravif::{BitDepth, ColorModel, Encoder, Img, MatrixCoefficients, RGBA8};
 use rgb::FromSlice;
 
+use super::{extra_option, Extra, Metadata};
+
 // Helper to cast bytes
 trait AsPixels {
     fn as_pixels(&self) -> &[RGBA8];
@@ -12,17 +15,56 @@ impl AsPixels for [u8] {
     }
 }
 
-pub fn encode_avif(
-    data: &[u8],
-    width: u32,
-    height: u32,
-    quality: u8,
-    speed: u8,
-    bit_depth: u8,
-) -> Result<Vec<u8>, String> {
+/// Settings for `encode_avif`. The default is 8-bit BT.601 with unassociated
+/// alpha at quality 80 and speed 6.
+#[derive(Debug, Clone, Copy)]
+pub struct AvifOptions<'a> {
+    pub quality: u8,
+    pub speed: u8,
+    pub bit_depth: u8,
+    pub deterministic: bool,
+    pub progressive: bool,
+    pub matrix: &'a str, // "bt601" (default), "bt709" or "identity" (RGB, no YUV conversion)
+    pub alpha_mode: &'a str, // "unassociated" (default) or "premultiplied" (color stored times alpha)
+    pub metadata: Option<&'a Metadata>, // EXIF is written as an Exif item; an ICC profile is an error
+    pub extra: Option<&'a Extra>, // Honors "avif.alpha_quality" (0-100, default follows quality)
+}
+
+impl Default for AvifOptions<'_> {
+    fn default() -> Self {
+        AvifOptions {
+            quality: 80,
+            speed: 6,
+            bit_depth: 8,
+            deterministic: false,
+            progressive: false,
+            matrix: "bt601",
+            alpha_mode: "unassociated",
+            metadata: None,
+            extra: None,
+        }
+    }
+}
+
+/// Progressive (layered) AVIF needs an encoder that can emit multiple
+/// spatial/quality layers plus the `a1lx`/`lsel` item properties. ravif 0.11
+/// (rav1e + avif-serialize) only writes single-layer still images, so
+/// `progressive` is rejected loudly until the encoder stack supports it.
+pub fn encode_avif(data: &[u8], width: u32, height: u32, options: &AvifOptions) -> Result<Vec<u8>, String> {
+    let AvifOptions { quality, speed, bit_depth, deterministic, progressive, matrix, alpha_mode, metadata, extra } = *options;
+    if progressive {
+        return Err("Progressive AVIF is not supported by the ravif encoder".to_string());
+    }
+    // avif-serialize has no colr ICC variant, so only EXIF can be carried over
+    if metadata.is_some_and(|m| m.icc_profile.is_some()) {
+        return Err("Preserving an ICC profile in AVIF is not supported by the ravif encoder".to_string());
+    }
+
+    super::check_nonzero(width, height)?;
+    super::check_rgba_len(data, width, height)?;
+
     // 1. Wrap data
     // ravif expects Img<[RGBA8]>
-    // We trust input is correct length RGBA
     let img = Img::new(
         data.as_pixels(), 
         width as usize, 
@@ -36,15 +78,288 @@ pub fn encode_avif(
         _ => BitDepth::Eight, // Default to 8-bit for compatibility
     };
 
-    let encoder = Encoder::new()
+    let color_model = match matrix {
+        "bt601" | "bt709" => ColorModel::YCbCr,
+        "identity" => ColorModel::RGB,
+        _ => {
+            return Err(format!(
+                "Unsupported AVIF matrix '{}': expected bt709, bt601 or identity",
+                matrix
+            ))
+        }
+    };
+
+    let alpha_color_mode = match alpha_mode {
+        "unassociated" => ravif::AlphaColorMode::UnassociatedClean,
+        "premultiplied" => ravif::AlphaColorMode::Premultiplied,
+        _ => {
+            return Err(format!(
+                "Unsupported AVIF alpha mode '{}': expected unassociated or premultiplied",
+                alpha_mode
+            ))
+        }
+    };
+    let premultiplied = alpha_color_mode == ravif::AlphaColorMode::Premultiplied;
+
+    let mut encoder = Encoder::new()
         .with_quality(quality as f32)
         .with_speed(speed)
         .with_bit_depth(depth)
-        .with_alpha_color_mode(ravif::AlphaColorMode::UnassociatedClean);
+        .with_internal_color_model(color_model)
+        .with_alpha_color_mode(alpha_color_mode)
+        // A single thread keeps rav1e's tile/frame decisions reproducible
+        .with_num_threads(if deterministic { Some(1) } else { None });
+    if let Some(alpha_quality) = extra_option::<f32>(extra, "avif.alpha_quality")? {
+        encoder = encoder.with_alpha_quality(alpha_quality.clamp(0.0, 100.0));
+    }
 
     // 3. Encode
-    let res = encoder.encode_rgba(img)
-        .map_err(|e| format!("AVIF encoding failed: {}", e))?;
+    // ravif's own YCbCr conversion is always BT.601, and its premultiplied
+    // conversion divides by alpha instead of multiplying (and blanks opaque
+    // pixels), so those planes are built here and handed over raw
+    let res = if matrix == "bt709" || premultiplied {
+        encode_planes(&encoder, img, depth, matrix, premultiplied)
+    } else {
+        encoder.encode_rgba(img)
+    }
+    .map_err(|e| format!("AVIF encoding failed: {}", e))?;
+
+    match metadata.and_then(|m| m.exif.as_deref()) {
+        Some(exif) => {
+            let bits = if depth == BitDepth::Ten { 10 } else { 8 };
+            Ok(remux(&res, Some(exif), matrix, premultiplied, width, height, bits))
+        }
+        None => Ok(res.avif_file),
+    }
+}
+
+/// Rewrite ravif's output with avif-serialize, adding `exif` as an `Exif`
+/// item. ravif only hands back the finished file, but avif-serialize lays
+/// `mdat` out last as [exif, alpha, color], so the AV1 payloads are its tail.
+/// The other settings must match what ravif wrote for the same encode.
+fn remux(
+    res: &ravif::EncodedImage,
+    exif: Option<&[u8]>,
+    matrix: &str,
+    premultiplied: bool,
+    width: u32,
+    height: u32,
+    bits: u8,
+) -> Vec<u8> {
+    let file = &res.avif_file;
+    let color_start = file.len() - res.color_byte_size;
+    let color = &file[color_start..];
+    let alpha = (res.alpha_byte_size > 0).then(|| &file[color_start - res.alpha_byte_size..color_start]);
 
-    Ok(res.avif_file)
+    let mut avif = avif_serialize::Aviffy::new();
+    avif.matrix_coefficients(match matrix {
+        "bt709" => avif_serialize::constants::MatrixCoefficients::Bt709,
+        "identity" => avif_serialize::constants::MatrixCoefficients::Rgb,
+        _ => avif_serialize::constants::MatrixCoefficients::Bt601,
+    })
+    .premultiplied_alpha(premultiplied);
+    if let Some(exif) = exif {
+        // HEIF Exif items start with the offset of the TIFF header in the payload
+        let mut payload = 0u32.to_be_bytes().to_vec();
+        payload.extend_from_slice(exif);
+        avif.set_exif(payload);
+    }
+    avif.to_vec(color, alpha, width, height, bits)
+}
+
+/// Kr, Kg, Kb luma weights of ITU-R BT.709.
+const BT709: [f32; 3] = [0.2126, 0.7152, 0.0722];
+/// Kr, Kg, Kb luma weights of ITU-R BT.601.
+const BT601: [f32; 3] = [0.299, 0.587, 0.114];
+
+/// Encode full-range YCbCr planes for `matrix` ("identity": GBR planes),
+/// optionally premultiplying color by alpha first. Alpha (written only when
+/// some pixel isn't opaque) is passed through as-is, without ravif's cleanup
+/// of color under transparent pixels.
+fn encode_planes(
+    encoder: &Encoder,
+    img: Img<&[RGBA8]>,
+    depth: BitDepth,
+    matrix: &str,
+    premultiply: bool,
+) -> Result<ravif::EncodedImage, ravif::Error> {
+    let bits = if depth == BitDepth::Ten { 10 } else { 8 };
+    let max = ((1 << bits) - 1) as f32;
+    let scale = max / 255.0;
+    let shift = (max * 0.5).round();
+    let (weights, coefficients) = match matrix {
+        "bt709" => (Some(BT709), MatrixCoefficients::BT709),
+        "identity" => (None, MatrixCoefficients::Identity),
+        _ => (Some(BT601), MatrixCoefficients::BT601),
+    };
+    let to_ycbcr = |px: &RGBA8| {
+        let alpha = if premultiply { px.a as f32 / 255.0 } else { 1.0 };
+        let [r, g, b] = [px.r, px.g, px.b].map(|v| v as f32 * alpha * scale);
+        let planes = match weights {
+            Some([kr, kg, kb]) => {
+                let y = kr * r + kg * g + kb * b;
+                [y, (b - y) * 0.5 / (1.0 - kb) + shift, (r - y) * 0.5 / (1.0 - kr) + shift]
+            }
+            None => [g, b, r],
+        };
+        planes.map(|v| v.round().clamp(0.0, max))
+    };
+
+    let (width, height) = (img.width(), img.height());
+    let pixels: Vec<RGBA8> = img.pixels().collect();
+    let has_alpha = pixels.iter().any(|px| px.a != 255);
+    if bits == 10 {
+        let planes = pixels.iter().map(|px| to_ycbcr(px).map(|v| v as u16));
+        let alpha = has_alpha.then(|| pixels.iter().map(|px| (px.a as u16) << 2 | (px.a as u16) >> 6));
+        encoder.encode_raw_planes_10_bit(width, height, planes, alpha, PixelRange::Full, coefficients)
+    } else {
+        let planes = pixels.iter().map(|px| to_ycbcr(px).map(|v| v as u8));
+        let alpha = has_alpha.then(|| pixels.iter().map(|px| px.a));
+        encoder.encode_raw_planes_8_bit(width, height, planes, alpha, PixelRange::Full, coefficients)
+    }
+}
+
+/// Check for an ISOBMFF `ftyp` box whose major or compatible brands include
+/// `avif` (still) or `avis` (sequence). HEIF brands like `heic` and the
+/// generic `mif1` alone don't count: those files may hold HEVC, not AV1.
+pub fn is_avif(data: &[u8]) -> bool {
+    if data.len() < 16 || &data[4..8] != b"ftyp" {
+        return false;
+    }
+    let size = u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as usize;
+    let Some(ftyp) = data.get(8..size.max(16)) else { return false };
+    // Major brand, minor version, then compatible brands
+    std::iter::once(&ftyp[..4])
+        .chain(ftyp[8..].chunks_exact(4))
+        .any(|brand| brand == b"avif" || brand == b"avis")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fastest reproducible encode, for tests that only inspect the container.
+    const FAST: AvifOptions = AvifOptions {
+        quality: 80,
+        speed: 10,
+        bit_depth: 8,
+        deterministic: true,
+        progressive: false,
+        matrix: "bt601",
+        alpha_mode: "unassociated",
+        metadata: None,
+        extra: None,
+    };
+
+    /// `ftyp` box with the given major and compatible brands.
+    fn ftyp(major: &[u8; 4], compatible: &[&[u8; 4]]) -> Vec<u8> {
+        let mut data = ((16 + 4 * compatible.len()) as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(b"ftyp");
+        data.extend_from_slice(major);
+        data.extend_from_slice(&0u32.to_be_bytes());
+        compatible.iter().for_each(|brand| data.extend_from_slice(*brand));
+        data
+    }
+
+    #[test]
+    fn test_is_avif_checks_ftyp_brands() {
+        let data: Vec<u8> = (0..16 * 16).flat_map(|i| [i as u8, 60, 200, 255]).collect();
+        assert!(is_avif(&encode_avif(&data, 16, 16, &FAST).unwrap()));
+
+        assert!(is_avif(&ftyp(b"mif1", &[b"mif1", b"miaf", b"avif"])));
+        assert!(is_avif(&ftyp(b"avis", &[])));
+        assert!(!is_avif(&ftyp(b"heic", &[b"mif1", b"heic"])));
+        assert!(!is_avif(&ftyp(b"mif1", &[b"mif1", b"miaf"])));
+        // Brand past the declared box size belongs to the next box
+        let mut truncated = ftyp(b"heic", &[]);
+        truncated.extend_from_slice(b"avif");
+        assert!(!is_avif(&truncated));
+        assert!(!is_avif(b"RIFF\x00\x00\x00\x00WEBPVP8 "));
+    }
+
+    /// matrix_coefficients from the `colr` box (nclx: primaries, transfer,
+    /// matrix), or None when the box is omitted, which readers take as BT.601.
+    fn nclx_matrix(avif: &[u8]) -> Option<u16> {
+        let pos = avif.windows(4).position(|w| w == b"nclx")?;
+        Some(u16::from_be_bytes([avif[pos + 8], avif[pos + 9]]))
+    }
+
+    #[test]
+    fn test_premultiplied_alpha_is_signaled() {
+        // Opaque red fading to transparent across the image
+        let data: Vec<u8> = (0..16 * 16).flat_map(|i| [255, 40, 0, (i % 16 * 17) as u8]).collect();
+        let encode = |matrix, mode| encode_avif(&data, 16, 16, &AvifOptions { matrix, alpha_mode: mode, ..FAST }).unwrap();
+        // The alpha item points at its color item through an iref of this type
+        let has_prem = |avif: &[u8]| avif.windows(4).any(|w| w == b"prem");
+
+        let unassociated = encode("bt601", "unassociated");
+        let premultiplied = encode("bt601", "premultiplied");
+        assert_ne!(unassociated, premultiplied);
+        assert!(!has_prem(&unassociated));
+        assert!(has_prem(&premultiplied));
+        assert!(has_prem(&encode("bt709", "premultiplied")));
+        assert!(has_prem(&encode("identity", "premultiplied")));
+        assert!(encode_avif(&data, 16, 16, &AvifOptions { alpha_mode: "straight", ..FAST }).is_err());
+    }
+
+    #[test]
+    fn test_matrix_is_signaled_in_output() {
+        // Screenshot-like content: hard-edged saturated UI colors
+        let data: Vec<u8> = (0..16 * 16)
+            .flat_map(|i| [[255, 0, 0, 255], [0, 200, 255, 255], [30, 30, 30, 255]][i % 16 / 6])
+            .collect();
+        let encode = |matrix| encode_avif(&data, 16, 16, &AvifOptions { matrix, ..FAST }).unwrap();
+
+        let (identity, bt709, bt601) = (encode("identity"), encode("bt709"), encode("bt601"));
+        assert_eq!(nclx_matrix(&identity), Some(0));
+        assert_eq!(nclx_matrix(&bt709), Some(1));
+        assert_eq!(nclx_matrix(&bt601), None);
+        assert_ne!(identity, bt709);
+        assert!(encode_avif(&data, 16, 16, &AvifOptions { matrix: "rec2020", ..FAST }).is_err());
+    }
+
+    #[test]
+    fn test_exif_is_written_as_item() {
+        // Half the pixels translucent, so an alpha item is written too
+        let data: Vec<u8> = (0..16 * 16).flat_map(|i| [i as u8, 60, 200, if i % 2 == 0 { 255 } else { 90 }]).collect();
+        let img = || Img::new(data.as_pixels(), 16, 16);
+
+        // Remuxing without EXIF reproduces ravif's file byte for byte
+        let cases = [
+            ("bt601", false, BitDepth::Eight, 8),
+            ("bt709", true, BitDepth::Ten, 10),
+            ("identity", false, BitDepth::Eight, 8),
+        ];
+        for (matrix, premultiplied, depth, bits) in cases {
+            let color_model = if matrix == "identity" { ColorModel::RGB } else { ColorModel::YCbCr };
+            let mode = match premultiplied {
+                true => ravif::AlphaColorMode::Premultiplied,
+                false => ravif::AlphaColorMode::UnassociatedClean,
+            };
+            let encoder = Encoder::new()
+                .with_speed(10)
+                .with_bit_depth(depth)
+                .with_internal_color_model(color_model)
+                .with_alpha_color_mode(mode);
+            let res = match matrix {
+                "bt601" => encoder.encode_rgba(img()),
+                _ => encode_planes(&encoder, img(), depth, matrix, premultiplied),
+            }
+            .unwrap();
+            assert!(res.alpha_byte_size > 0);
+            assert!(remux(&res, None, matrix, premultiplied, 16, 16, bits) == res.avif_file, "{} remux differs", matrix);
+        }
+
+        let exif = b"MM\0*\0\0\0\x08nanopng-exif-marker";
+        let metadata = Metadata { exif: Some(exif.to_vec()), icc_profile: None };
+        let encode = |metadata| encode_avif(&data, 16, 16, &AvifOptions { metadata, ..FAST });
+        let (plain, tagged) = (encode(None).unwrap(), encode(Some(&metadata)).unwrap());
+        let contains = |avif: &[u8], needle: &[u8]| avif.windows(needle.len()).any(|w| w == needle);
+        assert!(!contains(&plain, b"Exif"));
+        assert!(contains(&tagged, b"Exif"));
+        assert!(contains(&tagged, &[&[0u8; 4][..], exif].concat()));
+
+        let icc = Metadata { exif: None, icc_profile: Some(vec![0; 128]) };
+        assert!(encode(Some(&icc)).unwrap_err().contains("ICC"));
+    }
 }
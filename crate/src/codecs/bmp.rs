@@ -10,8 +10,10 @@ pub fn decode_bmp(data: &[u8]) -> Result<(Vec<u8>, u32, u32), String> {
         return Err("Not a valid BMP file".to_string());
     }
 
-    // Read header info (little-endian)
+    // Read header info (little-endian). BITMAPV4HEADER/V5HEADER (108/124
+    // bytes) extend the 40-byte BITMAPINFOHEADER, so these offsets hold for all
     let data_offset = u32::from_le_bytes([data[10], data[11], data[12], data[13]]) as usize;
+    let header_size = u32::from_le_bytes([data[14], data[15], data[16], data[17]]) as usize;
     let width = i32::from_le_bytes([data[18], data[19], data[20], data[21]]);
     let height = i32::from_le_bytes([data[22], data[23], data[24], data[25]]);
     let bits_per_pixel = u16::from_le_bytes([data[28], data[29]]);
@@ -21,15 +23,71 @@ pub fn decode_bmp(data: &[u8]) -> Result<(Vec<u8>, u32, u32), String> {
         return Err(format!("Unsupported BMP compression: {}", compression));
     }
 
+    // Validate every header field before trusting it for indexing or allocation.
+    // Sizes are all checked: usize is 32 bits on wasm32, where a huge field
+    // would otherwise wrap to something small that passes the bounds checks
+    let header_end = header_size
+        .checked_add(14)
+        .filter(|&end| header_size >= 40 && end <= data.len())
+        .ok_or_else(|| format!("Invalid BMP info header size: {}", header_size))?;
+    if bits_per_pixel != 24 && bits_per_pixel != 32 {
+        return Err(format!("Unsupported BMP bit depth: {}", bits_per_pixel));
+    }
+    if width <= 0 || height == 0 {
+        return Err(format!("Invalid BMP dimensions: {}x{}", width, height));
+    }
+    // BI_BITFIELDS channel masks live inside V2+ headers (52+ bytes), otherwise
+    // in the 12 bytes straight after a 40-byte header
+    let mask_offset = if header_size >= 52 { 14 + 40 } else { header_end };
+    let bitfields_end = if compression == 3 && header_size < 52 { mask_offset + 12 } else { header_end };
+    if data_offset < bitfields_end || data_offset > data.len() {
+        return Err(format!(
+            "Invalid BMP pixel data offset {} (file size {})",
+            data_offset,
+            data.len()
+        ));
+    }
+
+    let mask_at = |offset: usize| u32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]]);
+    let masks = if compression == 3 {
+        // Only V3+ headers (56+ bytes) carry an alpha mask
+        let alpha = if header_size >= 56 { mask_at(14 + 52) } else { 0xFF00_0000 };
+        [mask_at(mask_offset), mask_at(mask_offset + 4), mask_at(mask_offset + 8), alpha]
+    } else {
+        [0x00FF_0000, 0x0000_FF00, 0x0000_00FF, 0xFF00_0000]
+    };
+
     let width = width.unsigned_abs();
     let height_abs = height.unsigned_abs();
     let is_top_down = height < 0;
 
     // Calculate row size (rows are padded to 4-byte boundaries)
+    let too_large = || format!("BMP dimensions too large: {}x{}", width, height_abs);
     let bytes_per_pixel = (bits_per_pixel / 8) as usize;
-    let row_size = ((width as usize * bytes_per_pixel + 3) / 4) * 4;
+    let row_bytes = (width as usize).checked_mul(bytes_per_pixel).ok_or_else(too_large)?;
+    let row_size = row_bytes.checked_next_multiple_of(4).ok_or_else(too_large)?;
+
+    // The last row may omit its padding, so only require its pixel bytes
+    let required = row_size
+        .checked_mul(height_abs as usize - 1)
+        .and_then(|n| n.checked_add(row_bytes))
+        .ok_or_else(too_large)?;
+    if required > data.len() - data_offset {
+        return Err(format!(
+            "BMP data truncated: {}x{} at {} bpp needs {} bytes, found {}",
+            width,
+            height_abs,
+            bits_per_pixel,
+            required,
+            data.len() - data_offset
+        ));
+    }
 
-    let mut rgba = vec![0u8; (width * height_abs * 4) as usize];
+    let rgba_len = (width as usize)
+        .checked_mul(height_abs as usize)
+        .and_then(|n| n.checked_mul(4))
+        .ok_or_else(too_large)?;
+    let mut rgba = vec![0u8; rgba_len];
 
     for y in 0..height_abs {
         let src_y = if is_top_down { y } else { height_abs - 1 - y };
@@ -37,7 +95,7 @@ pub fn decode_bmp(data: &[u8]) -> Result<(Vec<u8>, u32, u32), String> {
 
         for x in 0..width {
             let src_idx = row_start + (x as usize * bytes_per_pixel);
-            let dst_idx = ((y * width + x) * 4) as usize;
+            let dst_idx = (y as usize * width as usize + x as usize) * 4;
 
             if src_idx + bytes_per_pixel > data.len() {
                 return Err("BMP data truncated".to_string());
@@ -52,11 +110,11 @@ pub fn decode_bmp(data: &[u8]) -> Result<(Vec<u8>, u32, u32), String> {
                     rgba[dst_idx + 3] = 255; // A
                 }
                 32 => {
-                    // BGRA -> RGBA
-                    rgba[dst_idx] = data[src_idx + 2]; // R
-                    rgba[dst_idx + 1] = data[src_idx + 1]; // G
-                    rgba[dst_idx + 2] = data[src_idx]; // B
-                    rgba[dst_idx + 3] = data[src_idx + 3]; // A
+                    // Channel masks -> RGBA (BGRA in the default layout)
+                    let px = mask_at(src_idx);
+                    for (c, &mask) in masks.iter().enumerate() {
+                        rgba[dst_idx + c] = extract_channel(px, mask, if c == 3 { 255 } else { 0 });
+                    }
                 }
                 _ => return Err(format!("Unsupported BMP bit depth: {}", bits_per_pixel)),
             }
@@ -66,13 +124,23 @@ pub fn decode_bmp(data: &[u8]) -> Result<(Vec<u8>, u32, u32), String> {
     Ok((rgba, width, height_abs))
 }
 
+/// Scale the bits of `px` selected by `mask` to 0-255; `missing` when the mask is empty.
+fn extract_channel(px: u32, mask: u32, missing: u8) -> u8 {
+    if mask == 0 {
+        return missing;
+    }
+    let value = (px & mask) >> mask.trailing_zeros();
+    let max = mask >> mask.trailing_zeros();
+    (value as u64 * 255 / max as u64) as u8
+}
+
 /// Check if data is a BMP file by checking magic bytes
 pub fn is_bmp(data: &[u8]) -> bool {
     data.len() >= 2 && &data[0..2] == b"BM"
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
 
     #[test]
@@ -97,4 +165,90 @@ mod tests {
         assert!(result.is_err());
         assert!(result.unwrap_err().contains("Not a valid BMP"));
     }
+
+    /// Build a bottom-up BMP with a BITMAPINFOHEADER from BGR(A) rows (unpadded).
+    pub(crate) fn make_bmp(width: i32, height: i32, bits_per_pixel: u16, pixels: &[u8]) -> Vec<u8> {
+        let bytes_per_pixel = (bits_per_pixel / 8) as usize;
+        let row_bytes = width.unsigned_abs() as usize * bytes_per_pixel;
+        let row_size = row_bytes.div_ceil(4) * 4;
+        let mut data = Vec::new();
+        data.extend_from_slice(b"BM");
+        data.extend_from_slice(&0u32.to_le_bytes()); // file size (unused)
+        data.extend_from_slice(&0u32.to_le_bytes()); // reserved
+        data.extend_from_slice(&54u32.to_le_bytes()); // pixel data offset
+        data.extend_from_slice(&40u32.to_le_bytes()); // info header size
+        data.extend_from_slice(&width.to_le_bytes());
+        data.extend_from_slice(&height.to_le_bytes());
+        data.extend_from_slice(&1u16.to_le_bytes()); // planes
+        data.extend_from_slice(&bits_per_pixel.to_le_bytes());
+        data.extend_from_slice(&[0u8; 24]); // compression .. important colors
+        for row in pixels.chunks(row_bytes) {
+            data.extend_from_slice(row);
+            data.resize(data.len() + row_size - row_bytes, 0);
+        }
+        data
+    }
+
+    #[test]
+    fn test_decode_bmp_24bit() {
+        // Bottom-up: first stored row is the bottom row (blue, then green)
+        let data = make_bmp(1, 2, 24, &[255, 0, 0, 0, 255, 0]);
+        let (pixels, width, height) = decode_bmp(&data).unwrap();
+        assert_eq!((width, height), (1, 2));
+        assert_eq!(pixels, vec![0, 255, 0, 255, 0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn test_decode_bmp_v5_header_with_bitfields() {
+        // 2x1 top-down, 32 bpp BI_BITFIELDS with RGBA byte order (R in the low byte),
+        // followed by a stray 8-byte color table before the pixel data
+        let mut data = Vec::new();
+        data.extend_from_slice(b"BM");
+        data.extend_from_slice(&[0u8; 8]); // file size, reserved
+        data.extend_from_slice(&(14u32 + 124 + 8).to_le_bytes());
+        data.extend_from_slice(&124u32.to_le_bytes()); // BITMAPV5HEADER
+        data.extend_from_slice(&2i32.to_le_bytes());
+        data.extend_from_slice(&(-1i32).to_le_bytes());
+        data.extend_from_slice(&1u16.to_le_bytes());
+        data.extend_from_slice(&32u16.to_le_bytes());
+        data.extend_from_slice(&3u32.to_le_bytes()); // BI_BITFIELDS
+        data.extend_from_slice(&[0u8; 20]); // image size .. important colors
+        for mask in [0x0000_00FFu32, 0x0000_FF00, 0x00FF_0000, 0xFF00_0000] {
+            data.extend_from_slice(&mask.to_le_bytes());
+        }
+        data.resize(14 + 124, 0); // color space, endpoints, gamma, intent, profile
+        data.extend_from_slice(&[0xAA; 8]);
+        data.extend_from_slice(&[10, 20, 30, 40, 50, 60, 70, 80]);
+
+        let (pixels, width, height) = decode_bmp(&data).unwrap();
+        assert_eq!((width, height), (2, 1));
+        assert_eq!(pixels, [10, 20, 30, 40, 50, 60, 70, 80]);
+    }
+
+    #[test]
+    fn test_decode_bmp_corrupt_data_offset() {
+        let mut data = make_bmp(2, 2, 24, &[0u8; 12]);
+        data[10..14].copy_from_slice(&10_000u32.to_le_bytes());
+        let result = decode_bmp(&data);
+        assert!(result.unwrap_err().contains("pixel data offset"));
+    }
+
+    #[test]
+    fn test_decode_bmp_absurd_width() {
+        let mut data = make_bmp(2, 2, 24, &[0u8; 12]);
+        data[18..22].copy_from_slice(&i32::MAX.to_le_bytes());
+        let result = decode_bmp(&data);
+        assert!(result.unwrap_err().contains("truncated"));
+
+        // Width whose row size wraps 32-bit usize (as on wasm32) to 4 bytes
+        let mut data = make_bmp(2, 1, 32, &[0u8; 8]);
+        data[18..22].copy_from_slice(&0x4000_0001i32.to_le_bytes());
+        let err = decode_bmp(&data).unwrap_err();
+        assert!(err.contains("truncated") || err.contains("too large"), "{}", err);
+
+        // Header size that wraps 14 + header_size
+        let mut data = make_bmp(2, 1, 32, &[0u8; 8]);
+        data[14..18].copy_from_slice(&(u32::MAX - 5).to_le_bytes());
+        assert!(decode_bmp(&data).unwrap_err().contains("info header size"));
+    }
 }
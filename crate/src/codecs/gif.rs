@@ -1,10 +1,115 @@
-use gif::{DecodeOptions, ColorOutput};
+use gif::{Decoder, DecodeOptions, ColorOutput, DisposalMethod, Frame as GifFrame};
+use serde::Serialize;
 
-/// Decode a GIF image to RGBA pixels.
-/// For animated GIFs, only decodes the first frame.
+/// One composited animation frame from `decode_gif_frames`.
+#[derive(Serialize, Debug, Clone)]
+pub struct Frame {
+    #[serde(with = "serde_bytes")]
+    pub pixels: Vec<u8>, // RGBA, logical screen size
+    pub delay: u16,      // Centiseconds until the next frame
+}
+
+/// Decode a GIF image to RGBA pixels at the logical screen size.
+/// For animated GIFs, only decodes the first frame (see `decode_gif_frames`);
+/// a frame smaller than the screen is placed at its offset on a transparent
+/// canvas. Pixels using the transparent color index come out with alpha 0.
 /// Returns (pixels, width, height)
 pub fn decode_gif(data: &[u8]) -> Result<(Vec<u8>, u32, u32), String> {
-    // Validate GIF magic bytes
+    decode_gif_frame_at(data, 0)
+}
+
+/// Logical-screen RGBA canvas that GIF frames are composited onto, applying
+/// each frame's disposal method before the next one is drawn.
+pub(crate) struct GifCanvas {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+    /// Opaque global-palette background color, if the screen declares a valid one
+    pub background: Option<[u8; 4]>,
+    /// Canvas before the last frame, kept when that frame disposes to "previous"
+    saved: Option<Vec<u8>>,
+    /// Rect, disposal and restore-to-background fill of the last drawn frame,
+    /// applied before the next one
+    pending: Option<(DisposalMethod, [u8; 4], u32, u32, u32, u32)>,
+}
+
+impl GifCanvas {
+    pub fn new(width: u32, height: u32) -> Self {
+        GifCanvas {
+            width,
+            height,
+            pixels: vec![0; width as usize * height as usize * 4],
+            background: None,
+            saved: None,
+            pending: None,
+        }
+    }
+
+    /// Canvas for the decoder's logical screen, with its background color.
+    pub fn for_decoder<R: std::io::Read>(decoder: &Decoder<R>) -> Self {
+        let mut canvas = GifCanvas::new(decoder.width() as u32, decoder.height() as u32);
+        canvas.background = decoder.bg_color().and_then(|index| {
+            let rgb = decoder.global_palette()?.get(index * 3..index * 3 + 3)?;
+            Some([rgb[0], rgb[1], rgb[2], 255])
+        });
+        canvas
+    }
+
+    /// Dispose the previous frame, then draw `frame` (RGBA buffer) over the
+    /// canvas. Transparent frame pixels leave the canvas untouched; parts of
+    /// the frame outside the logical screen are clipped.
+    pub fn draw(&mut self, frame: &GifFrame) {
+        match self.pending.take() {
+            Some((DisposalMethod::Background, fill, x, y, w, h)) => self.clear(x, y, w, h, fill),
+            Some((DisposalMethod::Previous, ..)) => {
+                if let Some(saved) = self.saved.take() {
+                    self.pixels = saved;
+                }
+            }
+            _ => {}
+        }
+
+        let (left, top) = (frame.left as u32, frame.top as u32);
+        let (fw, fh) = (frame.width as u32, frame.height as u32);
+        if frame.dispose == DisposalMethod::Previous {
+            self.saved = Some(self.pixels.clone());
+        }
+        for y in 0..fh.min(self.height.saturating_sub(top)) {
+            for x in 0..fw.min(self.width.saturating_sub(left)) {
+                let src = ((y * fw + x) * 4) as usize;
+                let Some(px) = frame.buffer.get(src..src + 4) else { continue };
+                if px[3] == 0 {
+                    continue;
+                }
+                let dst = (((top + y) * self.width + left + x) * 4) as usize;
+                self.pixels[dst..dst + 4].copy_from_slice(px);
+            }
+        }
+        // Frames with a transparent index restore to transparent, as browsers
+        // do; without one, nothing could show through, so use the screen's
+        // background color instead of punching a hole
+        let fill = match (frame.transparent, self.background) {
+            (None, Some(background)) => background,
+            _ => [0; 4],
+        };
+        self.pending = Some((frame.dispose, fill, left, top, fw, fh));
+    }
+
+    /// Reset a rect to `fill` (GIF "restore to background").
+    fn clear(&mut self, x: u32, y: u32, w: u32, h: u32, fill: [u8; 4]) {
+        for row in y..(y + h).min(self.height) {
+            let start = ((row * self.width + x.min(self.width)) * 4) as usize;
+            let end = ((row * self.width + (x + w).min(self.width)) * 4) as usize;
+            self.pixels[start..end].chunks_exact_mut(4).for_each(|px| px.copy_from_slice(&fill));
+        }
+    }
+}
+
+/// Decode frame `index` (0-based) of a GIF, composited onto the logical screen
+/// with every earlier frame's disposal applied. Frames are streamed, so only
+/// the canvas (plus one saved copy for "previous" disposal) is held in memory.
+/// Returns (pixels, width, height)
+pub fn decode_gif_frame_at(data: &[u8], index: usize) -> Result<(Vec<u8>, u32, u32), String> {
     if !is_gif(data) {
         return Err("Not a valid GIF file".to_string());
     }
@@ -16,29 +121,66 @@ pub fn decode_gif(data: &[u8]) -> Result<(Vec<u8>, u32, u32), String> {
         .read_info(data)
         .map_err(|e| format!("Failed to read GIF: {:?}", e))?;
 
-    let width = decoder.width() as u32;
-    let height = decoder.height() as u32;
+    let mut canvas = GifCanvas::for_decoder(&decoder);
+    for frame_index in 0..=index {
+        let frame = decoder
+            .read_next_frame()
+            .map_err(|e| format!("Failed to decode GIF frame {}: {:?}", frame_index, e))?
+            .ok_or_else(|| match frame_index {
+                0 => "GIF has no frames".to_string(),
+                _ => format!("GIF has {} frames, frame {} requested", frame_index, index),
+            })?;
+        canvas.draw(frame);
+    }
 
-    // Read the first frame
-    let frame = decoder
-        .read_next_frame()
-        .map_err(|e| format!("Failed to decode GIF frame: {:?}", e))?
-        .ok_or_else(|| "GIF has no frames".to_string())?;
+    Ok((canvas.pixels, canvas.width, canvas.height))
+}
 
-    // The frame buffer contains RGBA data
-    let pixels = frame.buffer.to_vec();
+/// Decode every frame of a GIF, each composited onto the logical screen
+/// (disposal applied) as it would be displayed, with its delay.
+/// Returns (frames, width, height)
+pub fn decode_gif_frames(data: &[u8]) -> Result<(Vec<Frame>, u32, u32), String> {
+    if !is_gif(data) {
+        return Err("Not a valid GIF file".to_string());
+    }
 
-    // Handle case where frame is smaller than canvas (dispose method)
-    // For now, we assume frame fills the canvas
-    if pixels.len() != (width * height * 4) as usize {
-        return Err(format!(
-            "GIF frame size mismatch: expected {}, got {}",
-            width * height * 4,
-            pixels.len()
-        ));
+    let mut decoder_opts = DecodeOptions::new();
+    decoder_opts.set_color_output(ColorOutput::RGBA);
+
+    let mut decoder = decoder_opts
+        .read_info(data)
+        .map_err(|e| format!("Failed to read GIF: {:?}", e))?;
+
+    let mut canvas = GifCanvas::for_decoder(&decoder);
+    let mut frames = Vec::new();
+    while let Some(frame) = decoder
+        .read_next_frame()
+        .map_err(|e| format!("Failed to decode GIF frame {}: {:?}", frames.len(), e))?
+    {
+        canvas.draw(frame);
+        frames.push(Frame { pixels: canvas.pixels.clone(), delay: frame.delay });
     }
+    if frames.is_empty() {
+        return Err("GIF has no frames".to_string());
+    }
+
+    Ok((frames, canvas.width, canvas.height))
+}
+
+/// Decode every composited frame of a GIF and lay them out left to right in
+/// one RGBA image of `frame_width * frame_count` x `frame_height`.
+/// Returns (pixels, width, height)
+pub fn decode_gif_sprite_strip(data: &[u8]) -> Result<(Vec<u8>, u32, u32), String> {
+    let (frames, width, height) = decode_gif_frames(data)?;
 
-    Ok((pixels, width, height))
+    let row_bytes = width as usize * 4;
+    let mut strip = Vec::with_capacity(row_bytes * frames.len() * height as usize);
+    for y in 0..height as usize {
+        for frame in &frames {
+            strip.extend_from_slice(&frame.pixels[y * row_bytes..(y + 1) * row_bytes]);
+        }
+    }
+    Ok((strip, width * frames.len() as u32, height))
 }
 
 /// Check if data is a GIF file by checking magic bytes
@@ -47,3 +189,201 @@ pub fn is_gif(data: &[u8]) -> bool {
         &data[0..6] == b"GIF87a" || &data[0..6] == b"GIF89a"
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gif::{Encoder, Frame};
+
+    /// Encode indexed rows as a GIF; interlaced frames store rows in the
+    /// four-pass order (every 8th from 0, every 8th from 4, every 4th from 2, odd rows).
+    fn make_gif(width: u16, height: u16, rows: &[Vec<u8>], palette: &[u8], interlaced: bool) -> Vec<u8> {
+        let order: Vec<usize> = if interlaced {
+            let h = height as usize;
+            (0..h).step_by(8)
+                .chain((4..h).step_by(8))
+                .chain((2..h).step_by(4))
+                .chain((1..h).step_by(2))
+                .collect()
+        } else {
+            (0..height as usize).collect()
+        };
+        let indices: Vec<u8> = order.iter().flat_map(|&y| rows[y].clone()).collect();
+
+        let mut frame = Frame::from_palette_pixels(width, height, indices, palette.to_vec(), None);
+        frame.interlaced = interlaced;
+        let mut output = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut output, width, height, &[]).unwrap();
+            encoder.write_frame(&frame).unwrap();
+        }
+        output
+    }
+
+    #[test]
+    fn test_frame_at_composites_earlier_frames() {
+        // 4x4 logical screen, palette: 0 red, 1 green, 2 blue, 3 transparent
+        let palette = [255, 0, 0, 0, 255, 0, 0, 0, 255, 0, 0, 0];
+        let frames = [
+            (0, 0, 4, 4, vec![0u8; 16], DisposalMethod::Keep),
+            (1, 1, 2, 2, vec![1, 1, 1, 3], DisposalMethod::Background),
+            (2, 0, 2, 1, vec![2, 3], DisposalMethod::Keep),
+        ];
+        let mut gif = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut gif, 4, 4, &palette).unwrap();
+            for (left, top, width, height, indices, dispose) in frames {
+                let mut frame = Frame::from_palette_pixels(width, height, indices, palette.to_vec(), Some(3));
+                (frame.left, frame.top, frame.dispose) = (left, top, dispose);
+                encoder.write_frame(&frame).unwrap();
+            }
+        }
+
+        let red = [255, 0, 0, 255];
+        let (green, blue, clear) = ([0, 255, 0, 255], [0, 0, 255, 255], [0, 0, 0, 0]);
+        let (frame1, w, h) = decode_gif_frame_at(&gif, 1).unwrap();
+        assert_eq!((w, h), (4, 4));
+        assert_eq!(frame1[(4 + 1) * 4..(4 + 1) * 4 + 4], green);
+        assert_eq!(frame1[(2 * 4 + 2) * 4..(2 * 4 + 2) * 4 + 4], red); // transparent index shows frame 0
+
+        // Frame 1's rect was restored to background before frame 2 was drawn
+        let (frame2, _, _) = decode_gif_frame_at(&gif, 2).unwrap();
+        let expected: Vec<[u8; 4]> = (0..16)
+            .map(|i| match (i % 4, i / 4) {
+                (2, 0) => blue,
+                (1..=2, 1..=2) => clear,
+                _ => red,
+            })
+            .collect();
+        assert_eq!(frame2, expected.concat());
+
+        assert!(decode_gif_frame_at(&gif, 3).unwrap_err().contains("3 frames"));
+    }
+
+    #[test]
+    fn test_background_disposal_without_transparent_index() {
+        // 4x4 screen, palette: 0 red, 1 blue, 2 green (the screen background)
+        let palette = [255, 0, 0, 0, 0, 255, 0, 255, 0];
+        let frames = [
+            (0, 0, 4, 4, vec![0u8; 16], DisposalMethod::Keep),
+            (1, 1, 2, 2, vec![1u8; 4], DisposalMethod::Background),
+            (0, 0, 1, 1, vec![0u8], DisposalMethod::Keep),
+        ];
+        let mut gif = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut gif, 4, 4, &palette).unwrap();
+            for (left, top, width, height, indices, dispose) in frames {
+                let mut frame = Frame::from_palette_pixels(width, height, indices, palette.to_vec(), None);
+                (frame.left, frame.top, frame.dispose) = (left, top, dispose);
+                encoder.write_frame(&frame).unwrap();
+            }
+        }
+        gif[11] = 2; // Background color index in the logical screen descriptor
+
+        let (red, green) = ([255, 0, 0, 255], [0, 255, 0, 255]);
+        let reference: Vec<[u8; 4]> = (0..16)
+            .map(|i| if (1..=2).contains(&(i % 4)) && (1..=2).contains(&(i / 4)) { green } else { red })
+            .collect();
+        assert_eq!(decode_gif_frame_at(&gif, 2).unwrap().0, reference.concat());
+        // The sprite strip composites the same way
+        let (strip, _, _) = decode_gif_sprite_strip(&gif).unwrap();
+        let third_frame: Vec<u8> = strip.chunks_exact(12 * 4).flat_map(|row| row[8 * 4..].to_vec()).collect();
+        assert_eq!(third_frame, reference.concat());
+    }
+
+    #[test]
+    fn test_frames_carry_delays_and_composite_partial_frames() {
+        // 3x1 screen, palette: 0 red, 1 green, 2 transparent
+        let palette = [255, 0, 0, 0, 255, 0, 0, 0, 0];
+        let frames = [
+            (0, 3, vec![0u8; 3], 10, DisposalMethod::Keep),
+            (1, 1, vec![1u8], 25, DisposalMethod::Background),
+            (2, 1, vec![1u8], 5, DisposalMethod::Keep),
+        ];
+        let mut gif = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut gif, 3, 1, &palette).unwrap();
+            for (left, width, indices, delay, dispose) in frames {
+                let mut frame = Frame::from_palette_pixels(width, 1, indices, palette.to_vec(), Some(2));
+                (frame.left, frame.delay, frame.dispose) = (left, delay, dispose);
+                encoder.write_frame(&frame).unwrap();
+            }
+        }
+
+        let (frames, width, height) = decode_gif_frames(&gif).unwrap();
+        assert_eq!((width, height), (3, 1));
+        assert_eq!(frames.iter().map(|f| f.delay).collect::<Vec<_>>(), [10, 25, 5]);
+        let (red, green, clear) = ([255, 0, 0, 255], [0, 255, 0, 255], [0, 0, 0, 0]);
+        assert_eq!(frames[0].pixels, [red, red, red].concat());
+        assert_eq!(frames[1].pixels, [red, green, red].concat());
+        // Frame 1 was disposed to background before frame 2 was drawn
+        assert_eq!(frames[2].pixels, [red, clear, green].concat());
+        // The first frame matches the single-frame decoder
+        assert_eq!(decode_gif(&gif).unwrap().0, frames[0].pixels);
+    }
+
+    #[test]
+    fn test_decode_places_partial_first_frame_on_canvas() {
+        // 4x3 screen, one 2x1 frame at (1, 2), as optimizers write them
+        let palette = [255, 0, 0, 0, 255, 0];
+        let mut gif = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut gif, 4, 3, &palette).unwrap();
+            let mut frame = Frame::from_palette_pixels(2, 1, vec![0, 1], palette.to_vec(), None);
+            (frame.left, frame.top) = (1, 2);
+            encoder.write_frame(&frame).unwrap();
+        }
+
+        let (pixels, width, height) = decode_gif(&gif).unwrap();
+        assert_eq!((width, height), (4, 3));
+        let (red, green, clear) = ([255, 0, 0, 255], [0, 255, 0, 255], [0u8; 4]);
+        let expected: Vec<[u8; 4]> = (0..12)
+            .map(|i| match i {
+                9 => red,
+                10 => green,
+                _ => clear,
+            })
+            .collect();
+        assert_eq!(pixels, expected.concat());
+    }
+
+    #[test]
+    fn test_sprite_strip_lays_frames_left_to_right() {
+        // 2x2 screen, three full frames: red, green, blue
+        let palette = [255, 0, 0, 0, 255, 0, 0, 0, 255];
+        let mut gif = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut gif, 2, 2, &palette).unwrap();
+            for index in 0..3u8 {
+                let frame = Frame::from_palette_pixels(2, 2, vec![index; 4], palette.to_vec(), None);
+                encoder.write_frame(&frame).unwrap();
+            }
+        }
+
+        let (strip, width, height) = decode_gif_sprite_strip(&gif).unwrap();
+        assert_eq!((width, height), (6, 2));
+        for y in 0..2 {
+            for x in 0..6 {
+                let idx = (y * 6 + x) * 4;
+                let color = &palette[x / 2 * 3..x / 2 * 3 + 3];
+                assert_eq!(&strip[idx..idx + 3], color, "({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn test_interlaced_gif_matches_progressive_order() {
+        let (width, height) = (5u16, 11u16);
+        let palette: Vec<u8> = (0..16u8).flat_map(|i| [i * 16, 255 - i * 16, i * 7]).collect();
+        let rows: Vec<Vec<u8>> = (0..height as u8)
+            .map(|y| (0..width as u8).map(|x| (y + x) % 16).collect())
+            .collect();
+
+        let plain = decode_gif(&make_gif(width, height, &rows, &palette, false)).unwrap();
+        let interlaced = decode_gif(&make_gif(width, height, &rows, &palette, true)).unwrap();
+        assert_eq!(plain, interlaced);
+        // Row 3 is stored last in interlaced order; it must land back at row 3
+        let idx = (3 * width as usize) * 4;
+        assert_eq!(&interlaced.0[idx..idx + 3], &palette[3 * 3..3 * 3 + 3]);
+    }
+}
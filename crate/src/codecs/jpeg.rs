@@ -1,35 +1,494 @@
-use jpeg_encoder::{Encoder, ColorType};
-
-pub fn encode_jpeg(
-    data: &[u8],
-    width: u32,
-    height: u32,
-    quality: u8,
-    _chroma_subsampling: bool, // Note: jpeg-encoder doesn't expose chroma subsampling control
-    _progressive: bool, // TODO: Progressive JPEG requires MozJPEG integration (Phase 2)
-                        // The jpeg-encoder crate doesn't support progressive encoding
-) -> Result<Vec<u8>, String> {
+use jpeg_decoder::{Decoder, PixelFormat};
+use jpeg_encoder::{Encoder, ColorType, Density, SamplingFactor};
+use serde::{Deserialize, Serialize};
+
+use super::{extra_option, Extra, Metadata};
+
+/// Uncompressed preview stored in the JFIF APP0 segment, as RGBA pixels
+/// (alpha is dropped). JFIF limits it to 255x255 and about 21k pixels.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct JfifThumbnail {
+    #[serde(with = "serde_bytes")]
+    pub data: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Settings for `encode_jpeg`. The default is baseline 4:2:0 at quality 80,
+/// with nothing else added.
+#[derive(Debug, Clone, Copy)]
+pub struct JpegOptions<'a> {
+    pub quality: u8,
+    pub chroma_subsampling: bool, // 4:2:0 when true, 4:4:4 otherwise
+    pub progressive: bool, // Progressive (SOF2) scans when true, baseline (SOF0) for legacy consumers otherwise
+    pub smoothing: Option<u8>, // Input smoothing factor 0-100 (libjpeg's smoothing_factor)
+    pub dpi: Option<u16>, // JFIF density in dots per inch; None writes an aspect-ratio-only 1:1
+    pub thumbnail: Option<&'a JfifThumbnail>,
+    pub metadata: Option<&'a Metadata>, // Written as APP1 (EXIF) / APP2 (ICC); None strips all APPn beyond JFIF
+    pub extra: Option<&'a Extra>, // Honors "jpeg.restart_interval" (MCUs) and "jpeg.optimize_huffman" (bool)
+}
+
+impl Default for JpegOptions<'_> {
+    fn default() -> Self {
+        JpegOptions {
+            quality: 80,
+            chroma_subsampling: true,
+            progressive: false,
+            smoothing: None,
+            dpi: None,
+            thumbnail: None,
+            metadata: None,
+            extra: None,
+        }
+    }
+}
+
+pub fn encode_jpeg(data: &[u8], width: u32, height: u32, options: &JpegOptions) -> Result<Vec<u8>, String> {
+    let JpegOptions { quality, chroma_subsampling, progressive, smoothing, dpi, thumbnail, metadata, extra } = *options;
     // Validate dimensions before casting to u16
+    super::check_nonzero(width, height)?;
     if width > u16::MAX as u32 || height > u16::MAX as u32 {
         return Err(format!(
             "Image dimensions {}x{} exceed JPEG encoder limit (max 65535)",
             width, height
         ));
     }
+    super::check_rgba_len(data, width, height)?;
+    if let Some(thumbnail) = thumbnail {
+        super::check_rgba_len(&thumbnail.data, thumbnail.width, thumbnail.height)?;
+        if thumbnail.width > 255 || thumbnail.height > 255 || 16 + thumbnail.data.len() / 4 * 3 > u16::MAX as usize {
+            return Err(format!(
+                "JFIF thumbnail {}x{} is too large (max 255x255 and 21839 pixels)",
+                thumbnail.width, thumbnail.height
+            ));
+        }
+    }
 
     // Convert RGBA to RGB (JPEG doesn't support alpha)
-    let rgb_data: Vec<u8> = data
-        .chunks(4)
-        .flat_map(|rgba| [rgba[0], rgba[1], rgba[2]])
-        .collect();
-    
+    let rgb_data = crate::color::rgba_to_rgb(data);
+
+    let rgb_data = match smoothing {
+        Some(factor) if factor > 0 => smooth_input(&rgb_data, width as usize, height as usize, factor.min(100)),
+        _ => rgb_data,
+    };
+
     let mut output = Vec::new();
     
-    let encoder = Encoder::new(&mut output, quality);
-    
+    let mut encoder = Encoder::new(&mut output, quality);
+    if let Some(dpi) = dpi {
+        encoder.set_density(Density::Inch { x: dpi, y: dpi });
+    }
+    // Odd sizes are fine at 4:2:0: the encoder replicates the last row and
+    // column to fill the final chroma block
+    encoder.set_sampling_factor(if chroma_subsampling { SamplingFactor::F_2_2 } else { SamplingFactor::F_1_1 });
+    encoder.set_progressive(progressive);
+    if let Some(interval) = extra_option(extra, "jpeg.restart_interval")? {
+        encoder.set_restart_interval(interval);
+    }
+    if let Some(optimize) = extra_option(extra, "jpeg.optimize_huffman")? {
+        encoder.set_optimized_huffman_tables(optimize);
+    }
+
+    if let Some(metadata) = metadata {
+        if let Some(exif) = &metadata.exif {
+            let mut segment = b"Exif\0\0".to_vec();
+            segment.extend_from_slice(exif);
+            encoder
+                .add_app_segment(1, &segment)
+                .map_err(|e| format!("JPEG EXIF write failed: {:?}", e))?;
+        }
+        if let Some(icc) = &metadata.icc_profile {
+            encoder
+                .add_icc_profile(icc)
+                .map_err(|e| format!("JPEG ICC write failed: {:?}", e))?;
+        }
+    }
+
     encoder
         .encode(&rgb_data, width as u16, height as u16, ColorType::Rgb)
         .map_err(|e| format!("JPEG encoding failed: {:?}", e))?;
-    
+
+    if let Some(thumbnail) = thumbnail {
+        add_jfif_thumbnail(&mut output, thumbnail)?;
+    }
     Ok(output)
 }
+
+/// Rewrite the encoder's JFIF APP0 segment (right after SOI, with a 0x0
+/// thumbnail) to carry `thumbnail` as packed RGB.
+fn add_jfif_thumbnail(jpeg: &mut Vec<u8>, thumbnail: &JfifThumbnail) -> Result<(), String> {
+    const APP0_END: usize = 2 + 2 + 16; // SOI, APP0 marker, 16-byte segment
+    if jpeg.get(2..4) != Some(&[0xFF, 0xE0]) || jpeg.get(6..11) != Some(b"JFIF\0") {
+        return Err("JPEG encoder output has no JFIF APP0 segment".to_string());
+    }
+    let rgb = crate::color::rgba_to_rgb(&thumbnail.data);
+    let length = (16 + rgb.len()) as u16;
+    jpeg[4..6].copy_from_slice(&length.to_be_bytes());
+    jpeg[18] = thumbnail.width as u8;
+    jpeg[19] = thumbnail.height as u8;
+    jpeg.splice(APP0_END..APP0_END, rgb);
+    Ok(())
+}
+
+/// Decode a JPEG image to RGBA pixels.
+/// CMYK/YCCK images are converted to RGB, honoring the Adobe APP14 marker.
+/// Returns (pixels, width, height)
+pub fn decode_jpeg(data: &[u8]) -> Result<(Vec<u8>, u32, u32), String> {
+    decode_jpeg_scaled(data, 1)
+}
+
+/// Decode a JPEG at 1/`scale_denom` size (1, 2, 4 or 8) straight from the DCT
+/// coefficients, which is much cheaper than a full decode plus resize when
+/// making thumbnails. Each side is rounded up (a 100px side at 1/8 gives 13).
+/// Returns (pixels, width, height)
+pub fn decode_jpeg_scaled(data: &[u8], scale_denom: u8) -> Result<(Vec<u8>, u32, u32), String> {
+    if !is_jpeg(data) {
+        return Err("Not a valid JPEG file".to_string());
+    }
+    if !matches!(scale_denom, 1 | 2 | 4 | 8) {
+        return Err(format!("Unsupported JPEG scale 1/{}: expected 1, 2, 4 or 8", scale_denom));
+    }
+
+    let mut decoder = Decoder::new(data);
+    if scale_denom > 1 {
+        decoder
+            .read_info()
+            .map_err(|e| format!("Failed to read JPEG header: {:?}", e))?;
+        let info = decoder
+            .info()
+            .ok_or_else(|| "JPEG has no frame header".to_string())?;
+        let denom = scale_denom as u16;
+        decoder
+            .scale(info.width.div_ceil(denom), info.height.div_ceil(denom))
+            .map_err(|e| format!("Failed to scale JPEG: {:?}", e))?;
+    }
+    let pixels = decoder
+        .decode()
+        .map_err(|e| format!("Failed to decode JPEG: {:?}", e))?;
+    let info = decoder
+        .info()
+        .ok_or_else(|| "JPEG has no frame header".to_string())?;
+
+    let rgba = match info.pixel_format {
+        PixelFormat::L8 => pixels.iter().flat_map(|&g| [g, g, g, 255]).collect(),
+        PixelFormat::L16 => {
+            // L16 covers every precision from 9 to 16 bits (e.g. 12-bit
+            // lossless), with samples left at that precision
+            let shift = frame_precision(data).unwrap_or(16).clamp(9, 16) - 8;
+            pixels
+                .chunks_exact(2)
+                .flat_map(|g| {
+                    let g8 = (u16::from_ne_bytes([g[0], g[1]]) >> shift) as u8;
+                    [g8, g8, g8, 255]
+                })
+                .collect()
+        }
+        PixelFormat::RGB24 => crate::color::rgb_to_rgba(&pixels),
+        PixelFormat::CMYK32 => {
+            // jpeg-decoder un-inverts every CMYK image as if Adobe wrote it (Adobe
+            // stores 255 = no ink). Without an APP14 marker the samples were stored
+            // as plain ink amounts, so that inversion has to be undone.
+            let adobe = adobe_transform(data).is_some();
+            crate::color::cmyk_to_rgba(&pixels, !adobe, None)
+        }
+    };
+
+    Ok((rgba, info.width as u32, info.height as u32))
+}
+
+/// Sample precision in bits from the start-of-frame segment (any SOFn).
+fn frame_precision(data: &[u8]) -> Option<u8> {
+    const SOF_MARKERS: [u8; 13] = [0xC0, 0xC1, 0xC2, 0xC3, 0xC5, 0xC6, 0xC7, 0xC9, 0xCA, 0xCB, 0xCD, 0xCE, 0xCF];
+    SOF_MARKERS.iter().find_map(|&marker| find_segment(data, marker, &[]))?.first().copied()
+}
+
+/// Find the Adobe APP14 marker and return its color transform byte
+/// (0 = CMYK/RGB, 1 = YCbCr, 2 = YCCK).
+fn adobe_transform(data: &[u8]) -> Option<u8> {
+    find_segment(data, 0xEE, b"Adobe")
+        .filter(|segment| segment.len() >= 12)
+        .map(|segment| segment[11])
+}
+
+/// EXIF orientation (1-8) from the APP1 segment, if present and valid.
+pub fn exif_orientation(data: &[u8]) -> Option<u16> {
+    let exif = find_segment(data, 0xE1, b"Exif\0\0")?.get(6..)?;
+    let big_endian = match exif.get(..2)? {
+        b"II" => false,
+        b"MM" => true,
+        _ => return None,
+    };
+    let u16_at = |pos: usize| -> Option<u16> {
+        let bytes: [u8; 2] = exif.get(pos..pos + 2)?.try_into().ok()?;
+        Some(if big_endian { u16::from_be_bytes(bytes) } else { u16::from_le_bytes(bytes) })
+    };
+    let u32_at = |pos: usize| -> Option<u32> {
+        let bytes: [u8; 4] = exif.get(pos..pos + 4)?.try_into().ok()?;
+        Some(if big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) })
+    };
+
+    // IFD0: entry count, then 12-byte entries of (tag, type, count, value)
+    let ifd = u32_at(4)? as usize;
+    (0..u16_at(ifd)? as usize)
+        .map(|i| ifd + 2 + i * 12)
+        .find(|&entry| u16_at(entry) == Some(0x0112))
+        .and_then(|entry| u16_at(entry + 8))
+        .filter(|orientation| (1..=8).contains(orientation))
+}
+
+/// Pixel aspect ratio (pixel width / height) from the JFIF X/Y density,
+/// or None for square pixels or without a JFIF segment.
+pub fn pixel_aspect(data: &[u8]) -> Option<f32> {
+    let jfif = find_segment(data, 0xE0, b"JFIF\0")?;
+    let x = u16::from_be_bytes(jfif.get(8..10)?.try_into().ok()?);
+    let y = u16::from_be_bytes(jfif.get(10..12)?.try_into().ok()?);
+    if x == 0 || y == 0 || x == y {
+        return None;
+    }
+    Some(y as f32 / x as f32)
+}
+
+/// Find the first `marker` segment whose payload starts with `prefix` and
+/// return the payload. Scans markers up to start-of-scan.
+fn find_segment<'a>(data: &'a [u8], marker: u8, prefix: &[u8]) -> Option<&'a [u8]> {
+    let mut pos = 2; // Skip SOI
+    while pos + 4 <= data.len() {
+        if data[pos] != 0xFF {
+            return None;
+        }
+        let current = data[pos + 1];
+        if current == 0xFF {
+            pos += 1; // Fill byte
+            continue;
+        }
+        if current == 0xDA || current == 0xD9 {
+            return None; // SOS/EOI: no more header segments
+        }
+        let len = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+        let segment = data.get(pos + 4..pos + 2 + len)?;
+        if current == marker && segment.starts_with(prefix) {
+            return Some(segment);
+        }
+        pos += 2 + len;
+    }
+    None
+}
+
+/// Check if data is a JPEG file by checking the SOI marker
+pub fn is_jpeg(data: &[u8]) -> bool {
+    data.len() >= 3 && data[0] == 0xFF && data[1] == 0xD8 && data[2] == 0xFF
+}
+
+/// Smooth RGB input before DCT, like libjpeg's `smoothing_factor`.
+/// Each sample becomes `(1 - 8*sf) * center + sf * sum(8 neighbors)` with
+/// `sf = factor / 1024`, replicating edge pixels. Reduces high-frequency
+/// noise (film grain, dithering) that would otherwise cost bits.
+fn smooth_input(rgb: &[u8], width: usize, height: usize, factor: u8) -> Vec<u8> {
+    let neighbor_weight = factor as f32 / 1024.0;
+    let center_weight = 1.0 - 8.0 * neighbor_weight;
+    let mut result = rgb.to_vec();
+
+    for y in 0..height {
+        let rows = [y.saturating_sub(1), y, (y + 1).min(height - 1)];
+        for x in 0..width {
+            let cols = [x.saturating_sub(1), x, (x + 1).min(width - 1)];
+            let idx = (y * width + x) * 3;
+            for c in 0..3 {
+                let mut neighbors = 0.0;
+                for &ny in &rows {
+                    for &nx in &cols {
+                        neighbors += rgb[(ny * width + nx) * 3 + c] as f32;
+                    }
+                }
+                let center = rgb[idx + c] as f32;
+                neighbors -= center;
+                let value = center * center_weight + neighbors * neighbor_weight;
+                result[idx + c] = value.round().clamp(0.0, 255.0) as u8;
+            }
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Mid-gray image with deterministic pseudo-random grain
+    fn noisy_image(width: u32, height: u32) -> Vec<u8> {
+        let mut state = 0x1234_5678u32;
+        let mut data = Vec::with_capacity((width * height * 4) as usize);
+        for _ in 0..width * height {
+            state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            let v = 128u8.wrapping_add((state >> 24) as u8 % 48).wrapping_sub(24);
+            data.extend_from_slice(&[v, v, v, 255]);
+        }
+        data
+    }
+
+    #[test]
+    fn test_smoothing_reduces_size_of_noisy_image() {
+        let data = noisy_image(64, 64);
+        let plain = encode_jpeg(&data, 64, 64, &JpegOptions::default()).unwrap();
+        let smoothed = encode_jpeg(&data, 64, 64, &JpegOptions { smoothing: Some(50), ..Default::default() }).unwrap();
+        assert!(smoothed.len() < plain.len(), "{} >= {}", smoothed.len(), plain.len());
+    }
+
+    #[test]
+    fn test_odd_size_subsampled_edges_stay_clean() {
+        // 101x101 with a saturated last row and column: the 4:2:0 chroma
+        // block straddling the edge must not bleed into them
+        let data: Vec<u8> = (0..101 * 101)
+            .flat_map(|i| match (i % 101, i / 101) {
+                (100, _) => [220, 30, 30, 255],
+                (_, 100) => [30, 30, 220, 255],
+                _ => [128, 128, 128, 255],
+            })
+            .collect();
+        for (subsampling, factor) in [(true, 0x22), (false, 0x11)] {
+            let options = JpegOptions { quality: 85, chroma_subsampling: subsampling, ..Default::default() };
+            let jpeg = encode_jpeg(&data, 101, 101, &options).unwrap();
+            // SOF0: precision, height, width, component count, then Y id and sampling
+            assert_eq!(find_segment(&jpeg, 0xC0, &[]).unwrap()[7], factor);
+
+            let (pixels, width, height) = decode_jpeg(&jpeg).unwrap();
+            assert_eq!((width, height), (101, 101));
+            let mean_error = |index: &dyn Fn(usize) -> usize| {
+                (0..100).map(|k| index(k) * 4).map(|i| (0..3).map(|c| pixels[i + c].abs_diff(data[i + c]) as u32).sum::<u32>()).sum::<u32>() / 300
+            };
+            assert!(mean_error(&|y| y * 101 + 100) < 25, "subsampling {}: right column", subsampling);
+            assert!(mean_error(&|x| 100 * 101 + x) < 25, "subsampling {}: bottom row", subsampling);
+        }
+    }
+
+    #[test]
+    fn test_dpi_and_thumbnail_in_jfif_header() {
+        let data = vec![90u8; 32 * 24 * 4];
+        let thumbnail = JfifThumbnail {
+            data: (0..4 * 3).flat_map(|i| [i as u8 * 20, 100, 200, 255]).collect(),
+            width: 4,
+            height: 3,
+        };
+        let jpeg = encode_jpeg(&data, 32, 24, &JpegOptions { dpi: Some(300), thumbnail: Some(&thumbnail), ..Default::default() }).unwrap();
+
+        let app0 = find_segment(&jpeg, 0xE0, b"JFIF\0").unwrap();
+        // Units (1 = dots per inch), X and Y density, thumbnail size, RGB
+        assert_eq!(app0[7], 1);
+        assert_eq!(app0[8..12], [1, 44, 1, 44]);
+        assert_eq!(app0[12..14], [4, 3]);
+        assert_eq!(app0.len(), 14 + 4 * 3 * 3);
+        assert_eq!(app0[14..20], [0, 100, 200, 20, 100, 200]);
+        assert_eq!(decode_jpeg(&jpeg).unwrap().1, 32);
+
+        let oversized = JfifThumbnail { data: vec![0; 256 * 4], width: 256, height: 1 };
+        assert!(encode_jpeg(&data, 32, 24, &JpegOptions { thumbnail: Some(&oversized), ..Default::default() }).is_err());
+    }
+
+    #[test]
+    fn test_scaled_decode_shrinks_by_denominator() {
+        // Left half dark, right half light, 100x60 (not a multiple of 8)
+        let data: Vec<u8> = (0..60)
+            .flat_map(|_| (0..100).flat_map(|x| if x < 50 { [40, 40, 40, 255] } else { [220, 220, 220, 255] }))
+            .collect();
+        let jpeg = encode_jpeg(&data, 100, 60, &JpegOptions { quality: 90, ..Default::default() }).unwrap();
+
+        for (denom, size) in [(1, (100, 60)), (2, (50, 30)), (4, (25, 15)), (8, (13, 8))] {
+            let (pixels, width, height) = decode_jpeg_scaled(&jpeg, denom).unwrap();
+            assert_eq!((width, height), size, "1/{}", denom);
+            assert_eq!(pixels.len(), (width * height * 4) as usize);
+            let left = pixels[(width as usize / 4) * 4];
+            let right = pixels[(width as usize * 3 / 4) * 4];
+            assert!(left < 80 && right > 180, "1/{}: {} {}", denom, left, right);
+        }
+        assert!(decode_jpeg_scaled(&jpeg, 3).is_err());
+    }
+
+    /// Encode a solid CMYK (ink amounts) image; jpeg-encoder writes Adobe-style
+    /// inverted samples plus an APP14 marker, like Photoshop.
+    fn adobe_cmyk_jpeg(cmyk: [u8; 4], color_type: ColorType) -> Vec<u8> {
+        let data: Vec<u8> = (0..64).flat_map(|_| cmyk).collect();
+        let mut output = Vec::new();
+        Encoder::new(&mut output, 100)
+            .encode(&data, 8, 8, color_type)
+            .unwrap();
+        output
+    }
+
+    fn assert_close(actual: &[u8], expected: [u8; 3]) {
+        for c in 0..3 {
+            assert!(
+                (actual[c] as i16 - expected[c] as i16).abs() <= 4,
+                "{:?} != {:?}",
+                &actual[..3],
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_decode_adobe_cmyk_is_not_inverted() {
+        let jpeg = adobe_cmyk_jpeg([255, 0, 0, 0], ColorType::Cmyk); // pure cyan ink
+        assert_eq!(adobe_transform(&jpeg), Some(0));
+        let (pixels, width, height) = decode_jpeg(&jpeg).unwrap();
+        assert_eq!((width, height), (8, 8));
+        assert_close(&pixels[..4], [0, 255, 255]);
+        assert_eq!(pixels[3], 255);
+    }
+
+    #[test]
+    fn test_decode_progressive_matches_baseline() {
+        let data: Vec<u8> = (0..24u32 * 16).flat_map(|i| [(i * 5) as u8, (i / 3) as u8, 90]).collect();
+        let encode = |progressive: bool| {
+            let mut output = Vec::new();
+            let mut encoder = Encoder::new(&mut output, 90);
+            encoder.set_progressive(progressive);
+            encoder.encode(&data, 24, 16, ColorType::Rgb).unwrap();
+            output
+        };
+        let baseline = decode_jpeg(&encode(false)).unwrap();
+        let progressive = decode_jpeg(&encode(true)).unwrap();
+        assert_eq!((progressive.1, progressive.2), (24, 16));
+        for (a, b) in baseline.0.iter().zip(&progressive.0) {
+            assert!((*a as i16 - *b as i16).abs() <= 2);
+        }
+    }
+
+    #[test]
+    fn test_progressive_flag_picks_frame_type() {
+        let data: Vec<u8> = (0..32u32 * 16).flat_map(|i| [(i * 7) as u8, 120, (i / 2) as u8, 255]).collect();
+        let has_marker = |jpeg: &[u8], marker: u8| jpeg.windows(2).any(|w| w == [0xFF, marker]);
+        let baseline = encode_jpeg(&data, 32, 16, &JpegOptions { quality: 85, ..Default::default() }).unwrap();
+        let progressive = encode_jpeg(&data, 32, 16, &JpegOptions { quality: 85, progressive: true, ..Default::default() }).unwrap();
+        assert!(has_marker(&baseline, 0xC0) && !has_marker(&baseline, 0xC2));
+        assert!(has_marker(&progressive, 0xC2) && !has_marker(&progressive, 0xC0));
+        assert_eq!(decode_jpeg(&progressive).unwrap().1, 32);
+    }
+
+    #[test]
+    fn test_decode_12bit_lossless_gray_scales_to_8bit() {
+        // 2x1 lossless (SOF3) 12-bit grayscale: samples 2048 (the initial
+        // prediction, difference 0) and 4095 (difference 2047, category 11)
+        let jpeg = [
+            0xFF, 0xD8, // SOI
+            0xFF, 0xC4, 0, 21, 0x00, // DHT: DC table 0, codes "0" -> 0 and "1" -> 11
+            2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x00, 0x0B,
+            0xFF, 0xC3, 0, 11, 12, 0, 1, 0, 2, 1, 1, 0x11, 0, // SOF3: 12-bit, 1 row of 2, one component
+            0xFF, 0xDA, 0, 8, 1, 1, 0x00, 1, 0, 0, // SOS: predictor 1 (left)
+            0x7F, 0xFF, 0x00, // "0", "1" + 11 one bits, padded with ones (0xFF stuffed)
+            0xFF, 0xD9, // EOI
+        ];
+        assert_eq!(frame_precision(&jpeg), Some(12));
+        let (pixels, width, height) = decode_jpeg(&jpeg).unwrap();
+        assert_eq!((width, height), (2, 1));
+        assert_eq!(pixels, [128, 128, 128, 255, 255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn test_decode_adobe_ycck() {
+        let jpeg = adobe_cmyk_jpeg([0, 0, 0, 128], ColorType::CmykAsYcck); // 50% black
+        assert_eq!(adobe_transform(&jpeg), Some(2));
+        let (pixels, _, _) = decode_jpeg(&jpeg).unwrap();
+        assert_close(&pixels[..4], [127, 127, 127]);
+    }
+}
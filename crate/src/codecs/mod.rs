@@ -1,3 +1,7 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::str::FromStr;
+
 pub mod avif;
 pub mod bmp;
 pub mod gif;
@@ -5,3 +9,154 @@ pub mod jpeg;
 pub mod jxl;  // Documentation only - JXL encoding is in JavaScript
 pub mod png;
 pub mod tiff;
+
+/// Metadata to embed in encoded output when `strip_metadata` is off.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Metadata {
+    #[serde(default, with = "serde_bytes")]
+    pub exif: Option<Vec<u8>>, // Raw TIFF-structured EXIF (no "Exif\0\0" header)
+    #[serde(default, with = "serde_bytes")]
+    pub icc_profile: Option<Vec<u8>>,
+}
+
+impl Metadata {
+    pub fn is_empty(&self) -> bool {
+        self.exif.is_none() && self.icc_profile.is_none()
+    }
+}
+
+/// Raw encoder options keyed "<format>.<option>" (`Config::extra`), for knobs
+/// without a typed field yet. Each encoder documents the keys it honors;
+/// every other key is ignored.
+pub type Extra = HashMap<String, String>;
+
+/// Parse the option `key` from `extra`, if set. A recognized key with a value
+/// that doesn't parse is an error rather than silently dropped.
+pub fn extra_option<T: FromStr>(extra: Option<&Extra>, key: &str) -> Result<Option<T>, String> {
+    match extra.and_then(|extra| extra.get(key)) {
+        Some(value) => value
+            .trim()
+            .parse()
+            .map(Some)
+            .map_err(|_| format!("Invalid value '{}' for encoder option '{}'", value, key)),
+        None => Ok(None),
+    }
+}
+
+/// Sniff the container format from magic bytes and decode to RGBA.
+/// Returns (pixels, width, height)
+pub fn decode_image(data: &[u8]) -> Result<(Vec<u8>, u32, u32), String> {
+    if jpeg::is_jpeg(data) {
+        jpeg::decode_jpeg(data)
+    } else if png::is_png(data) {
+        png::decode_png(data)
+    } else if gif::is_gif(data) {
+        gif::decode_gif(data)
+    } else if bmp::is_bmp(data) {
+        bmp::decode_bmp(data)
+    } else if tiff::is_tiff(data) {
+        tiff::decode_tiff(data)
+    } else if is_webp(data) {
+        Err("WebP input is recognized but not supported yet".to_string())
+    } else if avif::is_avif(data) {
+        Err("AVIF input is recognized but not supported yet".to_string())
+    } else {
+        Err("Unrecognized image format".to_string())
+    }
+}
+
+/// Pixel aspect ratio (pixel width / height) the file declares through its
+/// resolution tags, or None for square pixels. See `Config::pixel_aspect`.
+pub fn pixel_aspect(data: &[u8]) -> Option<f32> {
+    if jpeg::is_jpeg(data) {
+        jpeg::pixel_aspect(data)
+    } else if png::is_png(data) {
+        png::pixel_aspect(data)
+    } else if tiff::is_tiff(data) {
+        tiff::pixel_aspect(data)
+    } else {
+        None
+    }
+}
+
+/// Check for a RIFF container with the `WEBP` form type and a VP8/VP8L/VP8X
+/// first chunk (other RIFF forms like WAVE share the `RIFF` prefix).
+pub fn is_webp(data: &[u8]) -> bool {
+    data.len() >= 16
+        && &data[0..4] == b"RIFF"
+        && &data[8..12] == b"WEBP"
+        && matches!(&data[12..16], b"VP8 " | b"VP8L" | b"VP8X")
+}
+
+/// Reject zero-sized images (e.g. from a degenerate crop) before they reach
+/// an encoder, which would fail with a cryptic error or panic.
+pub fn check_nonzero(width: u32, height: u32) -> Result<(), String> {
+    if width == 0 || height == 0 {
+        return Err(format!("Cannot encode a {}x{} image: width and height must be non-zero", width, height));
+    }
+    Ok(())
+}
+
+/// Check that `data` holds exactly `width * height` RGBA pixels.
+pub fn check_rgba_len(data: &[u8], width: u32, height: u32) -> Result<(), String> {
+    let expected_len = (width as usize) * (height as usize) * 4;
+    if data.len() != expected_len {
+        return Err(format!(
+            "Dimension mismatch: data length {} doesn't match expected {} for {}x{} RGBA image",
+            data.len(),
+            expected_len,
+            width,
+            height
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encoders_reject_short_buffer() {
+        let short = vec![128u8; 8 * 8 * 4 - 1];
+        let errors = [
+            jpeg::encode_jpeg(&short, 8, 8, &jpeg::JpegOptions::default()).unwrap_err(),
+            png::encode_png(&short, 8, 8, &png::PngOptions { lossless: true, ..Default::default() }).unwrap_err(),
+            avif::encode_avif(&short, 8, 8, &avif::AvifOptions { speed: 10, ..Default::default() }).unwrap_err(),
+        ];
+        for err in errors {
+            assert!(err.starts_with("Dimension mismatch"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_encoders_reject_zero_dimensions() {
+        for (w, h) in [(0, 10), (10, 0)] {
+            let errors = [
+                jpeg::encode_jpeg(&[], w, h, &jpeg::JpegOptions::default()).unwrap_err(),
+                png::encode_png(&[], w, h, &png::PngOptions { lossless: true, ..Default::default() }).unwrap_err(),
+                png::encode_png(&[], w, h, &png::PngOptions::default()).unwrap_err(),
+                png::encode_png_auto(&[], w, h, &png::PngOptions::default()).unwrap_err(),
+                avif::encode_avif(&[], w, h, &avif::AvifOptions { speed: 10, ..Default::default() }).unwrap_err(),
+            ];
+            for err in errors {
+                assert!(err.contains("must be non-zero"), "{}", err);
+            }
+        }
+    }
+
+    #[test]
+    fn test_sniffs_webp_and_avif_containers() {
+        // 1x1 lossless WebP
+        let webp = b"RIFF\x1a\x00\x00\x00WEBPVP8L\x0d\x00\x00\x00\x2f\x00\x00\x00\x10\x07\x10\x11\x11\x88\x88\xfe\x07\x00";
+        assert!(is_webp(webp));
+        assert!(decode_image(webp).unwrap_err().contains("WebP"));
+        assert!(!is_webp(b"RIFF\x24\x00\x00\x00WAVEfmt \x10\x00\x00\x00"));
+        assert!(!is_webp(b"RIFF\x04\x00\x00\x00WEBP"));
+
+        let avif = b"\x00\x00\x00\x1cftypavif\x00\x00\x00\x00avifmif1miaf";
+        assert!(decode_image(avif).unwrap_err().contains("AVIF"));
+        let heic = b"\x00\x00\x00\x18ftypheic\x00\x00\x00\x00mif1heic";
+        assert_eq!(decode_image(heic).unwrap_err(), "Unrecognized image format");
+    }
+}
@@ -1,47 +1,84 @@
 use imagequant::{Attributes, RGBA};
-use png::{BitDepth, ColorType, Compression, Encoder};
+use png::{BitDepth, ColorType, Compression, Encoder, Info};
+use std::borrow::Cow;
 
-pub fn encode_png(
-    data: &[u8],
-    width: u32,
-    height: u32,
-    lossless: bool,
-    dithering_level: f32,
-    speed_mode: bool,
-    quality: u8,
-) -> Result<Vec<u8>, String> {
-    // Validate RGBA data length is a multiple of 4
-    if !data.len().is_multiple_of(4) {
-        return Err(format!(
-            "Invalid RGBA data length {}: must be multiple of 4",
-            data.len()
-        ));
+use super::{extra_option, Extra, Metadata};
+use crate::color::transfer::Transfer;
+
+/// Settings for `encode_png` and `encode_png_auto`. The default is fully
+/// dithered quantization at quality 80, with no metadata.
+#[derive(Debug, Clone, Copy)]
+pub struct PngOptions<'a> {
+    pub lossless: bool, // RGBA when true, quantized palette otherwise (ignored by encode_png_auto)
+    pub dithering_level: f32,
+    pub speed_mode: bool,
+    pub liq_speed: Option<u8>, // imagequant speed 1-10; None derives it from speed_mode
+    pub quality: u8,
+    pub deterministic: bool,
+    pub metadata: Option<&'a Metadata>, // Written as eXIf / iCCP; None writes no ancillary chunks
+    pub extra: Option<&'a Extra>, // Honors "png.compression" ("fast" or "best") for lossless and exact-palette output
+}
+
+impl Default for PngOptions<'_> {
+    fn default() -> Self {
+        PngOptions {
+            lossless: false,
+            dithering_level: 1.0,
+            speed_mode: false,
+            liq_speed: None,
+            quality: 80,
+            deterministic: false,
+            metadata: None,
+            extra: None,
+        }
     }
+}
 
+pub fn encode_png(data: &[u8], width: u32, height: u32, options: &PngOptions) -> Result<Vec<u8>, String> {
     // Validate data length matches expected size for dimensions
-    let expected_len = (width as usize) * (height as usize) * 4;
-    if data.len() != expected_len {
-        return Err(format!(
-            "Data length {} doesn't match expected {} for {}x{} RGBA image",
-            data.len(),
-            expected_len,
-            width,
-            height
-        ));
-    }
-
-    if lossless {
-        encode_lossless(data, width, height, speed_mode)
+    super::check_nonzero(width, height)?;
+    super::check_rgba_len(data, width, height)?;
+
+    let info = png_info(width, height, options.metadata);
+    let fast_deflate = fast_compression(options.extra, options.speed_mode)?;
+    #[cfg(feature = "parallel")]
+    if options.lossless && height as usize > PARALLEL_BAND_ROWS {
+        return encode_lossless_parallel(data, info, fast_deflate, PARALLEL_BAND_ROWS);
+    }
+    if options.lossless {
+        encode_lossless(data, info, fast_deflate)
     } else {
-        encode_lossy(data, width, height, dithering_level, speed_mode, quality)
+        encode_lossy(data, info, options)
     }
 }
 
-fn encode_lossless(data: &[u8], width: u32, height: u32, speed_mode: bool) -> Result<Vec<u8>, String> {
+/// Whether lossless output uses fast DEFLATE: the "png.compression" extra
+/// option when set, else `speed_mode`.
+fn fast_compression(extra: Option<&Extra>, speed_mode: bool) -> Result<bool, String> {
+    match extra_option::<String>(extra, "png.compression")?.as_deref() {
+        None => Ok(speed_mode),
+        Some("fast") => Ok(true),
+        Some("best") => Ok(false),
+        Some(other) => Err(format!("Invalid value '{}' for encoder option 'png.compression'", other)),
+    }
+}
+
+/// Header info for the encoder, carrying any metadata chunks to write.
+fn png_info(width: u32, height: u32, metadata: Option<&Metadata>) -> Info<'_> {
+    let mut info = Info::with_size(width, height);
+    if let Some(metadata) = metadata {
+        info.exif_metadata = metadata.exif.as_deref().map(Cow::Borrowed);
+        info.icc_profile = metadata.icc_profile.as_deref().map(Cow::Borrowed);
+    }
+    info
+}
+
+fn encode_lossless(data: &[u8], info: Info, speed_mode: bool) -> Result<Vec<u8>, String> {
     let mut output = Vec::new();
 
     {
-        let mut encoder = Encoder::new(&mut output, width, height);
+        let mut encoder = Encoder::with_info(&mut output, info)
+            .map_err(|e| format!("PNG header setup failed: {:?}", e))?;
         encoder.set_color(ColorType::Rgba);
         encoder.set_depth(BitDepth::Eight);
         // Use Fast compression in speed mode, Best otherwise (3-5x speedup)
@@ -59,14 +96,122 @@ fn encode_lossless(data: &[u8], width: u32, height: u32, speed_mode: bool) -> Re
     Ok(output)
 }
 
-fn encode_lossy(
-    data: &[u8],
-    width: u32,
-    height: u32,
-    dithering_level: f32,
-    speed_mode: bool,
-    quality: u8,
-) -> Result<Vec<u8>, String> {
+/// Rows per independently filtered and compressed band in the parallel path.
+#[cfg(feature = "parallel")]
+const PARALLEL_BAND_ROWS: usize = 128;
+
+/// Lossless encode with row filtering and DEFLATE split across rayon workers.
+/// Rows use the Sub filter, which only looks within the row, so bands are independent.
+/// Each band is compressed as its own raw DEFLATE segment (sync-flushed, only
+/// the last one final), so the concatenation is a single valid zlib stream in
+/// one IDAT. Costs a little ratio at band seams versus the serial path.
+#[cfg(feature = "parallel")]
+fn encode_lossless_parallel(data: &[u8], info: Info, speed_mode: bool, band_rows: usize) -> Result<Vec<u8>, String> {
+    use flate2::{Compress, Compression as Level, FlushCompress};
+    use rayon::prelude::*;
+
+    let stride = info.width as usize * 4;
+    let level = if speed_mode { Level::fast() } else { Level::best() };
+    let bands: Vec<&[u8]> = data.chunks(stride * band_rows).collect();
+    let last_band = bands.len() - 1;
+
+    let compressed: Vec<Result<(Vec<u8>, u32), String>> = bands
+        .par_iter()
+        .enumerate()
+        .map(|(i, band)| {
+            let mut filtered = Vec::with_capacity(band.len() + band.len() / stride);
+            for row in band.chunks(stride) {
+                filter_row(row, &mut filtered);
+            }
+
+            let mut compress = Compress::new(level, false);
+            let mut out = Vec::with_capacity(filtered.len() / 2 + 64);
+            let flush = if i == last_band { FlushCompress::Finish } else { FlushCompress::Sync };
+            loop {
+                let consumed = compress.total_in() as usize;
+                let status = compress
+                    .compress_vec(&filtered[consumed..], &mut out, flush)
+                    .map_err(|e| format!("PNG band compression failed: {:?}", e))?;
+                let done = compress.total_in() as usize == filtered.len()
+                    && (status == flate2::Status::StreamEnd || (flush == FlushCompress::Sync && out.len() < out.capacity()));
+                if done {
+                    break;
+                }
+                out.reserve(out.capacity().max(64));
+            }
+            Ok((out, adler32(&filtered)))
+        })
+        .collect();
+
+    // zlib header (deflate, 32K window, max compression), segments, Adler-32 trailer
+    let mut zlib = vec![0x78, 0xDA];
+    let mut checksum = 1u32;
+    for (band, result) in bands.iter().zip(compressed) {
+        let (segment, band_adler) = result?;
+        zlib.extend_from_slice(&segment);
+        checksum = adler32_combine(checksum, band_adler, band.len() + band.len() / stride);
+    }
+    zlib.extend_from_slice(&checksum.to_be_bytes());
+
+    let mut output = Vec::new();
+    {
+        let mut encoder = Encoder::with_info(&mut output, info)
+            .map_err(|e| format!("PNG header setup failed: {:?}", e))?;
+        encoder.set_color(ColorType::Rgba);
+        encoder.set_depth(BitDepth::Eight);
+        let mut writer = encoder
+            .write_header()
+            .map_err(|e| format!("PNG header write failed: {:?}", e))?;
+        writer
+            .write_chunk(png::chunk::IDAT, &zlib)
+            .map_err(|e| format!("PNG data write failed: {:?}", e))?;
+    }
+
+    Ok(output)
+}
+
+/// Append one Sub-filtered scanline (filter byte + data), the same filter the
+/// `png` crate applies by default in the serial path.
+#[cfg(feature = "parallel")]
+fn filter_row(row: &[u8], out: &mut Vec<u8>) {
+    const BPP: usize = 4;
+    out.push(1); // Sub
+    out.extend_from_slice(&row[..BPP.min(row.len())]);
+    out.extend(row.iter().skip(BPP).zip(row).map(|(&cur, &left)| cur.wrapping_sub(left)));
+}
+
+#[cfg(feature = "parallel")]
+const ADLER_MOD: u32 = 65521;
+
+#[cfg(feature = "parallel")]
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    // 5552 is the largest run that can't overflow u32 before reducing
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= ADLER_MOD;
+        b %= ADLER_MOD;
+    }
+    (b << 16) | a
+}
+
+/// Adler-32 of A followed by B, from adler(A), adler(B) and len(B) (zlib's adler32_combine).
+#[cfg(feature = "parallel")]
+fn adler32_combine(adler_a: u32, adler_b: u32, len_b: usize) -> u32 {
+    let rem = (len_b % ADLER_MOD as usize) as u64;
+    let m = ADLER_MOD as u64;
+    let (a1, b1) = ((adler_a & 0xFFFF) as u64, (adler_a >> 16) as u64);
+    let (a2, b2) = ((adler_b & 0xFFFF) as u64, (adler_b >> 16) as u64);
+    let a = (a1 + a2 + m - 1) % m;
+    let b = (b1 + b2 + rem * a1 % m + m - rem) % m;
+    ((b as u32) << 16) | a as u32
+}
+
+fn encode_lossy(data: &[u8], info: Info, options: &PngOptions) -> Result<Vec<u8>, String> {
+    let PngOptions { dithering_level, speed_mode, liq_speed, quality, deterministic, .. } = *options;
     // 1. Convert raw bytes to RGBA pixels
     let pixels: Vec<RGBA> = data
         .chunks(4)
@@ -78,39 +223,84 @@ fn encode_lossy(
         })
         .collect();
 
+    let (width, height) = (info.width, info.height);
+    // Speed: 1 = slowest/best, 10 = fastest. In speed mode, use 10 for ~2x
+    // speedup; otherwise 5 for balanced quality, unless set explicitly
+    let liq_speed = liq_speed.map_or(if speed_mode { 10 } else { 5 }, |s| s.clamp(1, 10));
+
     // 2. Quantize with libimagequant
-    let mut attr = Attributes::new();
-    // Speed: 1 = slowest/best, 10 = fastest
-    // In speed mode, use 10 for ~2x speedup; otherwise use 5 for balanced quality
-    attr.set_speed(if speed_mode { 10 } else { 5 })
-        .map_err(|e| format!("Failed to set LIQ speed: {:?}", e))?;
-    // Quality 80 means range 60-80, quality 100 means 80-100
-    let min_quality = quality.saturating_sub(20);
-    attr.set_quality(min_quality, quality)
-        .map_err(|e| format!("Failed to set LIQ quality: {:?}", e))?;
+    let (palette, indexed_pixels) = if deterministic {
+        // imagequant is otherwise deterministic (no RNG; dithering is a fixed
+        // Floyd-Steinberg scan), but both palette refinement and remapping
+        // merge per-thread k-means accumulators and error sums in scheduling
+        // order, so pin it to a single-thread pool for byte-reproducible output
+        match rayon::ThreadPoolBuilder::new().num_threads(1).build() {
+            Ok(pool) => pool.install(|| quantize(pixels, width, height, dithering_level, liq_speed, quality))?,
+            // No thread support (e.g. plain wasm32): already single-threaded
+            Err(_) => quantize(pixels, width, height, dithering_level, liq_speed, quality)?,
+        }
+    } else {
+        quantize(pixels, width, height, dithering_level, liq_speed, quality)?
+    };
 
-    let mut img = attr
-        .new_image(pixels, width as usize, height as usize, 0.0)
-        .map_err(|e| format!("Failed to create LIQ image: {:?}", e))?;
+    // 3. Encode to PNG with palette using the `png` crate
+    write_indexed(info, &palette, &indexed_pixels, speed_mode)
+}
 
-    let mut res = attr
-        .quantize(&mut img)
-        .map_err(|e| format!("Quantization failed: {:?}", e))?;
+/// Automatic lossless/lossy choice: images with at most 256 distinct RGBA
+/// colors are written as an exact palette (lossless, and far smaller than
+/// RGBA); anything else goes through the quantizer.
+/// Returns the PNG and whether the exact palette was used.
+pub fn encode_png_auto(data: &[u8], width: u32, height: u32, options: &PngOptions) -> Result<(Vec<u8>, bool), String> {
+    super::check_nonzero(width, height)?;
+    super::check_rgba_len(data, width, height)?;
 
-    res.set_dithering_level(dithering_level)
-        .map_err(|e| format!("Failed to set dithering: {:?}", e))?;
+    let info = png_info(width, height, options.metadata);
+    match exact_palette(data) {
+        Some((palette, indices)) => {
+            let fast_deflate = fast_compression(options.extra, options.speed_mode)?;
+            Ok((write_indexed(info, &palette, &indices, fast_deflate)?, true))
+        }
+        None => Ok((encode_lossy(data, info, options)?, false)),
+    }
+}
 
-    let (palette, indexed_pixels) = res
-        .remapped(&mut img)
-        .map_err(|e| format!("Remapping failed: {:?}", e))?;
+/// Palette and per-pixel indices when the image has at most 256 distinct
+/// colors, in first-seen order; None otherwise.
+fn exact_palette(data: &[u8]) -> Option<(Vec<RGBA>, Vec<u8>)> {
+    let mut lookup = std::collections::HashMap::new();
+    let mut palette = Vec::new();
+    let mut indices = Vec::with_capacity(data.len() / 4);
+    for px in data.chunks_exact(4) {
+        let index = *lookup.entry([px[0], px[1], px[2], px[3]]).or_insert_with(|| {
+            palette.push(RGBA::new(px[0], px[1], px[2], px[3]));
+            palette.len() - 1
+        });
+        if index > 255 {
+            return None;
+        }
+        indices.push(index as u8);
+    }
+    Some((palette, indices))
+}
 
-    // 3. Encode to PNG with palette using the `png` crate
+/// Write one index per pixel as a palette PNG with a tRNS chunk.
+fn write_indexed(info: Info, palette: &[RGBA], indexed_pixels: &[u8], speed_mode: bool) -> Result<Vec<u8>, String> {
+    // Small palettes pack several indices per byte (1/2/4-bit)
+    let bits = palette_bit_depth(palette.len());
+    let indexed_pixels = pack_indices(indexed_pixels, info.width as usize, bits);
     let mut output = Vec::new();
 
     {
-        let mut encoder = Encoder::new(&mut output, width, height);
+        let mut encoder = Encoder::with_info(&mut output, info)
+            .map_err(|e| format!("PNG header setup failed: {:?}", e))?;
         encoder.set_color(ColorType::Indexed);
-        encoder.set_depth(BitDepth::Eight);
+        encoder.set_depth(match bits {
+            1 => BitDepth::One,
+            2 => BitDepth::Two,
+            4 => BitDepth::Four,
+            _ => BitDepth::Eight,
+        });
         // Use Fast compression in speed mode, Best otherwise
         encoder.set_compression(if speed_mode { Compression::Fast } else { Compression::Best });
 
@@ -118,7 +308,7 @@ fn encode_lossy(
         let mut rgb_palette: Vec<u8> = Vec::with_capacity(palette.len() * 3);
         let mut trns: Vec<u8> = Vec::with_capacity(palette.len());
 
-        for px in &palette {
+        for px in palette {
             rgb_palette.push(px.r);
             rgb_palette.push(px.g);
             rgb_palette.push(px.b);
@@ -139,3 +329,307 @@ fn encode_lossy(
 
     Ok(output)
 }
+
+/// Smallest PNG bit depth that can index `colors` palette entries.
+fn palette_bit_depth(colors: usize) -> u8 {
+    match colors {
+        0..=2 => 1,
+        3..=4 => 2,
+        5..=16 => 4,
+        _ => 8,
+    }
+}
+
+/// Pack 8-bit indices into `bits`-wide samples, MSB first, each row padded
+/// to a whole byte as PNG requires.
+fn pack_indices(indices: &[u8], width: usize, bits: u8) -> Vec<u8> {
+    if bits == 8 || width == 0 {
+        return indices.to_vec();
+    }
+    let per_byte = (8 / bits) as usize;
+    let mut packed = Vec::with_capacity(indices.len().div_ceil(per_byte));
+    for row in indices.chunks(width) {
+        for group in row.chunks(per_byte) {
+            let byte = group
+                .iter()
+                .enumerate()
+                .fold(0u8, |acc, (i, &idx)| acc | (idx << (8 - bits as usize * (i + 1))));
+            packed.push(byte);
+        }
+    }
+    packed
+}
+
+/// Decode a PNG image to RGBA pixels. Palette, grayscale and 16-bit images
+/// are expanded to 8-bit RGBA (tRNS becomes alpha).
+/// Returns (pixels, width, height)
+pub fn decode_png(data: &[u8]) -> Result<(Vec<u8>, u32, u32), String> {
+    decode_png_with_transfer(data).map(|(pixels, width, height, _)| (pixels, width, height))
+}
+
+/// Like `decode_png`, also returning the transfer function its `sRGB`/`gAMA`
+/// chunks declare (sRGB when absent). Pixels are not gamma-converted.
+/// Returns (pixels, width, height, transfer)
+pub fn decode_png_with_transfer(data: &[u8]) -> Result<(Vec<u8>, u32, u32, Transfer), String> {
+    if !is_png(data) {
+        return Err("Not a valid PNG file".to_string());
+    }
+
+    let mut decoder = png::Decoder::new(data);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder
+        .read_info()
+        .map_err(|e| format!("Failed to read PNG header: {:?}", e))?;
+    let info = reader.info();
+    let transfer = Transfer::from_png(info.srgb.is_some(), info.source_gamma.map(|g| g.into_value()));
+    let mut pixels = vec![0u8; reader.output_buffer_size()];
+    let frame = reader
+        .next_frame(&mut pixels)
+        .map_err(|e| format!("Failed to decode PNG: {:?}", e))?;
+    pixels.truncate(frame.buffer_size());
+
+    let rgba = match frame.color_type {
+        ColorType::Rgba => pixels,
+        ColorType::Rgb => crate::color::rgb_to_rgba(&pixels),
+        ColorType::GrayscaleAlpha => pixels.chunks_exact(2).flat_map(|p| [p[0], p[0], p[0], p[1]]).collect(),
+        ColorType::Grayscale => pixels.iter().flat_map(|&g| [g, g, g, 255]).collect(),
+        ColorType::Indexed => return Err("PNG palette was not expanded".to_string()),
+    };
+
+    Ok((rgba, frame.width, frame.height, transfer))
+}
+
+/// Pixel aspect ratio (pixel width / height) from the pHYs chunk, or None
+/// for square pixels or when it's absent.
+pub fn pixel_aspect(data: &[u8]) -> Option<f32> {
+    let reader = png::Decoder::new(data).read_info().ok()?;
+    let dims = reader.info().pixel_dims?;
+    if dims.xppu == 0 || dims.yppu == 0 || dims.xppu == dims.yppu {
+        return None;
+    }
+    Some(dims.yppu as f32 / dims.xppu as f32)
+}
+
+/// Check if data is a PNG file by checking the 8-byte signature
+pub fn is_png(data: &[u8]) -> bool {
+    data.starts_with(b"\x89PNG\r\n\x1a\n")
+}
+
+/// Analysis helper: the palette imagequant would choose for at most
+/// `max_colors` colors (2-256), without remapping or encoding the image.
+pub fn preview_palette(data: &[u8], width: u32, height: u32, max_colors: u32) -> Result<Vec<[u8; 4]>, String> {
+    super::check_rgba_len(data, width, height)?;
+
+    let pixels: Vec<RGBA> = data
+        .chunks(4)
+        .map(|chunk| RGBA::new(chunk[0], chunk[1], chunk[2], chunk[3]))
+        .collect();
+
+    let mut attr = Attributes::new();
+    attr.set_max_colors(max_colors.clamp(2, 256))
+        .map_err(|e| format!("Failed to set LIQ max colors: {:?}", e))?;
+
+    let mut img = attr
+        .new_image(pixels, width as usize, height as usize, 0.0)
+        .map_err(|e| format!("Failed to create LIQ image: {:?}", e))?;
+
+    let mut res = attr
+        .quantize(&mut img)
+        .map_err(|e| format!("Quantization failed: {:?}", e))?;
+
+    Ok(res.palette().iter().map(|px| [px.r, px.g, px.b, px.a]).collect())
+}
+
+/// Quantize RGBA pixels with libimagequant, returning (palette, indexed pixels).
+fn quantize(
+    pixels: Vec<RGBA>,
+    width: u32,
+    height: u32,
+    dithering_level: f32,
+    liq_speed: u8,
+    quality: u8,
+) -> Result<(Vec<RGBA>, Vec<u8>), String> {
+    let mut attr = Attributes::new();
+    attr.set_speed(liq_speed.into())
+        .map_err(|e| format!("Failed to set LIQ speed: {:?}", e))?;
+    // Quality 80 means range 60-80, quality 100 means 80-100
+    let min_quality = quality.saturating_sub(20);
+    attr.set_quality(min_quality, quality)
+        .map_err(|e| format!("Failed to set LIQ quality: {:?}", e))?;
+
+    let mut img = attr
+        .new_image(pixels, width as usize, height as usize, 0.0)
+        .map_err(|e| format!("Failed to create LIQ image: {:?}", e))?;
+
+    // An image that can't reach min_quality in 256 colors would otherwise
+    // abort the whole request; take the best palette it can get instead
+    let mut res = match attr.quantize(&mut img) {
+        Err(imagequant::Error::QualityTooLow) => {
+            attr.set_quality(0, quality)
+                .map_err(|e| format!("Failed to set LIQ quality: {:?}", e))?;
+            attr.quantize(&mut img)
+        }
+        res => res,
+    }
+    .map_err(|e| format!("Quantization failed: {:?}", e))?;
+
+    res.set_dithering_level(dithering_level)
+        .map_err(|e| format!("Failed to set dithering: {:?}", e))?;
+
+    let (palette, indexed_pixels) = res
+        .remapped(&mut img)
+        .map_err(|e| format!("Remapping failed: {:?}", e))?;
+
+    Ok((palette, indexed_pixels))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unreachable_min_quality_relaxes_instead_of_failing() {
+        // Smooth full-color noise: far more than 256 colors at quality 100 (floor 80)
+        let (w, h) = (64u32, 64u32);
+        let data: Vec<u8> = (0..w * h)
+            .flat_map(|i| {
+                let n = i.wrapping_mul(2654435761);
+                [(n >> 24) as u8, (n >> 16) as u8, (n >> 8) as u8, 255]
+            })
+            .collect();
+        let encoded = encode_png(&data, w, h, &PngOptions { quality: 100, deterministic: true, ..Default::default() }).unwrap();
+        let (pixels, dw, dh) = decode_png(&encoded).unwrap();
+        assert_eq!((dw, dh, pixels.len()), (w, h, data.len()));
+    }
+
+    #[test]
+    fn test_decode_reports_declared_gamma() {
+        // Half-transparent white, written with a 1.8 (classic Mac) gAMA chunk
+        let mut png = Vec::new();
+        {
+            let mut encoder = Encoder::new(&mut png, 1, 1);
+            encoder.set_color(ColorType::Rgba);
+            encoder.set_source_gamma(png::ScaledFloat::new(1.0 / 1.8));
+            encoder.write_header().unwrap().write_image_data(&[255, 255, 255, 128]).unwrap();
+        }
+        let (pixels, _, _, transfer) = decode_png_with_transfer(&png).unwrap();
+        assert!(matches!(transfer, Transfer::Gamma(e) if (e - 1.8).abs() < 1e-3), "{:?}", transfer);
+
+        // Linear flatten over black follows the declared curve, not sRGB's
+        let expected = ((128.0f32 / 255.0).powf(1.0 / 1.8) * 255.0).round() as u8;
+        assert_eq!(crate::filters::flatten(&pixels, 1, 1, [0; 3], Some(transfer))[0], expected);
+        assert_ne!(crate::filters::flatten(&pixels, 1, 1, [0; 3], Some(Transfer::Srgb))[0], expected);
+
+        // sRGB writers' 1/2.2 and no chunk at all both mean sRGB
+        assert_eq!(Transfer::from_png(false, Some(0.45455)), Transfer::Srgb);
+        assert_eq!(Transfer::from_png(true, Some(1.0 / 1.8)), Transfer::Srgb);
+        let plain = encode_png(&pixels, 1, 1, &PngOptions { lossless: true, ..Default::default() }).unwrap();
+        assert_eq!(decode_png_with_transfer(&plain).unwrap().3, Transfer::Srgb);
+    }
+
+    #[test]
+    fn test_four_color_image_packs_to_two_bits() {
+        let colors = [[255, 0, 0, 255], [0, 255, 0, 255], [0, 0, 255, 255], [255, 255, 255, 255]];
+        let (w, h) = (7u32, 5u32); // odd width exercises row padding
+        let data: Vec<u8> = (0..w * h).flat_map(|i| colors[(i as usize * 3 / 2) % 4]).collect();
+
+        let encoded = encode_png(&data, w, h, &PngOptions { dithering_level: 0.0, quality: 100, ..Default::default() }).unwrap();
+        let mut reader = png::Decoder::new(encoded.as_slice()).read_info().unwrap();
+        assert_eq!(reader.info().bit_depth, BitDepth::Two);
+
+        let (color_type, _) = reader.output_color_type();
+        assert_eq!(color_type, ColorType::Indexed);
+        let mut indices = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut indices).unwrap();
+        let palette = reader.info().palette.as_ref().unwrap().to_vec();
+
+        // Unpack and resolve through the palette: must match the source exactly
+        let stride = (w as usize * 2).div_ceil(8);
+        for y in 0..h as usize {
+            for x in 0..w as usize {
+                let idx = (indices[y * stride + x / 4] >> (6 - 2 * (x % 4))) & 0b11;
+                let rgb = &palette[idx as usize * 3..idx as usize * 3 + 3];
+                let src = (y * w as usize + x) * 4;
+                assert_eq!(rgb, &data[src..src + 3], "({}, {})", x, y);
+            }
+        }
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn test_parallel_lossless_decodes_like_serial() {
+        let (w, h) = (37u32, 50u32);
+        let data: Vec<u8> = (0..w * h)
+            .flat_map(|i| [(i % w * 7) as u8, (i / w * 5) as u8, (i.wrapping_mul(2654435761) >> 24) as u8, 200])
+            .collect();
+        let decode = |encoded: &[u8]| {
+            let mut reader = png::Decoder::new(encoded).read_info().unwrap();
+            let mut pixels = vec![0; reader.output_buffer_size()];
+            reader.next_frame(&mut pixels).unwrap();
+            pixels
+        };
+
+        let serial = encode_lossless(&data, Info::with_size(w, h), false).unwrap();
+        // Small bands so the image spans several independently compressed segments
+        let parallel = encode_lossless_parallel(&data, Info::with_size(w, h), false, 8).unwrap();
+        assert_eq!(decode(&parallel), decode(&serial));
+        assert_eq!(decode(&parallel), data);
+    }
+
+    #[test]
+    fn test_deterministic_lossy_is_stable_across_thread_pools() {
+        // Grainy gradient so palette refinement and dithering have real work to do
+        let mut seed = 12345u32;
+        let data: Vec<u8> = (0..96 * 96u32)
+            .flat_map(|i| {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                let grain = (seed >> 28) as u8;
+                [(i % 96 * 2) as u8 + grain, (i / 96 * 2) as u8 + grain, 128, 255]
+            })
+            .collect();
+        let encode = || encode_png(&data, 96, 96, &PngOptions { deterministic: true, ..Default::default() }).unwrap();
+
+        let first = encode();
+        assert_eq!(encode(), first);
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(4).build().unwrap();
+        assert_eq!(pool.install(encode), first);
+    }
+
+    #[test]
+    fn test_liq_speed_one_beats_speed_ten() {
+        // Smooth two-axis color gradient: more colors than the palette can hold
+        let (w, h) = (128u32, 128u32);
+        let data: Vec<u8> = (0..w * h)
+            .flat_map(|i| [(i % w * 2) as u8, (i / w * 2) as u8, ((i % w + i / w) * 255 / 254) as u8, 255])
+            .collect();
+        let error = |speed| {
+            let options = PngOptions { dithering_level: 0.0, liq_speed: Some(speed), quality: 70, deterministic: true, ..Default::default() };
+            let encoded = encode_png(&data, w, h, &options).unwrap();
+            let (decoded, _, _) = decode_png(&encoded).unwrap();
+            data.iter()
+                .zip(&decoded)
+                .map(|(&a, &b)| (a as i64 - b as i64).pow(2))
+                .sum::<i64>()
+        };
+
+        let (best, fastest) = (error(1), error(10));
+        assert!(best < fastest, "speed 1 error {} vs speed 10 error {}", best, fastest);
+    }
+
+    #[test]
+    fn test_preview_palette_spans_gradient() {
+        let (w, h) = (64u32, 16u32);
+        let data: Vec<u8> = (0..h)
+            .flat_map(|_| (0..w).flat_map(|x| {
+                let v = (x * 255 / (w - 1)) as u8;
+                [v, v, v, 255]
+            }))
+            .collect();
+
+        let palette = preview_palette(&data, w, h, 8).unwrap();
+        assert!(!palette.is_empty() && palette.len() <= 8, "{} colors", palette.len());
+        let darkest = palette.iter().map(|c| c[0]).min().unwrap();
+        let lightest = palette.iter().map(|c| c[0]).max().unwrap();
+        assert!(darkest < 32 && lightest > 223, "range {}..{}", darkest, lightest);
+    }
+}
@@ -1,9 +1,22 @@
-use tiff::decoder::{Decoder, DecodingResult};
+use tiff::decoder::{ifd::Value, Decoder, DecodingResult};
+use tiff::tags::Tag;
 use std::io::Cursor;
 
 /// Decode a TIFF image to RGBA pixels.
+/// Float samples are auto-normalized from their min/max (see `decode_tiff_normalized`).
 /// Returns (pixels, width, height)
 pub fn decode_tiff(data: &[u8]) -> Result<(Vec<u8>, u32, u32), String> {
+    decode_tiff_normalized(data, None)
+}
+
+/// Decode a TIFF image to RGBA pixels, mapping 32-bit float color samples to
+/// 8-bit through `float_range` (low, high), or the image's own finite min/max
+/// when None. Float alpha is taken as 0.0-1.0.
+/// Returns (pixels, width, height)
+pub fn decode_tiff_normalized(
+    data: &[u8],
+    float_range: Option<(f32, f32)>,
+) -> Result<(Vec<u8>, u32, u32), String> {
     // Validate TIFF magic bytes
     if !is_tiff(data) {
         return Err("Not a valid TIFF file".to_string());
@@ -16,6 +29,29 @@ pub fn decode_tiff(data: &[u8]) -> Result<(Vec<u8>, u32, u32), String> {
     let (width, height) = decoder.dimensions()
         .map_err(|e| format!("Failed to get TIFF dimensions: {:?}", e))?;
 
+    // The tiff crate reads sub-byte samples as if they were one byte each,
+    // so bilevel and 2/4-bit grayscale strips are unpacked here instead
+    if let Ok(tiff::ColorType::Gray(bits @ (1 | 2 | 4))) = decoder.colortype() {
+        return decode_packed_gray(&mut decoder, data, width, height, bits)
+            .map(|rgba| (rgba, width, height));
+    }
+
+    // Likewise for pixels with more samples than RGBA (e.g. microscopy or
+    // GIS channels), which the tiff crate rejects outright
+    if decoder.colortype().is_err() {
+        let samples = tag_vec(&mut decoder, Tag::SamplesPerPixel).unwrap_or(vec![1]);
+        let photometric = tag_vec(&mut decoder, Tag::PhotometricInterpretation)?;
+        let color_channels = match photometric[..] {
+            [0 | 1] => 1,
+            [2] => 3,
+            _ => 0,
+        };
+        if color_channels > 0 && samples[0] > color_channels {
+            return decode_extra_samples(&mut decoder, data, width, height, samples[0] as usize, color_channels as usize)
+                .map(|rgba| (rgba, width, height));
+        }
+    }
+
     let result = decoder.read_image()
         .map_err(|e| format!("Failed to decode TIFF: {:?}", e))?;
 
@@ -33,14 +69,15 @@ pub fn decode_tiff(data: &[u8]) -> Result<(Vec<u8>, u32, u32), String> {
                         .collect()
                 }
                 tiff::ColorType::RGB(8) => {
-                    // Convert RGB to RGBA
-                    pixels.chunks(3)
-                        .flat_map(|rgb| [rgb[0], rgb[1], rgb[2], 255])
-                        .collect()
+                    crate::color::rgb_to_rgba(&pixels)
                 }
                 tiff::ColorType::RGBA(8) => {
                     pixels
                 }
+                tiff::ColorType::CMYK(8) => {
+                    // Separated TIFFs store ink amounts (0 = no ink)
+                    crate::color::cmyk_to_rgba(&pixels, false, None)
+                }
                 tiff::ColorType::GrayA(8) => {
                     // Convert grayscale + alpha to RGBA
                     pixels.chunks(2)
@@ -82,12 +119,187 @@ pub fn decode_tiff(data: &[u8]) -> Result<(Vec<u8>, u32, u32), String> {
                 _ => return Err(format!("Unsupported TIFF 16-bit color type: {:?}", color_type)),
             }
         }
+        DecodingResult::F32(pixels) => {
+            let color_type = decoder.colortype()
+                .map_err(|e| format!("Failed to get color type: {:?}", e))?;
+
+            let (channels, has_alpha) = match color_type {
+                tiff::ColorType::Gray(32) => (1, false),
+                tiff::ColorType::RGB(32) => (3, false),
+                tiff::ColorType::RGBA(32) => (4, true),
+                _ => return Err(format!("Unsupported TIFF float color type: {:?}", color_type)),
+            };
+            let color_channels = if has_alpha { 3 } else { channels };
+
+            let (low, high) = float_range.unwrap_or_else(|| {
+                pixels
+                    .chunks(channels)
+                    .flat_map(|px| &px[..color_channels])
+                    .filter(|v| v.is_finite())
+                    .fold((f32::INFINITY, f32::NEG_INFINITY), |(lo, hi), &v| (lo.min(v), hi.max(v)))
+            });
+            let scale = if high > low { 255.0 / (high - low) } else { 0.0 };
+            let to_u8 = |v: f32| if v.is_nan() { 0 } else { ((v - low) * scale).round().clamp(0.0, 255.0) as u8 };
+            let alpha_to_u8 = |v: f32| (v * 255.0).round().clamp(0.0, 255.0) as u8;
+
+            pixels.chunks(channels)
+                .flat_map(|px| match channels {
+                    1 => {
+                        let g = to_u8(px[0]);
+                        [g, g, g, 255]
+                    }
+                    3 => [to_u8(px[0]), to_u8(px[1]), to_u8(px[2]), 255],
+                    _ => [to_u8(px[0]), to_u8(px[1]), to_u8(px[2]), alpha_to_u8(px[3])],
+                })
+                .collect()
+        }
         _ => return Err("Unsupported TIFF pixel format".to_string()),
     };
 
     Ok((rgba, width, height))
 }
 
+/// Pixel aspect ratio (pixel width / height) from XResolution and
+/// YResolution, or None for square pixels or when either is missing.
+/// A higher X resolution means narrower pixels, so this is Y / X.
+pub fn pixel_aspect(data: &[u8]) -> Option<f32> {
+    if !is_tiff(data) {
+        return None;
+    }
+    let mut decoder = Decoder::new(Cursor::new(data)).ok()?;
+    let mut resolution = |tag| match decoder.find_tag(tag).ok()?? {
+        Value::Rational(n, d) if n > 0 && d > 0 => Some(n as f64 / d as f64),
+        _ => None,
+    };
+    let (x, y) = (resolution(Tag::XResolution)?, resolution(Tag::YResolution)?);
+    let aspect = (y / x) as f32;
+    ((aspect - 1.0).abs() > 1e-3).then_some(aspect)
+}
+
+type TiffDecoder<'a> = Decoder<Cursor<&'a [u8]>>;
+
+fn tag_vec(decoder: &mut TiffDecoder, tag: Tag) -> Result<Vec<u64>, String> {
+    decoder.get_tag_u64_vec(tag)
+        .map_err(|e| format!("Failed to read TIFF {:?}: {:?}", tag, e))
+}
+
+/// Concatenate the raw bytes of every strip, for layouts the tiff crate
+/// can't expand itself. Only uncompressed strips are supported.
+fn read_uncompressed_strips(decoder: &mut TiffDecoder, data: &[u8], what: &str) -> Result<Vec<u8>, String> {
+    let compression = tag_vec(decoder, Tag::Compression).unwrap_or(vec![1]);
+    if compression != [1] {
+        return Err(format!("Unsupported compression {:?} for {} TIFF", compression, what));
+    }
+    let offsets = tag_vec(decoder, Tag::StripOffsets)?;
+    let counts = tag_vec(decoder, Tag::StripByteCounts)?;
+
+    let mut raw = Vec::new();
+    for (&offset, &count) in offsets.iter().zip(&counts) {
+        let strip = usize::try_from(offset).ok()
+            .zip(usize::try_from(count).ok())
+            .and_then(|(start, len)| data.get(start..start.checked_add(len)?))
+            .ok_or("TIFF strip lies outside the file")?;
+        raw.extend_from_slice(strip);
+    }
+    Ok(raw)
+}
+
+/// Decode uncompressed, stripped 1/2/4-bit grayscale to full-range RGBA,
+/// honoring WhiteIsZero (0 = white) vs BlackIsZero (0 = black).
+fn decode_packed_gray(
+    decoder: &mut TiffDecoder,
+    data: &[u8],
+    width: u32,
+    height: u32,
+    bits: u8,
+) -> Result<Vec<u8>, String> {
+    let white_is_zero = tag_vec(decoder, Tag::PhotometricInterpretation)? == [0];
+    // Strips hold whole rows, each padded to a byte boundary
+    let packed = read_uncompressed_strips(decoder, data, &format!("{}-bit grayscale", bits))?;
+
+    let (width, height, bits) = (width as usize, height as usize, bits as usize);
+    let row_bytes = (width * bits).div_ceil(8);
+    if packed.len() < row_bytes * height {
+        return Err("TIFF strips are shorter than the image".to_string());
+    }
+
+    let max = (1u32 << bits) - 1;
+    let mut rgba = Vec::with_capacity(width * height * 4);
+    for row in packed.chunks(row_bytes).take(height) {
+        for x in 0..width {
+            let bit = x * bits;
+            let mut value = (row[bit / 8] >> (8 - bits - bit % 8)) as u32 & max;
+            if white_is_zero {
+                value = max - value;
+            }
+            let g = (value * 255 / max) as u8;
+            rgba.extend_from_slice(&[g, g, g, 255]);
+        }
+    }
+    Ok(rgba)
+}
+
+/// Decode uncompressed, interleaved 8/16-bit pixels that carry extra samples
+/// beyond gray or RGB. The first ExtraSample marked as alpha (associated or
+/// unassociated) becomes the alpha channel; without one the image is opaque.
+/// Any further channels are ignored.
+fn decode_extra_samples(
+    decoder: &mut TiffDecoder,
+    data: &[u8],
+    width: u32,
+    height: u32,
+    samples: usize,
+    color_channels: usize,
+) -> Result<Vec<u8>, String> {
+    let bits = tag_vec(decoder, Tag::BitsPerSample)?;
+    let bytes_per_sample = match bits[0] {
+        8 => 1,
+        16 => 2,
+        n => return Err(format!("Unsupported {}-bit TIFF with {} samples per pixel", n, samples)),
+    };
+    if bits.iter().any(|&b| b != bits[0]) {
+        return Err(format!("Unsupported mixed TIFF bit depths {:?}", bits));
+    }
+    if tag_vec(decoder, Tag::PlanarConfiguration).unwrap_or(vec![1]) != [1] {
+        return Err("Unsupported planar TIFF with extra samples".to_string());
+    }
+    let white_is_zero = tag_vec(decoder, Tag::PhotometricInterpretation)? == [0];
+    let extra = tag_vec(decoder, Tag::ExtraSamples).unwrap_or_default();
+    let alpha = extra.iter().position(|&kind| kind == 1 || kind == 2);
+    let premultiplied = alpha.is_some_and(|i| extra[i] == 1);
+    let alpha = alpha.map(|i| color_channels + i).filter(|&i| i < samples);
+
+    let raw = read_uncompressed_strips(decoder, data, &format!("{}-sample", samples))?;
+    let pixel_bytes = samples * bytes_per_sample;
+    let pixel_count = width as usize * height as usize;
+    if raw.len() < pixel_count * pixel_bytes {
+        return Err("TIFF strips are shorter than the image".to_string());
+    }
+
+    // Keep the high byte of 16-bit samples, whichever order they're stored in
+    let high_byte = if bytes_per_sample == 2 && data.starts_with(b"II") { 1 } else { 0 };
+    let mut rgba = Vec::with_capacity(pixel_count * 4);
+    for px in raw.chunks_exact(pixel_bytes).take(pixel_count) {
+        let sample = |i: usize| px[i * bytes_per_sample + high_byte];
+        let a = alpha.map_or(255, sample);
+        let color = |i: usize| {
+            let v = if white_is_zero { 255 - sample(i) } else { sample(i) };
+            if premultiplied && a > 0 {
+                (v as u32 * 255 / a as u32).min(255) as u8
+            } else {
+                v
+            }
+        };
+        if color_channels == 1 {
+            let g = color(0);
+            rgba.extend_from_slice(&[g, g, g, a]);
+        } else {
+            rgba.extend_from_slice(&[color(0), color(1), color(2), a]);
+        }
+    }
+    Ok(rgba)
+}
+
 /// Check if data is a TIFF file by checking magic bytes
 pub fn is_tiff(data: &[u8]) -> bool {
     data.len() >= 4 && (
@@ -97,3 +309,129 @@ pub fn is_tiff(data: &[u8]) -> bool {
         (&data[0..4] == b"MM\x00\x2a")
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tiff::encoder::{colortype, TiffEncoder};
+
+    /// Minimal uncompressed little-endian TIFF with one strip.
+    fn make_tiff(width: u32, height: u32, bits: u16, photometric: u16, strip: &[u8]) -> Vec<u8> {
+        make_tiff_samples(width, height, bits, photometric, 1, &[], strip)
+    }
+
+    /// Same as `make_tiff` with interleaved samples; at most two ExtraSamples
+    /// so the values fit inline in the IFD entry.
+    fn make_tiff_samples(
+        width: u32,
+        height: u32,
+        bits: u16,
+        photometric: u16,
+        samples: u16,
+        extra: &[u16],
+        strip: &[u8],
+    ) -> Vec<u8> {
+        let mut entries: Vec<(u16, u16, u32, u32)> = vec![
+            (256, 4, 1, width),              // ImageWidth
+            (257, 4, 1, height),             // ImageLength
+            (258, 3, 1, bits as u32),        // BitsPerSample
+            (259, 3, 1, 1),                  // Compression: none
+            (262, 3, 1, photometric as u32), // PhotometricInterpretation
+            (273, 4, 1, 0),                  // StripOffsets: patched below
+            (277, 3, 1, samples as u32),     // SamplesPerPixel
+            (278, 4, 1, height),             // RowsPerStrip
+            (279, 4, 1, strip.len() as u32), // StripByteCounts
+        ];
+        if !extra.is_empty() {
+            let packed = extra.iter().rev().fold(0u32, |acc, &kind| acc << 16 | kind as u32);
+            entries.push((338, 3, extra.len() as u32, packed)); // ExtraSamples
+        }
+        entries[5].3 = 8 + 2 + entries.len() as u32 * 12 + 4; // Right after the IFD
+
+        let mut data = b"II\x2a\x00".to_vec();
+        data.extend_from_slice(&8u32.to_le_bytes());
+        data.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+        for (tag, kind, count, value) in entries {
+            data.extend_from_slice(&tag.to_le_bytes());
+            data.extend_from_slice(&kind.to_le_bytes());
+            data.extend_from_slice(&count.to_le_bytes());
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        data.extend_from_slice(&0u32.to_le_bytes()); // no next IFD
+        data.extend_from_slice(strip);
+        data
+    }
+
+    #[test]
+    fn test_decode_bilevel_white_is_zero() {
+        // 10x2 fax-style page: set bits are ink. Row 0 has ink at x = 3, 4 and 9.
+        let tiff = make_tiff(10, 2, 1, 0, &[0b0001_1000, 0b0100_0000, 0, 0]);
+        let (pixels, width, height) = decode_tiff(&tiff).unwrap();
+        assert_eq!((width, height), (10, 2));
+        let gray: Vec<u8> = pixels.chunks(4).map(|px| px[0]).collect();
+        let ink = [3, 4, 9];
+        for (i, &g) in gray.iter().enumerate() {
+            let expected = if i < 10 && ink.contains(&i) { 0 } else { 255 };
+            assert_eq!(g, expected, "pixel {}", i);
+        }
+
+        // 4-bit BlackIsZero expands to full range
+        let (pixels, _, _) = decode_tiff(&make_tiff(2, 1, 4, 1, &[0x0F])).unwrap();
+        assert_eq!((pixels[0], pixels[4]), (0, 255));
+    }
+
+    #[test]
+    fn test_decode_rgb_with_two_extra_samples() {
+        // RGB + an unspecified channel + unassociated alpha: alpha is sample 4,
+        // the unspecified channel (sample 3) is dropped
+        let strip = [10, 20, 30, 99, 255, 200, 100, 50, 7, 128];
+        let tiff = make_tiff_samples(2, 1, 8, 2, 5, &[0, 2], &strip);
+        let (pixels, width, height) = decode_tiff(&tiff).unwrap();
+        assert_eq!((width, height), (2, 1));
+        assert_eq!(pixels, [10, 20, 30, 255, 200, 100, 50, 128]);
+
+        // No alpha flagged: opaque
+        let tiff = make_tiff_samples(2, 1, 8, 2, 5, &[0, 0], &strip);
+        let (pixels, _, _) = decode_tiff(&tiff).unwrap();
+        assert_eq!(pixels, [10, 20, 30, 255, 200, 100, 50, 255]);
+    }
+
+    #[test]
+    fn test_decode_cmyk_matches_shared_conversion() {
+        // Cyan, magenta, yellow, black, and a mid-tone
+        let samples = [255, 0, 0, 0, 0, 255, 0, 0, 0, 0, 255, 0, 0, 0, 0, 255, 128, 0, 0, 128];
+        let mut data = Cursor::new(Vec::new());
+        TiffEncoder::new(&mut data)
+            .unwrap()
+            .write_image::<colortype::CMYK8>(5, 1, &samples)
+            .unwrap();
+
+        let (pixels, width, height) = decode_tiff(&data.into_inner()).unwrap();
+        assert_eq!((width, height), (5, 1));
+        let expected = [[0, 255, 255, 255], [255, 0, 255, 255], [255, 255, 0, 255], [0, 0, 0, 255], [63, 127, 127, 255]];
+        assert_eq!(pixels, expected.concat());
+    }
+
+    #[test]
+    fn test_decode_float_gray_normalizes_to_full_range() {
+        // HDR-ish samples from -2.0 to 6.0
+        let samples: Vec<f32> = (0..16).map(|i| -2.0 + i as f32 * 8.0 / 15.0).collect();
+        let mut data = Cursor::new(Vec::new());
+        TiffEncoder::new(&mut data)
+            .unwrap()
+            .write_image::<colortype::Gray32Float>(4, 4, &samples)
+            .unwrap();
+        let data = data.into_inner();
+
+        let (pixels, width, height) = decode_tiff(&data).unwrap();
+        assert_eq!((width, height), (4, 4));
+        let gray: Vec<u8> = pixels.chunks(4).map(|px| px[0]).collect();
+        assert_eq!((gray[0], gray[15]), (0, 255));
+        assert!(gray.windows(2).all(|w| w[0] <= w[1]));
+
+        // Fixed range: values above 2.0 clip to white
+        let (fixed, _, _) = decode_tiff_normalized(&data, Some((0.0, 2.0))).unwrap();
+        assert_eq!(fixed[0], 0);
+        assert_eq!(fixed[15 * 4], 255);
+    }
+}
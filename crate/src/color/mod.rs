@@ -0,0 +1,82 @@
+//! Pixel layout conversions and color math shared by codecs and filters.
+
+pub mod srgb;
+pub mod transfer;
+
+/// Expand packed RGB to RGBA, fully opaque. A trailing partial pixel is dropped.
+pub fn rgb_to_rgba(data: &[u8]) -> Vec<u8> {
+    data.chunks_exact(3).flat_map(|rgb| [rgb[0], rgb[1], rgb[2], 255]).collect()
+}
+
+/// Drop the alpha channel of RGBA pixels without compositing: callers flatten
+/// first when alpha matters. A trailing partial pixel is dropped.
+pub fn rgba_to_rgb(data: &[u8]) -> Vec<u8> {
+    data.chunks_exact(4).flat_map(|rgba| [rgba[0], rgba[1], rgba[2]]).collect()
+}
+
+/// Convert CMYK ink amounts (0 = no ink, 255 = full ink) to RGB with the
+/// naive device formula: each ink subtracts its complement, then black scales
+/// what's left. No press profile is applied, so rich blacks and saturated
+/// inks come out brighter than on paper.
+pub fn cmyk_to_rgb(c: u8, m: u8, y: u8, k: u8) -> [u8; 3] {
+    let white = 255 - k as u32;
+    let channel = |ink: u8| (((255 - ink as u32) * white + 127) / 255) as u8;
+    [channel(c), channel(m), channel(y)]
+}
+
+/// Profile-based CMYK -> RGB conversion for `cmyk_to_rgba`, taking ink
+/// amounts like `cmyk_to_rgb`. Lets an ICC transform replace the naive math.
+pub type CmykTransform<'a> = &'a dyn Fn([u8; 4]) -> [u8; 3];
+
+/// Convert packed CMYK pixels to opaque RGBA. `inverted` samples store 255 as
+/// no ink (Adobe's convention) and are flipped first. `transform` converts
+/// each pixel when set, otherwise `cmyk_to_rgb` does. A trailing partial
+/// pixel is dropped.
+pub fn cmyk_to_rgba(data: &[u8], inverted: bool, transform: Option<CmykTransform>) -> Vec<u8> {
+    let ink = |v: u8| if inverted { 255 - v } else { v };
+    data.chunks_exact(4)
+        .flat_map(|px| {
+            let cmyk = [ink(px[0]), ink(px[1]), ink(px[2]), ink(px[3])];
+            let [r, g, b] = match transform {
+                Some(transform) => transform(cmyk),
+                None => cmyk_to_rgb(cmyk[0], cmyk[1], cmyk[2], cmyk[3]),
+            };
+            [r, g, b, 255]
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rgb_rgba_round_trip() {
+        let rgb = [10, 20, 30, 40, 50, 60];
+        let rgba = rgb_to_rgba(&rgb);
+        assert_eq!(rgba, [10, 20, 30, 255, 40, 50, 60, 255]);
+        assert_eq!(rgba_to_rgb(&rgba), rgb);
+        // Alpha is discarded, not composited
+        assert_eq!(rgba_to_rgb(&[200, 100, 0, 0, 1, 2, 3]), [200, 100, 0]);
+        assert_eq!(rgb_to_rgba(&[1, 2]), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn test_cmyk_to_rgb() {
+        assert_eq!(cmyk_to_rgb(255, 0, 0, 0), [0, 255, 255]); // Cyan
+        assert_eq!(cmyk_to_rgb(0, 255, 0, 0), [255, 0, 255]); // Magenta
+        assert_eq!(cmyk_to_rgb(0, 0, 255, 0), [255, 255, 0]); // Yellow
+        assert_eq!(cmyk_to_rgb(0, 0, 0, 255), [0, 0, 0]); // Black
+        assert_eq!(cmyk_to_rgb(0, 0, 0, 0), [255, 255, 255]); // Paper
+        // Half cyan over half black: (255 - 128) * 127 / 255, rounded
+        assert_eq!(cmyk_to_rgb(128, 0, 0, 128), [63, 127, 127]);
+
+        // Batch: Adobe-inverted samples match plain ink amounts
+        let plain = cmyk_to_rgba(&[128, 0, 0, 128, 0, 0, 0, 255], false, None);
+        assert_eq!(plain, [63, 127, 127, 255, 0, 0, 0, 255]);
+        assert_eq!(cmyk_to_rgba(&[127, 255, 255, 127, 255, 255, 255, 0], true, None), plain);
+        // A profile transform replaces the naive formula
+        let profile = |cmyk: [u8; 4]| [cmyk[0], cmyk[3], 7];
+        assert_eq!(cmyk_to_rgba(&[1, 2, 3, 4], false, Some(&profile)), [1, 4, 7, 255]);
+    }
+}
@@ -0,0 +1,75 @@
+//! sRGB transfer function lookups shared by the linear-light filters.
+
+use std::sync::OnceLock;
+
+/// Entries in the linear -> sRGB table. Fine enough that every 8-bit value
+/// survives sRGB -> linear -> sRGB unchanged.
+const LINEAR_STEPS: usize = 4096;
+
+fn to_linear_table() -> &'static [f32; 256] {
+    static TABLE: OnceLock<[f32; 256]> = OnceLock::new();
+    TABLE.get_or_init(|| std::array::from_fn(|v| decode(v as f32 / 255.0)))
+}
+
+fn to_srgb_table() -> &'static [u8; LINEAR_STEPS] {
+    static TABLE: OnceLock<[u8; LINEAR_STEPS]> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        std::array::from_fn(|i| (encode(i as f32 / (LINEAR_STEPS - 1) as f32) * 255.0).round() as u8)
+    })
+}
+
+/// Exact sRGB EOTF on 0-1.
+fn decode(v: f32) -> f32 {
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Exact inverse of `decode` on 0-1.
+fn encode(l: f32) -> f32 {
+    if l <= 0.0031308 {
+        l * 12.92
+    } else {
+        1.055 * l.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// sRGB-encoded 8-bit value to linear light (0-1).
+pub fn srgb_to_linear(v: u8) -> f32 {
+    to_linear_table()[v as usize]
+}
+
+/// Linear light (0-1, clamped) back to an sRGB-encoded 8-bit value.
+pub fn linear_to_srgb(l: f32) -> u8 {
+    let index = (l.clamp(0.0, 1.0) * (LINEAR_STEPS - 1) as f32).round() as usize;
+    to_srgb_table()[index]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_endpoints_are_exact() {
+        assert_eq!(srgb_to_linear(0), 0.0);
+        assert_eq!(srgb_to_linear(255), 1.0);
+        assert_eq!(linear_to_srgb(0.0), 0);
+        assert_eq!(linear_to_srgb(1.0), 255);
+        assert_eq!((linear_to_srgb(-0.5), linear_to_srgb(1.5)), (0, 255));
+    }
+
+    #[test]
+    fn test_round_trip_and_table_accuracy() {
+        for v in 0..=255u8 {
+            assert_eq!(linear_to_srgb(srgb_to_linear(v)), v);
+        }
+        // Between table entries the result stays within one code of exact
+        for i in 0..=10_000 {
+            let l = i as f32 / 10_000.0;
+            let exact = encode(l) * 255.0;
+            assert!((linear_to_srgb(l) as f32 - exact).abs() <= 1.0, "{}: {} vs {}", l, linear_to_srgb(l), exact);
+        }
+    }
+}
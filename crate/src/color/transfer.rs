@@ -0,0 +1,40 @@
+//! Transfer functions between encoded 8-bit values and linear light, for
+//! sources that don't use the sRGB curve.
+
+use super::srgb::{linear_to_srgb, srgb_to_linear};
+
+/// How an image's 8-bit values map to linear light.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Transfer {
+    #[default]
+    Srgb,
+    /// Pure power law with this decoding exponent (e.g. 1.8 for classic Mac images)
+    Gamma(f32),
+}
+
+impl Transfer {
+    /// Transfer declared by PNG `sRGB`/`gAMA` chunks. `sRGB` wins, and a gAMA of
+    /// 1/2.2 (what sRGB-aware writers store) counts as sRGB too; no chunk means sRGB.
+    pub fn from_png(srgb: bool, file_gamma: Option<f32>) -> Self {
+        match file_gamma {
+            Some(gamma) if !srgb && gamma > 0.0 && (1.0 / gamma - 2.2).abs() > 0.01 => Transfer::Gamma(1.0 / gamma),
+            _ => Transfer::Srgb,
+        }
+    }
+
+    /// Encoded 8-bit value to linear light (0-1).
+    pub fn to_linear(self, v: u8) -> f32 {
+        match self {
+            Transfer::Srgb => srgb_to_linear(v),
+            Transfer::Gamma(exponent) => (v as f32 / 255.0).powf(exponent),
+        }
+    }
+
+    /// Linear light (0-1, clamped) back to an encoded 8-bit value.
+    pub fn from_linear(self, l: f32) -> u8 {
+        match self {
+            Transfer::Srgb => linear_to_srgb(l),
+            Transfer::Gamma(exponent) => (l.clamp(0.0, 1.0).powf(1.0 / exponent) * 255.0).round() as u8,
+        }
+    }
+}
@@ -1,6 +1,14 @@
+use crate::color::transfer::Transfer;
+
 /// Apply unsharp mask sharpening to an RGBA image.
 /// amount: 0.0 to 1.0 (0 = no sharpening, 1 = maximum)
-pub fn sharpen(data: &[u8], width: u32, height: u32, amount: f32) -> Vec<u8> {
+/// linear: sharpen in linear light (decoded with this transfer), which keeps
+/// bright-side halos at high-contrast edges from overshooting
+/// edge_aware: scale the amount per pixel by `edge_mask`, so detail is
+/// sharpened while flat areas (skies, backdrops) don't have their noise amplified
+/// Neighbors count in proportion to their alpha (the rest of their weight goes
+/// to the center), so the RGB under transparent pixels can't fringe an edge.
+pub fn sharpen(data: &[u8], width: u32, height: u32, amount: f32, linear: Option<Transfer>, edge_aware: bool) -> Vec<u8> {
     if amount <= 0.0 || width < 3 || height < 3 {
         return data.to_vec();
     }
@@ -17,17 +25,28 @@ pub fn sharpen(data: &[u8], width: u32, height: u32, amount: f32) -> Vec<u8> {
     // Blended with original based on amount
 
     let kernel_strength = amount.min(1.0);
+    // Samples are 0-255 either way; linear mode works on linear light scaled to 255
+    let sample = |idx: usize| linear.map_or(data[idx] as f32, |transfer| transfer.to_linear(data[idx]) * 255.0);
+    let mask = if edge_aware { edge_mask(data, width, height) } else { Vec::new() };
 
     for y in 1..(h - 1) {
         for x in 1..(w - 1) {
             let idx = (y * w + x) * 4;
+            let kernel_strength = if edge_aware { kernel_strength * mask[y * w + x] } else { kernel_strength };
+            if kernel_strength == 0.0 || data[idx + 3] == 0 {
+                continue;
+            }
 
             for c in 0..3 {  // RGB channels only, preserve alpha
-                let center = data[idx + c] as f32;
-                let top = data[((y - 1) * w + x) * 4 + c] as f32;
-                let bottom = data[((y + 1) * w + x) * 4 + c] as f32;
-                let left = data[(y * w + x - 1) * 4 + c] as f32;
-                let right = data[(y * w + x + 1) * 4 + c] as f32;
+                let center = sample(idx + c);
+                let neighbor = |pixel: usize| {
+                    let alpha = data[pixel * 4 + 3] as f32 / 255.0;
+                    sample(pixel * 4 + c) * alpha + center * (1.0 - alpha)
+                };
+                let top = neighbor((y - 1) * w + x);
+                let bottom = neighbor((y + 1) * w + x);
+                let left = neighbor(y * w + x - 1);
+                let right = neighbor(y * w + x + 1);
 
                 // Sharpen kernel: 5*center - neighbors
                 let sharpened = 5.0 * center - top - bottom - left - right;
@@ -36,7 +55,10 @@ pub fn sharpen(data: &[u8], width: u32, height: u32, amount: f32) -> Vec<u8> {
                 let blended = center + (sharpened - center) * kernel_strength;
 
                 // Clamp to valid range
-                result[idx + c] = blended.max(0.0).min(255.0) as u8;
+                result[idx + c] = match linear {
+                    Some(transfer) => transfer.from_linear(blended / 255.0),
+                    None => blended.clamp(0.0, 255.0) as u8,
+                };
             }
         }
     }
@@ -44,14 +66,61 @@ pub fn sharpen(data: &[u8], width: u32, height: u32, amount: f32) -> Vec<u8> {
     result
 }
 
+/// Sobel gradient magnitude (on luma) below which a pixel counts as flat.
+const EDGE_MASK_LOW: f32 = 24.0;
+/// Sobel gradient magnitude at and above which a pixel counts as a full edge.
+const EDGE_MASK_HIGH: f32 = 96.0;
+
+/// Per-pixel edge strength 0-1 from the Sobel gradient of luma, ramping
+/// linearly from EDGE_MASK_LOW to EDGE_MASK_HIGH. Border pixels are 0.
+pub fn edge_mask(data: &[u8], width: u32, height: u32) -> Vec<f32> {
+    let (w, h) = (width as usize, height as usize);
+    let luma: Vec<f32> = data
+        .chunks_exact(4)
+        .take(w * h)
+        .map(|px| 0.299 * px[0] as f32 + 0.587 * px[1] as f32 + 0.114 * px[2] as f32)
+        .collect();
+    let mut mask = vec![0.0; w * h];
+    for y in 1..h.saturating_sub(1) {
+        for x in 1..w.saturating_sub(1) {
+            let at = |dx: usize, dy: usize| luma[(y + dy - 1) * w + x + dx - 1];
+            let gx = at(2, 0) + 2.0 * at(2, 1) + at(2, 2) - at(0, 0) - 2.0 * at(0, 1) - at(0, 2);
+            let gy = at(0, 2) + 2.0 * at(1, 2) + at(2, 2) - at(0, 0) - 2.0 * at(1, 0) - at(2, 0);
+            let magnitude = (gx * gx + gy * gy).sqrt();
+            mask[y * w + x] = ((magnitude - EDGE_MASK_LOW) / (EDGE_MASK_HIGH - EDGE_MASK_LOW)).clamp(0.0, 1.0);
+        }
+    }
+    mask
+}
+
+/// Mean `edge_mask` strength, 0 (flat) to 1 (edges everywhere): how much
+/// detail an encoder has to preserve.
+pub fn complexity(data: &[u8], width: u32, height: u32) -> f32 {
+    let mask = edge_mask(data, width, height);
+    if mask.is_empty() {
+        return 0.0;
+    }
+    mask.iter().sum::<f32>() / mask.len() as f32
+}
+
 /// Detect the bounding box of non-background content.
 /// Returns (x, y, width, height) of the content area.
 /// threshold: 0-255, how different a pixel must be from the background to be considered content
+/// alpha_mode: compare alpha instead of color, so transparent or uniformly
+/// semi-transparent borders are trimmed regardless of their RGB values
+/// corner_sample: side of the NxN block sampled at each corner; the background
+/// is the per-channel median of all samples, so a stray corner pixel is ignored
+/// stride: scan every Nth pixel of every Nth row first, then grow that coarse
+/// box through full-resolution stride-wide bands around it. Exact unless some
+/// disconnected piece of content slips between samples; 1 scans every pixel.
 pub fn detect_content_bounds(
     data: &[u8],
     width: u32,
     height: u32,
     threshold: u8,
+    alpha_mode: bool,
+    corner_sample: u32,
+    stride: u32,
 ) -> Option<(u32, u32, u32, u32)> {
     if width == 0 || height == 0 {
         return None;
@@ -60,57 +129,65 @@ pub fn detect_content_bounds(
     let w = width as usize;
     let h = height as usize;
 
-    // Sample corner pixels to determine background color
-    // Use average of corners for more robust detection
-    let corners = [(0, 0), (w - 1, 0), (0, h - 1), (w - 1, h - 1)];
-
-    let mut bg_r = 0u32;
-    let mut bg_g = 0u32;
-    let mut bg_b = 0u32;
-
-    for (x, y) in corners.iter() {
-        let idx = (y * w + x) * 4;
-        bg_r += data[idx] as u32;
-        bg_g += data[idx + 1] as u32;
-        bg_b += data[idx + 2] as u32;
+    // Sample an NxN block at each corner to determine background color
+    let n = (corner_sample as usize).clamp(1, w.min(h));
+    let mut samples: [Vec<u8>; 4] = Default::default();
+    for (x0, y0) in [(0, 0), (w - n, 0), (0, h - n), (w - n, h - n)] {
+        for y in y0..y0 + n {
+            for x in x0..x0 + n {
+                let idx = (y * w + x) * 4;
+                for (c, channel) in samples.iter_mut().enumerate() {
+                    channel.push(data[idx + c]);
+                }
+            }
+        }
     }
-
-    let bg_r = (bg_r / 4) as u8;
-    let bg_g = (bg_g / 4) as u8;
-    let bg_b = (bg_b / 4) as u8;
+    let [bg_r, bg_g, bg_b, bg_a] = samples.map(|mut channel| {
+        channel.sort_unstable();
+        channel[channel.len() / 2]
+    });
 
     let is_background = |idx: usize| -> bool {
+        if alpha_mode {
+            return (data[idx + 3] as i16 - bg_a as i16).unsigned_abs() as u8 <= threshold;
+        }
         let dr = (data[idx] as i16 - bg_r as i16).unsigned_abs() as u8;
         let dg = (data[idx + 1] as i16 - bg_g as i16).unsigned_abs() as u8;
         let db = (data[idx + 2] as i16 - bg_b as i16).unsigned_abs() as u8;
         dr <= threshold && dg <= threshold && db <= threshold
     };
 
-    // Find bounds
-    let mut min_x = w;
-    let mut max_x = 0usize;
-    let mut min_y = h;
-    let mut max_y = 0usize;
-
-    for y in 0..h {
-        for x in 0..w {
-            let idx = (y * w + x) * 4;
-            if !is_background(idx) {
-                if x < min_x {
-                    min_x = x;
-                }
-                if x > max_x {
-                    max_x = x;
-                }
-                if y < min_y {
-                    min_y = y;
-                }
-                if y > max_y {
-                    max_y = y;
+    // Widen (min_x, max_x, min_y, max_y) to every content pixel in the ranges
+    let scan = |bounds: &mut (usize, usize, usize, usize), xs: std::ops::Range<usize>, ys: std::ops::Range<usize>, step: usize| {
+        for y in ys.step_by(step) {
+            for x in xs.clone().step_by(step) {
+                if !is_background((y * w + x) * 4) {
+                    *bounds = (bounds.0.min(x), bounds.1.max(x), bounds.2.min(y), bounds.3.max(y));
                 }
             }
         }
+    };
+
+    // Find bounds
+    let step = stride.max(1) as usize;
+    let mut bounds = (w, 0, h, 0);
+    scan(&mut bounds, 0..w, 0..h, step);
+    while step > 1 && bounds.0 <= bounds.1 {
+        // Grow the box until a full-resolution ring around it holds no content,
+        // so anything connected to the coarse hits ends up inside
+        let before = bounds;
+        let (min_x, max_x, min_y, max_y) = bounds;
+        let (x0, x1) = (min_x.saturating_sub(step), (max_x + step + 1).min(w));
+        let (y0, y1) = (min_y.saturating_sub(step), (max_y + step + 1).min(h));
+        scan(&mut bounds, x0..min_x, y0..y1, 1);
+        scan(&mut bounds, max_x + 1..x1, y0..y1, 1);
+        scan(&mut bounds, x0..x1, y0..min_y, 1);
+        scan(&mut bounds, x0..x1, max_y + 1..y1, 1);
+        if bounds == before {
+            break;
+        }
     }
+    let (min_x, max_x, min_y, max_y) = bounds;
 
     // Check if we found any content
     if min_x > max_x || min_y > max_y {
@@ -130,16 +207,33 @@ pub fn detect_content_bounds(
     Some((crop_x, crop_y, crop_w, crop_h))
 }
 
-/// Auto-trim whitespace from image borders.
-/// Returns trimmed image data and new dimensions, or original if no trimming needed.
-pub fn auto_trim(data: &[u8], width: u32, height: u32, threshold: u8) -> (Vec<u8>, u32, u32) {
-    match detect_content_bounds(data, width, height, threshold) {
-        Some((x, y, w, h)) => {
-            let trimmed = crate::resize::crop_image(data, width, height, x, y, w, h);
-            (trimmed, w, h)
-        }
-        None => (data.to_vec(), width, height),
+/// Content bounds from alpha coverage: a row or column is content when its
+/// summed alpha exceeds `fraction` (0-1) of the largest row/column sum.
+/// Faint drop shadows contribute little coverage and fall outside the bounds,
+/// while the object itself is kept whole.
+pub fn alpha_coverage_bounds(data: &[u8], width: u32, height: u32, fraction: f32) -> Option<(u32, u32, u32, u32)> {
+    let (w, h) = (width as usize, height as usize);
+    let mut rows = vec![0u64; h];
+    let mut cols = vec![0u64; w];
+    for (i, px) in data.chunks_exact(4).take(w * h).enumerate() {
+        rows[i / w] += px[3] as u64;
+        cols[i % w] += px[3] as u64;
     }
+
+    let span = |sums: &[u64]| -> Option<(usize, usize)> {
+        let cutoff = *sums.iter().max()? as f64 * fraction.clamp(0.0, 1.0) as f64;
+        let first = sums.iter().position(|&s| s > 0 && s as f64 >= cutoff)?;
+        let last = sums.iter().rposition(|&s| s > 0 && s as f64 >= cutoff)?;
+        Some((first, last))
+    };
+    let (min_y, max_y) = span(&rows)?;
+    let (min_x, max_x) = span(&cols)?;
+
+    let bounds = (min_x as u32, min_y as u32, (max_x - min_x + 1) as u32, (max_y - min_y + 1) as u32);
+    if bounds == (0, 0, width, height) {
+        return None;
+    }
+    Some(bounds)
 }
 
 /// Apply box blur to an RGBA image.
@@ -219,3 +313,683 @@ pub fn blur(data: &[u8], width: u32, height: u32, radius: u32) -> Vec<u8> {
 
     result
 }
+
+/// Smallest Gaussian sigma `blur_region` applies: lighter blurs of text or
+/// faces can be partly undone by deconvolution.
+pub const REDACT_MIN_SIGMA: f32 = 8.0;
+
+/// Obscure the rect (x, y, width, height), clipped to the image, with a
+/// Gaussian of at least REDACT_MIN_SIGMA. The rect is blurred on its own with
+/// clamped edges, so its content doesn't smear outward and nothing bleeds in.
+pub fn blur_region(data: &[u8], width: u32, height: u32, rect: (u32, u32, u32, u32), sigma: f32) -> Vec<u8> {
+    let mut result = data.to_vec();
+    let (x, y) = (rect.0.min(width), rect.1.min(height));
+    let (rw, rh) = (rect.2.min(width - x), rect.3.min(height - y));
+    if rw == 0 || rh == 0 {
+        return result;
+    }
+
+    let region = crate::resize::crop_image(data, width, height, x, y, rw, rh);
+    let blurred = gaussian_blur(&region, rw, rh, sigma.max(REDACT_MIN_SIGMA));
+    let row_bytes = rw as usize * 4;
+    for (row, pixels) in blurred.chunks_exact(row_bytes).enumerate() {
+        let start = ((y as usize + row) * width as usize + x as usize) * 4;
+        result[start..start + row_bytes].copy_from_slice(pixels);
+    }
+    result
+}
+
+/// Apply a separable Gaussian blur to an RGBA image.
+/// sigma: standard deviation in pixels; the kernel spans 3 sigma each side.
+/// Edges are clamped.
+pub fn gaussian_blur(data: &[u8], width: u32, height: u32, sigma: f32) -> Vec<u8> {
+    if sigma <= 0.0 || width == 0 || height == 0 {
+        return data.to_vec();
+    }
+
+    let w = width as usize;
+    let h = height as usize;
+    let radius = (sigma * 3.0).ceil().min(100.0) as i32;
+    let kernel: Vec<f32> = (-radius..=radius)
+        .map(|d| (-(d * d) as f32 / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let norm: f32 = kernel.iter().sum();
+
+    let mut temp = vec![0f32; w * h * 4];
+    let mut result = vec![0u8; w * h * 4];
+
+    // Horizontal pass
+    for y in 0..h {
+        for x in 0..w {
+            let mut sum = [0f32; 4];
+            for (k, weight) in kernel.iter().enumerate() {
+                let nx = (x as i32 + k as i32 - radius).clamp(0, w as i32 - 1) as usize;
+                let idx = (y * w + nx) * 4;
+                for c in 0..4 {
+                    sum[c] += data[idx + c] as f32 * weight;
+                }
+            }
+            let idx = (y * w + x) * 4;
+            for c in 0..4 {
+                temp[idx + c] = sum[c] / norm;
+            }
+        }
+    }
+
+    // Vertical pass
+    for y in 0..h {
+        for x in 0..w {
+            let mut sum = [0f32; 4];
+            for (k, weight) in kernel.iter().enumerate() {
+                let ny = (y as i32 + k as i32 - radius).clamp(0, h as i32 - 1) as usize;
+                let idx = (ny * w + x) * 4;
+                for c in 0..4 {
+                    sum[c] += temp[idx + c] * weight;
+                }
+            }
+            let idx = (y * w + x) * 4;
+            for c in 0..4 {
+                result[idx + c] = (sum[c] / norm).round().clamp(0.0, 255.0) as u8;
+            }
+        }
+    }
+
+    result
+}
+
+/// Side of the luma thumbnail the perceptual hash is computed on.
+const PHASH_SIZE: usize = 32;
+
+/// 64-bit perceptual hash (pHash): luma is area-averaged down to 32x32, the
+/// lowest 8x8 DCT frequencies are kept, and each bit records whether a
+/// coefficient is above their median (DC excluded). Recompression and
+/// resizing barely change it; compare hashes with `hamming_distance`.
+pub fn phash(data: &[u8], width: u32, height: u32) -> u64 {
+    let (w, h) = (width as usize, height as usize);
+    if w == 0 || h == 0 {
+        return 0;
+    }
+
+    let mut thumb = [[0f64; PHASH_SIZE]; PHASH_SIZE];
+    for (ty, row) in thumb.iter_mut().enumerate() {
+        let (y0, y1) = (ty * h / PHASH_SIZE, ((ty + 1) * h / PHASH_SIZE).max(ty * h / PHASH_SIZE + 1));
+        for (tx, cell) in row.iter_mut().enumerate() {
+            let (x0, x1) = (tx * w / PHASH_SIZE, ((tx + 1) * w / PHASH_SIZE).max(tx * w / PHASH_SIZE + 1));
+            let mut sum = 0.0;
+            for y in y0..y1 {
+                for x in x0..x1 {
+                    let idx = (y * w + x) * 4;
+                    sum += 0.299 * data[idx] as f64 + 0.587 * data[idx + 1] as f64 + 0.114 * data[idx + 2] as f64;
+                }
+            }
+            *cell = sum / ((y1 - y0) * (x1 - x0)) as f64;
+        }
+    }
+
+    // Separable DCT-II, only the 8 lowest frequencies per axis
+    let basis: Vec<[f64; PHASH_SIZE]> = (0..8)
+        .map(|u| std::array::from_fn(|x| ((2 * x + 1) as f64 * u as f64 * std::f64::consts::PI / (2 * PHASH_SIZE) as f64).cos()))
+        .collect();
+    let rows: Vec<[f64; 8]> = thumb
+        .iter()
+        .map(|row| std::array::from_fn(|u| row.iter().zip(&basis[u]).map(|(p, b)| p * b).sum()))
+        .collect();
+    let coefficients: Vec<f64> = (0..8)
+        .flat_map(|v| {
+            let (rows, basis) = (&rows, &basis);
+            (0..8).map(move |u| rows.iter().zip(&basis[v]).map(|(row, b)| row[u] * b).sum())
+        })
+        .collect();
+
+    let mut ac = coefficients[1..].to_vec();
+    ac.sort_by(|a, b| a.total_cmp(b));
+    let median = ac[ac.len() / 2];
+    coefficients
+        .iter()
+        .enumerate()
+        .filter(|&(_, &c)| c > median)
+        .fold(0u64, |hash, (bit, _)| hash | 1 << bit)
+}
+
+/// Number of differing bits between two hashes; for `phash`, roughly 0-10
+/// means the same picture, 25+ unrelated ones.
+pub fn hamming_distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+/// Mean structural similarity (SSIM) of two same-sized RGBA images, computed on
+/// luma over non-overlapping 8x8 windows. 1.0 means identical.
+pub fn ssim(a: &[u8], b: &[u8], width: u32, height: u32) -> f64 {
+    const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+    const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+    const WINDOW: usize = 8;
+
+    let w = width as usize;
+    let h = height as usize;
+    let luma = |data: &[u8], idx: usize| {
+        0.299 * data[idx] as f64 + 0.587 * data[idx + 1] as f64 + 0.114 * data[idx + 2] as f64
+    };
+
+    let mut total = 0.0;
+    let mut windows = 0usize;
+
+    for wy in (0..h).step_by(WINDOW) {
+        for wx in (0..w).step_by(WINDOW) {
+            let (mut sum_a, mut sum_b, mut sum_aa, mut sum_bb, mut sum_ab) = (0.0, 0.0, 0.0, 0.0, 0.0);
+            let mut n = 0.0;
+            for y in wy..(wy + WINDOW).min(h) {
+                for x in wx..(wx + WINDOW).min(w) {
+                    let idx = (y * w + x) * 4;
+                    let (la, lb) = (luma(a, idx), luma(b, idx));
+                    sum_a += la;
+                    sum_b += lb;
+                    sum_aa += la * la;
+                    sum_bb += lb * lb;
+                    sum_ab += la * lb;
+                    n += 1.0;
+                }
+            }
+            let (mean_a, mean_b) = (sum_a / n, sum_b / n);
+            let var_a = sum_aa / n - mean_a * mean_a;
+            let var_b = sum_bb / n - mean_b * mean_b;
+            let cov = sum_ab / n - mean_a * mean_b;
+            total += ((2.0 * mean_a * mean_b + C1) * (2.0 * cov + C2))
+                / ((mean_a * mean_a + mean_b * mean_b + C1) * (var_a + var_b + C2));
+            windows += 1;
+        }
+    }
+
+    if windows == 0 {
+        1.0
+    } else {
+        total / windows as f64
+    }
+}
+
+/// Bleed opaque colors under fully transparent pixels, so texture sampling and
+/// resampling don't pull in their (often black) RGB.
+/// Each iteration fills transparent pixels that touch a filled pixel (8-neighborhood)
+/// with the average RGB of those neighbors. Alpha is left untouched.
+pub fn alpha_bleed(data: &[u8], width: u32, height: u32, iterations: u32) -> Vec<u8> {
+    let w = width as usize;
+    let h = height as usize;
+    let mut result = data.to_vec();
+    // Pixels whose RGB is meaningful: opaque-ish input, then bled pixels
+    let mut filled: Vec<bool> = data.chunks_exact(4).take(w * h).map(|px| px[3] > 0).collect();
+
+    for _ in 0..iterations {
+        let mut updates = Vec::new();
+        for y in 0..h {
+            for x in 0..w {
+                if filled[y * w + x] {
+                    continue;
+                }
+                let mut sum = [0u32; 3];
+                let mut count = 0u32;
+                for ny in y.saturating_sub(1)..(y + 2).min(h) {
+                    for nx in x.saturating_sub(1)..(x + 2).min(w) {
+                        if filled[ny * w + nx] {
+                            let idx = (ny * w + nx) * 4;
+                            for c in 0..3 {
+                                sum[c] += result[idx + c] as u32;
+                            }
+                            count += 1;
+                        }
+                    }
+                }
+                if count > 0 {
+                    updates.push((y * w + x, sum.map(|s| ((s + count / 2) / count) as u8)));
+                }
+            }
+        }
+        if updates.is_empty() {
+            break;
+        }
+        for (i, rgb) in updates {
+            result[i * 4..i * 4 + 3].copy_from_slice(&rgb);
+            filled[i] = true;
+        }
+    }
+
+    result
+}
+
+/// Histogram-equalize luminance while keeping each pixel's chroma.
+/// Luma (BT.601) is remapped through the CDF of its histogram; the Cb/Cr
+/// color differences are preserved, so hue and saturation don't shift the way
+/// per-channel equalization would. Fully transparent pixels are ignored.
+pub fn equalize(data: &[u8], width: u32, height: u32) -> Vec<u8> {
+    let pixel_count = (width * height) as usize;
+    let luma = |px: &[u8]| 0.299 * px[0] as f32 + 0.587 * px[1] as f32 + 0.114 * px[2] as f32;
+
+    let mut histogram = [0u32; 256];
+    for px in data.chunks_exact(4).take(pixel_count).filter(|px| px[3] > 0) {
+        histogram[luma(px).round() as usize] += 1;
+    }
+
+    // CDF mapping, anchored so the darkest occupied level maps to 0
+    let total: u32 = histogram.iter().sum();
+    let cdf_min = histogram.iter().copied().find(|&c| c > 0).unwrap_or(0);
+    if total <= cdf_min {
+        return data.to_vec(); // Empty or single-level: nothing to spread
+    }
+    let mut mapping = [0f32; 256];
+    let mut cumulative = 0u32;
+    for (level, &count) in histogram.iter().enumerate() {
+        cumulative += count;
+        mapping[level] = (cumulative.saturating_sub(cdf_min)) as f32 / (total - cdf_min) as f32 * 255.0;
+    }
+
+    let mut result = data.to_vec();
+    for px in result.chunks_exact_mut(4).take(pixel_count).filter(|px| px[3] > 0) {
+        // Shifting R, G and B by the same amount moves luma by exactly that
+        // amount (the weights sum to 1) and leaves Cb/Cr unchanged
+        let y = luma(px);
+        let delta = mapping[y.round() as usize] - y;
+        for c in px.iter_mut().take(3) {
+            *c = (*c as f32 + delta).round().clamp(0.0, 255.0) as u8;
+        }
+    }
+
+    result
+}
+
+/// Composite an RGBA image over an opaque background color.
+/// Output alpha is always 255.
+/// linear: blend in linear light (decoded with this transfer) instead of the
+/// encoded values, so anti-aliased edges don't come out darker than they should
+pub fn flatten(data: &[u8], width: u32, height: u32, bg: [u8; 3], linear: Option<Transfer>) -> Vec<u8> {
+    let mut result = data.to_vec();
+    let pixel_count = (width * height) as usize;
+
+    for px in result.chunks_exact_mut(4).take(pixel_count) {
+        let alpha = px[3] as u32;
+        for c in 0..3 {
+            px[c] = match linear {
+                Some(transfer) if alpha != 0 && alpha != 255 => {
+                    let a = alpha as f32 / 255.0;
+                    transfer.from_linear(transfer.to_linear(px[c]) * a + transfer.to_linear(bg[c]) * (1.0 - a))
+                }
+                _ => ((px[c] as u32 * alpha + bg[c] as u32 * (255 - alpha) + 127) / 255) as u8,
+            };
+        }
+        px[3] = 255;
+    }
+
+    result
+}
+
+/// Inverse of `flatten`: recover alpha and foreground color from an image
+/// that was composited over the known `matte` color, assuming
+/// `observed = fg * a + matte * (1 - a)`. Each pixel gets the smallest alpha
+/// that keeps its foreground in range, so colors equal to the matte become
+/// fully transparent. Existing alpha is multiplied in.
+pub fn unmatte(data: &[u8], width: u32, height: u32, matte: [u8; 3]) -> Vec<u8> {
+    let mut result = data.to_vec();
+    let pixel_count = (width * height) as usize;
+
+    for px in result.chunks_exact_mut(4).take(pixel_count) {
+        let alpha = (0..3)
+            .map(|c| {
+                let (observed, bg) = (px[c] as f32, matte[c] as f32);
+                if observed > bg {
+                    (observed - bg) / (255.0 - bg)
+                } else if observed < bg {
+                    (bg - observed) / bg
+                } else {
+                    0.0
+                }
+            })
+            .fold(0.0f32, f32::max);
+
+        if alpha <= 0.0 {
+            px.copy_from_slice(&[0, 0, 0, 0]);
+            continue;
+        }
+        for c in 0..3 {
+            let bg = matte[c] as f32;
+            px[c] = (bg + (px[c] as f32 - bg) / alpha).round().clamp(0.0, 255.0) as u8;
+        }
+        px[3] = (alpha * px[3] as f32).round() as u8;
+    }
+
+    result
+}
+
+/// Snap every alpha value to 0 or 255 for 1-bit alpha output.
+/// Alpha below `cutoff` becomes fully transparent, everything else fully opaque.
+pub fn threshold_alpha(data: &[u8], width: u32, height: u32, cutoff: u8) -> Vec<u8> {
+    let mut result = data.to_vec();
+    let pixel_count = (width * height) as usize;
+
+    for px in result.chunks_exact_mut(4).take(pixel_count) {
+        px[3] = if px[3] < cutoff { 0 } else { 255 };
+    }
+
+    result
+}
+
+/// Alpha channel as an opaque grayscale RGBA image (alpha in R, G and B).
+pub fn extract_alpha(data: &[u8], width: u32, height: u32) -> Vec<u8> {
+    let pixel_count = (width * height) as usize;
+    data.chunks_exact(4)
+        .take(pixel_count)
+        .flat_map(|px| [px[3], px[3], px[3], 255])
+        .collect()
+}
+
+/// Replace the alpha channel of `rgb_data` with the luma (BT.601) of
+/// `mask_data`, an RGBA image of the same size. The mask's own alpha is ignored.
+pub fn apply_alpha_mask(rgb_data: &[u8], mask_data: &[u8], width: u32, height: u32) -> Vec<u8> {
+    let mut result = rgb_data.to_vec();
+    let pixel_count = (width * height) as usize;
+
+    for (px, mask) in result.chunks_exact_mut(4).zip(mask_data.chunks_exact(4)).take(pixel_count) {
+        // Integer weights sum to 1000, so a gray mask maps back to its exact value
+        px[3] = ((299 * mask[0] as u32 + 587 * mask[1] as u32 + 114 * mask[2] as u32 + 500) / 1000) as u8;
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unmatte_recovers_flattened_alpha() {
+        // Each color has a channel at 0, so over white its alpha is recoverable
+        let colors = [[0, 100, 200], [255, 0, 0], [30, 0, 90], [0, 0, 0]];
+        let alphas = [255, 200, 128, 64, 10, 0];
+        let data: Vec<u8> = colors
+            .iter()
+            .flat_map(|&[r, g, b]| alphas.iter().map(move |&a| [r, g, b, a]))
+            .flatten()
+            .collect();
+        let (w, h) = (alphas.len() as u32, colors.len() as u32);
+
+        let flat = flatten(&data, w, h, [255, 255, 255], None);
+        let recovered = unmatte(&flat, w, h, [255, 255, 255]);
+        for (orig, got) in data.chunks_exact(4).zip(recovered.chunks_exact(4)) {
+            assert!(got[3].abs_diff(orig[3]) <= 1, "{:?} -> {:?}", orig, got);
+            // Color error grows as alpha shrinks (less of it survived the flatten)
+            if orig[3] >= 64 {
+                for c in 0..3 {
+                    assert!(got[c].abs_diff(orig[c]) <= 4, "{:?} -> {:?}", orig, got);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_extract_then_apply_alpha_round_trips() {
+        let data: Vec<u8> = (0..=255u8).flat_map(|a| [a / 2, 255 - a, 40, a]).collect();
+        let mask = extract_alpha(&data, 16, 16);
+        assert_eq!(&mask[..8], [0, 0, 0, 255, 1, 1, 1, 255]);
+
+        let mut opaque = data.clone();
+        opaque.chunks_exact_mut(4).for_each(|px| px[3] = 255);
+        assert_eq!(apply_alpha_mask(&opaque, &mask, 16, 16), data);
+
+        // Colored masks contribute their luma: pure green is 587/1000 of 255
+        let green = [0, 255, 0, 255].repeat(256);
+        assert!(apply_alpha_mask(&opaque, &green, 16, 16).chunks_exact(4).all(|px| px[3] == 150));
+    }
+
+    #[test]
+    fn test_threshold_alpha_snaps_and_keeps_rgb() {
+        let data = vec![
+            10, 20, 30, 0, //
+            40, 50, 60, 127, //
+            70, 80, 90, 128, //
+            100, 110, 120, 255,
+        ];
+        let result = threshold_alpha(&data, 2, 2, 128);
+        assert_eq!(
+            result,
+            vec![10, 20, 30, 0, 40, 50, 60, 0, 70, 80, 90, 255, 100, 110, 120, 255]
+        );
+    }
+
+    #[test]
+    fn test_alpha_bleed_spreads_one_ring_per_iteration() {
+        // 5x5 transparent black with one opaque orange pixel in the middle
+        let mut data = vec![0u8; 5 * 5 * 4];
+        data[12 * 4..12 * 4 + 4].copy_from_slice(&[250, 120, 10, 255]);
+
+        let bled = alpha_bleed(&data, 5, 5, 1);
+        for (i, px) in bled.chunks(4).enumerate() {
+            let (x, y) = (i % 5, i / 5);
+            let neighbor = x.abs_diff(2) <= 1 && y.abs_diff(2) <= 1;
+            let expected_rgb: &[u8] = if neighbor { &[250, 120, 10] } else { &[0, 0, 0] };
+            assert_eq!(&px[..3], expected_rgb, "({}, {})", x, y);
+            assert_eq!(px[3], if i == 12 { 255 } else { 0 });
+        }
+    }
+
+    #[test]
+    fn test_equalize_spreads_narrow_histogram() {
+        // Low-contrast gray ramp: luma 100..=140
+        let data: Vec<u8> = (0..41u8 * 4).flat_map(|i| [100 + i / 4, 100 + i / 4, 100 + i / 4, 255]).collect();
+        let out = equalize(&data, 41, 4);
+
+        let levels: Vec<u8> = out.chunks(4).map(|px| px[0]).collect();
+        let (min, max) = (*levels.iter().min().unwrap(), *levels.iter().max().unwrap());
+        assert!(min <= 10 && max >= 245, "range {}..{}", min, max);
+        // Flatter: the CDF tracks the identity line far more closely
+        let cdf_error = |values: &[u8]| {
+            (0..=255u32)
+                .map(|v| {
+                    let below = values.iter().filter(|&&x| x as u32 <= v).count() as f64 / values.len() as f64;
+                    (below - v as f64 / 255.0).abs()
+                })
+                .fold(0.0, f64::max)
+        };
+        let before: Vec<u8> = data.chunks(4).map(|px| px[0]).collect();
+        assert!(cdf_error(&levels) < cdf_error(&before) / 4.0);
+        // Gray stays gray
+        assert!(out.chunks(4).all(|px| px[0] == px[1] && px[1] == px[2]));
+    }
+
+    #[test]
+    fn test_linear_sharpen_overshoots_less() {
+        // 6x3 vertical edge: sRGB 64 on the left, 192 on the right
+        let data: Vec<u8> = (0..3)
+            .flat_map(|_| (0..6).flat_map(|x| if x < 3 { [64, 64, 64, 255] } else { [192, 192, 192, 255] }))
+            .collect();
+        let bright_side = |px: &[u8]| px[(6 + 3) * 4];
+        let gamma = sharpen(&data, 6, 3, 0.5, None, false);
+        let linear = sharpen(&data, 6, 3, 0.5, Some(Transfer::Srgb), false);
+        assert_eq!(bright_side(&gamma), 255);
+        assert!((200..250).contains(&bright_side(&linear)), "{}", bright_side(&linear));
+        // Flat areas are untouched by the round trip
+        assert_eq!(linear[(6 + 4) * 4], 192);
+    }
+
+    #[test]
+    fn test_edge_aware_sharpen_spares_flat_background() {
+        // 32x32 grainy mid-gray backdrop (+-2) with a bright 12x12 subject in the middle
+        let mut seed = 7u32;
+        let data: Vec<u8> = (0..32 * 32)
+            .flat_map(|i| {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                let (x, y) = (i % 32, i / 32);
+                let v = if (10..22).contains(&x) && (10..22).contains(&y) { 220 } else { 126 + (seed >> 29) as u8 % 5 };
+                [v, v, v, 255]
+            })
+            .collect();
+        // Spread of the backdrop around its mean, away from the subject
+        let grain = |px: &[u8]| -> f32 {
+            let values: Vec<f32> = (2..8).flat_map(|y| (2..30).map(move |x| px[(y * 32 + x) * 4] as f32)).collect();
+            let mean = values.iter().sum::<f32>() / values.len() as f32;
+            values.iter().map(|v| (v - mean).abs()).sum::<f32>() / values.len() as f32
+        };
+
+        let plain = sharpen(&data, 32, 32, 1.0, None, false);
+        let aware = sharpen(&data, 32, 32, 1.0, None, true);
+        assert!(grain(&plain) > 2.0 * grain(&data));
+        assert!(grain(&aware) < 1.2 * grain(&data), "{} vs {}", grain(&aware), grain(&data));
+        // Both still push the subject's edge apart
+        let (inside, outside) = ((16 * 32 + 10) * 4, (16 * 32 + 9) * 4);
+        assert!(aware[inside] > 240 && aware[outside] < 60, "{} {}", aware[inside], aware[outside]);
+    }
+
+    #[test]
+    fn test_sharpen_ignores_rgb_under_transparency() {
+        // Opaque gray on the left, transparent magenta garbage on the right
+        let (w, h) = (6u32, 3u32);
+        let data: Vec<u8> = (0..w * h).flat_map(|i| if i % w < 3 { [128, 128, 128, 255] } else { [255, 0, 255, 0] }).collect();
+        for linear in [None, Some(Transfer::Srgb)] {
+            let sharpened = sharpen(&data, w, h, 1.0, linear, false);
+            // The opaque edge pixel next to the transparent ones keeps its color
+            let edge = ((w + 2) * 4) as usize;
+            assert_eq!(sharpened[edge..edge + 4], [128, 128, 128, 255], "{:?}", linear);
+            assert_eq!(sharpened, data);
+        }
+    }
+
+    #[test]
+    fn test_linear_flatten_lightens_half_alpha_edge() {
+        // 50%-alpha white over black, plus fully opaque and transparent pixels
+        let data = [255, 255, 255, 128, 10, 20, 30, 255, 99, 99, 99, 0];
+        let srgb = flatten(&data, 3, 1, [0, 0, 0], None);
+        let linear = flatten(&data, 3, 1, [0, 0, 0], Some(Transfer::Srgb));
+        assert_eq!(srgb[0], 128);
+        // Half the light of white is ~188 in sRGB, not 128
+        assert!((186..=190).contains(&linear[0]), "{}", linear[0]);
+        assert_eq!(&linear[4..], &srgb[4..]);
+    }
+
+    #[test]
+    fn test_alpha_trim_removes_uniform_semi_transparent_border() {
+        // 6x6 with a 2px 50%-alpha border (noisy RGB) around an opaque 2x2 center
+        let mut data = Vec::new();
+        for y in 0..6u8 {
+            for x in 0..6u8 {
+                if (2..4).contains(&x) && (2..4).contains(&y) {
+                    data.extend_from_slice(&[255, 255, 255, 255]);
+                } else {
+                    data.extend_from_slice(&[x * 40, y * 40, 90, 128]);
+                }
+            }
+        }
+        assert_eq!(detect_content_bounds(&data, 6, 6, 10, true, 1, 1), Some((2, 2, 2, 2)));
+        // Color mode sees the noisy border as content
+        assert_ne!(detect_content_bounds(&data, 6, 6, 10, false, 1, 1), Some((2, 2, 2, 2)));
+    }
+
+    #[test]
+    fn test_corner_block_median_ignores_stray_corner_pixels() {
+        // Noisy light background (240-255, like JPEG grain) around a black 4x4 center,
+        // with darker specks on three corner pixels
+        let mut data = Vec::new();
+        for y in 0..12usize {
+            for x in 0..12usize {
+                let v = if (4..8).contains(&x) && (4..8).contains(&y) {
+                    0
+                } else if (x == 0 || x == 11) && (y == 0 || y == 11) && (x, y) != (11, 11) {
+                    238
+                } else {
+                    240 + ((x * 7 + y * 3) % 16) as u8
+                };
+                data.extend_from_slice(&[v, v, v, 255]);
+            }
+        }
+
+        // Single corner pixels: the background is taken from the specks and
+        // the lighter grain reads as content, so nothing gets trimmed
+        assert_eq!(detect_content_bounds(&data, 12, 12, 10, false, 1, 1), None);
+        // 3x3 blocks: the median lands inside the grain and the center is found
+        assert_eq!(detect_content_bounds(&data, 12, 12, 10, false, 3, 1), Some((4, 4, 4, 4)));
+    }
+
+    #[test]
+    fn test_alpha_coverage_trims_faint_shadow() {
+        // 40x40 transparent canvas: opaque 20x20 object at (10, 10) with a faint
+        // (alpha 20) shadow offset 6px down and right
+        let mut data = Vec::new();
+        for y in 0..40 {
+            for x in 0..40 {
+                let alpha = if (10..30).contains(&x) && (10..30).contains(&y) {
+                    255
+                } else if (16..36).contains(&x) && (16..36).contains(&y) {
+                    20
+                } else {
+                    0
+                };
+                data.extend_from_slice(&[0, 0, 0, alpha]);
+            }
+        }
+        assert_eq!(alpha_coverage_bounds(&data, 40, 40, 0.1), Some((10, 10, 20, 20)));
+        // Corner-alpha matching keeps the shadow as content
+        assert_eq!(detect_content_bounds(&data, 40, 40, 10, true, 3, 1), Some((10, 10, 26, 26)));
+    }
+
+    #[test]
+    fn test_phash_tolerates_recompression() {
+        let (w, h) = (96u32, 64u32);
+        let scene: Vec<u8> = (0..w * h)
+            .flat_map(|i| {
+                let (x, y) = ((i % w) as f32, (i / w) as f32);
+                let sun = if (x - 60.0).powi(2) + (y - 20.0).powi(2) < 150.0 { 120.0 } else { 0.0 };
+                let v = (y * 2.5 + sun + (x / 7.0).sin() * 20.0).min(255.0) as u8;
+                [v, v / 2, 255 - v, 255]
+            })
+            .collect();
+        let options = crate::codecs::jpeg::JpegOptions { quality: 50, ..Default::default() };
+        let jpeg = crate::codecs::jpeg::encode_jpeg(&scene, w, h, &options).unwrap();
+        let (recompressed, _, _) = crate::codecs::jpeg::decode_jpeg(&jpeg).unwrap();
+        let unrelated: Vec<u8> = (0..w * h)
+            .flat_map(|i| if (i % w / 12 + i / w / 12) % 2 == 0 { [250, 250, 250, 255] } else { [10, 30, 10, 255] })
+            .collect();
+
+        let hash = phash(&scene, w, h);
+        assert!(hamming_distance(hash, phash(&recompressed, w, h)) <= 4);
+        assert!(hamming_distance(hash, phash(&unrelated, w, h)) >= 20);
+        assert_eq!(hamming_distance(0b1011, 0b0110), 3);
+    }
+
+    #[test]
+    fn test_blur_region_obscures_content() {
+        // Hash noise: every pixel independent, the hardest case to wash out
+        let (w, h) = (64u32, 48u32);
+        let data: Vec<u8> = (0..w * h).flat_map(|i| [(i.wrapping_mul(2654435761) >> 24) as u8, 0, 0, 255]).collect();
+        let variance = |pixels: &[u8], rect: (u32, u32, u32, u32)| {
+            let values: Vec<f64> = (rect.1..rect.1 + rect.3)
+                .flat_map(|y| (rect.0..rect.0 + rect.2).map(move |x| ((y * w + x) * 4) as usize))
+                .map(|idx| pixels[idx] as f64)
+                .collect();
+            let mean = values.iter().sum::<f64>() / values.len() as f64;
+            values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / values.len() as f64
+        };
+
+        let rect = (16, 8, 32, 32);
+        // A token sigma is raised to redaction strength
+        let redacted = blur_region(&data, w, h, rect, 1.0);
+        assert!(variance(&data, rect) > 3000.0);
+        assert!(variance(&redacted, rect) < 25.0, "{}", variance(&redacted, rect));
+        // Outside the rect nothing changes; a rect past the edge is clipped
+        assert_eq!(redacted[..8 * w as usize * 4], data[..8 * w as usize * 4]);
+        assert_eq!(blur_region(&data, w, h, (60, 40, 100, 100), 8.0).len(), data.len());
+    }
+
+    #[test]
+    fn test_strided_scan_matches_full_scan() {
+        // Clean white background with a disc whose edges fall between stride samples
+        let (w, h) = (97u32, 83u32);
+        for (cx, cy, r) in [(40.0, 30.0, 17.3), (70.5, 55.2, 9.1), (5.0, 78.0, 6.0)] {
+            let data: Vec<u8> = (0..w * h)
+                .flat_map(|i| {
+                    let (x, y) = ((i % w) as f32, (i / w) as f32);
+                    let inside = (x - cx).powi(2) + (y - cy).powi(2) <= r * r;
+                    if inside { [200, 40, 40, 255] } else { [255, 255, 255, 255] }
+                })
+                .collect();
+            let full = detect_content_bounds(&data, w, h, 10, false, 1, 1);
+            assert!(full.is_some());
+            for stride in [2, 5, 8] {
+                assert_eq!(detect_content_bounds(&data, w, h, 10, false, 1, stride), full, "stride {}", stride);
+            }
+        }
+    }
+}
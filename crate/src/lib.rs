@@ -2,31 +2,217 @@ use serde::{Deserialize, Serialize};
 use wasm_bindgen::prelude::*;
 
 mod codecs;
+pub mod color;
 mod filters;
 mod resize;
 mod transform;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
 pub enum Format {
     Jpeg,
     Png,
     Avif,
+    /// Processed RGBA pixels with no codec step; dimensions are in ProcessInfo
+    Raw,
 }
 
-#[derive(Serialize, Deserialize)]
+impl Format {
+    /// Whether the encoded format can carry an alpha channel.
+    pub fn supports_alpha(self) -> bool {
+        !matches!(self, Format::Jpeg)
+    }
+}
+
+/// Perceptual quality levels that map to comparable per-format encoder settings
+/// (JPEG 75 and AVIF 75 don't look alike, so each format gets its own numbers).
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum QualityPreset {
+    Low,
+    Medium,
+    High,
+    Max,
+}
+
+/// Encoder settings a `QualityPreset` resolves to for one format.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PresetSettings {
+    pub quality: u8,
+    pub chroma_subsampling: bool,
+    pub avif_speed: u8,
+}
+
+impl QualityPreset {
+    pub fn settings_for(self, format: Format) -> PresetSettings {
+        let (quality, chroma_subsampling, avif_speed) = match (format, self) {
+            (Format::Jpeg, QualityPreset::Low) => (60, true, 6),
+            (Format::Jpeg, QualityPreset::Medium) => (75, true, 6),
+            (Format::Jpeg, QualityPreset::High) => (85, true, 6),
+            (Format::Jpeg, QualityPreset::Max) => (95, false, 6),
+            (Format::Avif, QualityPreset::Low) => (45, true, 8),
+            (Format::Avif, QualityPreset::Medium) => (58, true, 6),
+            (Format::Avif, QualityPreset::High) => (70, true, 5),
+            (Format::Avif, QualityPreset::Max) => (88, true, 4),
+            // PNG quality is the imagequant target; Max keeps nearly every color
+            (Format::Png, QualityPreset::Low) => (55, true, 6),
+            (Format::Png, QualityPreset::Medium) => (70, true, 6),
+            (Format::Png, QualityPreset::High) => (85, true, 6),
+            (Format::Png, QualityPreset::Max) => (98, true, 6),
+            // Nothing is encoded, so there's nothing to tune
+            (Format::Raw, _) => (100, false, 6),
+        };
+        PresetSettings { quality, chroma_subsampling, avif_speed }
+    }
+}
+
+/// Complexity (`filters::complexity`) at which `quality_auto` keeps the baseline.
+const AUTO_QUALITY_PIVOT: f32 = 0.15;
+
+/// Nudge `baseline` by up to a per-format span: down for flat images, whose
+/// artifacts stay invisible at lower settings, up for detailed ones.
+fn auto_quality(baseline: u8, format: Format, complexity: f32) -> u8 {
+    let span = match format {
+        Format::Jpeg => 10.0,
+        Format::Avif => 12.0,
+        Format::Png => 8.0,
+        Format::Raw => 0.0,
+    };
+    let offset = if complexity < AUTO_QUALITY_PIVOT {
+        complexity / AUTO_QUALITY_PIVOT - 1.0
+    } else {
+        ((complexity - AUTO_QUALITY_PIVOT) / AUTO_QUALITY_PIVOT).min(1.0)
+    };
+    (baseline as f32 + offset * span).round().clamp(1.0, 100.0) as u8
+}
+
+/// Outcome of a `target_bytes`/`target_ssim` quality search, in `ProcessInfo`.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct QualitySearch {
+    pub quality: u8,  // Quality of the returned output
+    pub encodes: u32, // Trial encodes performed (never more than quality_search_max_encodes)
+    pub met: bool,    // False when even quality_floor (or 100, for SSIM) misses; that result is returned
+}
+
+/// Binary search over integer quality `floor`-100 (so it converges to a
+/// single step) for the boundary of `encode`'s predicate, which must be
+/// monotone in quality. `highest` finds the highest quality that meets it
+/// (size budgets), otherwise the lowest (similarity targets).
+///
+/// The end of the range most likely to meet is tried first: when even that
+/// misses, the target is out of reach and that result comes back unmet (the
+/// floor, for a budget). Otherwise the search stops after `max_encodes`
+/// trials in all and returns the best meeting output found.
+fn search_quality(
+    floor: u8,
+    max_encodes: u32,
+    highest: bool,
+    mut encode: impl FnMut(u8) -> Result<(Vec<u8>, bool), String>,
+) -> Result<(Vec<u8>, QualitySearch), String> {
+    if max_encodes == 0 {
+        return Err("quality_search_max_encodes must be at least 1".to_string());
+    }
+    let (mut low, mut high) = (floor.clamp(1, 100), 100u8);
+    let edge = if highest { low } else { high };
+    let (data, met) = encode(edge)?;
+    let mut encodes = 1;
+    if !met {
+        return Ok((data, QualitySearch { quality: edge, encodes, met }));
+    }
+    let mut best = (data, edge);
+    if highest {
+        low += 1;
+    } else {
+        high -= 1;
+    }
+
+    while low <= high && encodes < max_encodes {
+        let quality = low + (high - low) / 2;
+        let (data, met) = encode(quality)?;
+        encodes += 1;
+        // Each later success is nearer the boundary than the ones before
+        if met == highest {
+            low = quality + 1;
+        } else {
+            high = quality.saturating_sub(1);
+        }
+        if met {
+            best = (data, quality);
+        }
+    }
+    Ok((best.0, QualitySearch { quality: best.1, encodes, met: true }))
+}
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct ResizeConfig {
-    pub width: u32,
+    #[serde(default)]
+    pub width: u32, // Target box; unused when scale_x/scale_y are set
+    #[serde(default)]
     pub height: u32,
     pub filter: String, // "Lanczos3", "CatmullRom", etc.
     #[serde(default = "default_fit_mode")]
-    pub fit_mode: String, // "contain", "cover", "fill", "inside", "outside"
+    pub fit_mode: String, // "contain", "cover", "fill", "inside", "outside", "contain_or_crop"
+    #[serde(default = "default_rounding")]
+    pub rounding: String, // "round", "floor" or "ceil" for fit dimensions (match a frontend's layout math)
+    #[serde(default)]
+    pub pad: bool, // Letterbox contain/inside results to exactly width x height (implied by contain_or_crop)
+    #[serde(default)]
+    pub prefilter_blur: Option<f32>, // Gaussian sigma applied before downscales of 2x or more
+    #[serde(default)]
+    pub no_upscale: bool, // Never enlarge: uniform modes keep the source size, "fill" clamps each axis
+    #[serde(default)]
+    pub scale_x: Option<f32>, // Independent multipliers on the source size (e.g. anamorphic desqueeze);
+    #[serde(default)]
+    pub scale_y: Option<f32>, // either one set stretches to the scaled size, ignoring width/height/fit_mode
 }
 
 fn default_fit_mode() -> String {
     "contain".to_string()
 }
 
-#[derive(Serialize, Deserialize)]
+fn default_rounding() -> String {
+    "round".to_string()
+}
+
+/// Edges auto_trim may move; the others stay at the image border.
+/// Omitted sides default to trimmable.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct TrimSides {
+    pub top: bool,
+    pub right: bool,
+    pub bottom: bool,
+    pub left: bool,
+}
+
+impl Default for TrimSides {
+    fn default() -> Self {
+        TrimSides { top: true, right: true, bottom: true, left: true }
+    }
+}
+
+impl TrimSides {
+    /// Restore the edges of `rect` that may not be trimmed; None when nothing
+    /// is left to trim.
+    fn restrict(self, (x, y, w, h): resize::CropRegion, width: u32, height: u32) -> Option<resize::CropRegion> {
+        let left = if self.left { x } else { 0 };
+        let top = if self.top { y } else { 0 };
+        let right = if self.right { x + w } else { width };
+        let bottom = if self.bottom { y + h } else { height };
+        let rect = (left, top, right - left, bottom - top);
+        (rect != (0, 0, width, height)).then_some(rect)
+    }
+}
+
+/// Separate mask image (RGBA pixels, e.g. a decoded mask PNG) whose luma
+/// becomes the output alpha.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct AlphaMask {
+    #[serde(with = "serde_bytes")]
+    pub data: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct CropConfig {
     pub x: u32,
     pub y: u32,
@@ -34,39 +220,168 @@ pub struct CropConfig {
     pub height: u32,
 }
 
-#[derive(Serialize, Deserialize)]
+/// Crop box as fractions (0-1) of the image at the crop stage.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct NormalizedCrop {
+    pub x: f32,
+    pub y: f32,
+    pub w: f32,
+    pub h: f32,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Config {
     pub format: Format,
     pub quality: u8,       // 0-100
-    pub transparent: bool, // Maintain transparency?
+    #[serde(default)]
+    pub quality_preset: Option<QualityPreset>, // Overrides quality/chroma/avif_speed when set
+    #[serde(default)]
+    pub quality_auto: bool, // Lower quality for flat images, raise it for detailed ones (around quality/preset)
+    #[serde(default)]
+    pub target_bytes: Option<usize>, // Search for the highest quality whose output fits (overrides quality)
+    #[serde(default)]
+    pub target_ssim: Option<f64>, // Search for the lowest quality whose decoded output reaches this SSIM (JPEG/PNG)
+    #[serde(default = "default_quality_search_max_encodes")]
+    pub quality_search_max_encodes: u32, // Trial encode cap per search (at least 1); 8 pins quality 1-100 exactly
+    #[serde(default)]
+    pub quality_floor: u8, // Lowest quality a target_bytes/target_ssim search may pick
+    pub transparent: bool, // Maintain transparency? (false flattens onto flatten_bg)
     pub lossless: bool,    // Force lossless?
+    #[serde(default)]
+    pub png_auto_mode: bool, // PNG: exact palette when <= 256 colors, quantized otherwise (overrides lossless)
     pub dithering: f32,    // 0.0 - 1.0 (for PNG/quantization)
     pub resize: Option<ResizeConfig>,
     pub chroma_subsampling: bool, // true = 4:2:0, false = 4:4:4
     #[serde(default)]
     pub speed_mode: bool, // true = fast encoding presets, false = quality presets
+    #[serde(default)]
+    pub png_liq_speed: Option<u8>, // PNG quantizer speed 1 (best) - 10 (fastest), overrides speed_mode
     #[serde(default = "default_avif_speed")]
     pub avif_speed: u8,   // AVIF encoder speed (0-10, higher = faster)
     #[serde(default = "default_avif_bit_depth")]
     pub avif_bit_depth: u8, // AVIF bit depth: 8 or 10
+    #[serde(default = "default_avif_matrix")]
+    pub avif_matrix: String, // "bt601", "bt709" or "identity" (RGB, for screenshots)
+    #[serde(default = "default_avif_alpha_mode")]
+    pub avif_alpha_mode: String, // "unassociated" or "premultiplied" (for compositors that expect it)
+    #[serde(default)]
+    pub avif_progressive: bool, // Layered AVIF (currently unsupported by ravif, errors if set)
     #[serde(default = "default_progressive")]
     pub progressive: bool, // Progressive JPEG encoding (default: true)
     #[serde(default)]
-    pub rotate: u16,  // 0, 90, 180, 270
+    pub jpeg_smoothing: Option<u8>, // JPEG encoder input smoothing 0-100
+    #[serde(default)]
+    pub dpi: Option<u16>, // JPEG: JFIF density for print workflows
+    #[serde(default)]
+    pub jpeg_thumbnail: Option<codecs::jpeg::JfifThumbnail>, // JPEG: RGB preview in the JFIF header (max 255x255)
+    #[serde(default)]
+    pub odd_size_444: bool, // JPEG: encode 4:4:4 instead of 4:2:0 when a dimension is odd (AVIF is always 4:4:4)
+    #[serde(default)]
+    pub rotate: transform::Rotation,  // 0, 90, 180, 270 (other angles are rejected)
+    #[serde(default)]
+    pub rotate_angle: Option<f32>, // Arbitrary clockwise degrees, after rotate/flips; the canvas grows to fit
+    #[serde(default)]
+    pub rotate_fill: Option<[u8; 4]>, // Uncovered corners; default transparent, or flatten_bg when alpha is flattened
     #[serde(default)]
     pub flip_h: bool,
     #[serde(default)]
     pub flip_v: bool,
     #[serde(default)]
+    pub pixel_aspect: Option<f32>, // Source pixel width / height (decode_*_info's pixel_aspect); None = square
+    #[serde(default)]
+    pub apply_pixel_aspect: bool, // Desqueeze non-square pixels (stretch one axis, after crops) before resize
+    #[serde(default)]
     pub auto_trim: bool,
     #[serde(default = "default_trim_threshold")]
     pub auto_trim_threshold: u8,  // 0-255
+    #[serde(default = "default_corner_sample")]
+    pub auto_trim_corner_sample: u32, // NxN corner block whose median is the background
+    #[serde(default)]
+    pub auto_trim_stride: Option<u32>, // Coarse scan every Nth pixel/row, then refine the edges (large images)
+    #[serde(default = "default_trim_mode")]
+    pub auto_trim_mode: String, // "color" (match corner color), "alpha" (match corner alpha) or "alpha_coverage"
+    #[serde(default = "default_trim_coverage")]
+    pub auto_trim_coverage: f32, // alpha_coverage: fraction (0-1) of the fullest row/column that counts as content
+    #[serde(default)]
+    pub auto_trim_sides: TrimSides, // e.g. {"left": false, "right": false} trims only letterbox bars
     #[serde(default)]
     pub crop: Option<CropConfig>,
     #[serde(default)]
+    pub crop_normalized: Option<NormalizedCrop>, // Fractional crop box, applied after `crop`
+    #[serde(default)]
+    pub crop_aspect: Option<(u32, u32)>, // e.g. [16, 9]: largest matching region, no scaling
+    #[serde(default = "default_crop_gravity")]
+    pub crop_gravity: String, // "center", "top", "bottom", "left", "right", "top_left", ...
+    #[serde(default)]
+    pub max_width: Option<u32>,  // Downscale-only limit, independent of fit mode
+    #[serde(default)]
+    pub max_height: Option<u32>,
+    #[serde(default)]
+    pub max_megapixels: Option<f32>, // Downscale-only pixel-count limit
+    #[serde(default)]
+    pub equalize: bool, // Luma histogram equalization (chroma preserved)
+    #[serde(default)]
     pub sharpen: f32,  // 0.0 to 1.0
     #[serde(default)]
-    pub blur: u32,  // Blur radius 0-50
+    pub sharpen_linear: bool, // Sharpen in linear light (less halo overshoot at high-contrast edges)
+    #[serde(default)]
+    pub input_gamma: Option<f32>, // Source decoding exponent for linear-light ops (decode_png_info's gamma); None = sRGB
+    #[serde(default)]
+    pub sharpen_edge_aware: bool, // Sharpen edges fully, flat areas not at all (no amplified sky noise)
+    #[serde(default, alias = "blur_radius")]
+    pub blur: u32,  // Box blur radius 0-50 (also accepted as "blur_radius")
+    #[serde(default)]
+    pub blur_regions: Vec<CropConfig>, // Input-pixel rects to redact with a Gaussian, before any other step
+    #[serde(default)]
+    pub blur_region_sigma: f32, // Redaction sigma, raised to at least filters::REDACT_MIN_SIGMA
+    #[serde(default = "default_strip_metadata")]
+    pub strip_metadata: bool, // Omit EXIF/ICC from output (all formats); AVIF can keep EXIF but not ICC
+    #[serde(default)]
+    pub metadata: Option<codecs::Metadata>, // Embedded when strip_metadata is false
+    #[serde(default)]
+    pub extra: codecs::Extra, // Raw encoder options, e.g. {"jpeg.restart_interval": "4"}; unknown keys are ignored
+    #[serde(default)]
+    pub gamma_correct_blend: bool, // Flatten in linear light (lighter, correct anti-aliased edges)
+    #[serde(default)]
+    pub flatten_bg: Option<[u8; 3]>, // Background for flattening/padding opaque output (default white)
+    #[serde(default)]
+    pub pad_to_multiple: Option<u32>, // Pad (no scaling) right/bottom up to a multiple, e.g. 8 or 16
+    #[serde(default = "default_pad_mode")]
+    pub pad_mode: String, // "constant" (flatten_bg or transparent), "edge", "reflect"
+    #[serde(default)]
+    pub alpha_bleed: Option<u32>, // Iterations of color bleeding under transparent pixels (before resize)
+    #[serde(default)]
+    pub mask: Option<AlphaMask>, // Resized to the image after crop/resize; its luma replaces alpha
+    #[serde(default)]
+    pub alpha_threshold: Option<u8>, // Snap alpha to 0/255 at this cutoff
+    #[serde(default)]
+    pub never_enlarge: bool, // Return the original file when the output would be larger (process_image_with_original; not for Raw)
+    #[serde(default)]
+    pub deterministic: bool, // Byte-identical output for identical input (pins encoder threads)
+    #[serde(default)]
+    pub diagnostics: bool, // Record per-stage decisions in ProcessInfo
+    #[serde(default)]
+    pub timing: bool, // Record per-stage milliseconds in ProcessInfo
+}
+
+fn default_crop_gravity() -> String {
+    "center".to_string()
+}
+
+fn default_pad_mode() -> String {
+    "constant".to_string()
+}
+
+fn default_corner_sample() -> u32 {
+    3
+}
+
+fn default_trim_mode() -> String {
+    "color".to_string()
+}
+
+fn default_trim_coverage() -> f32 {
+    0.1
 }
 
 fn default_trim_threshold() -> u8 {
@@ -81,6 +396,22 @@ fn default_avif_bit_depth() -> u8 {
     8 // Default 8-bit for maximum compatibility
 }
 
+fn default_avif_alpha_mode() -> String {
+    "unassociated".to_string()
+}
+
+fn default_avif_matrix() -> String {
+    "bt601".to_string() // ravif's built-in conversion
+}
+
+fn default_strip_metadata() -> bool {
+    true // Default ON - smallest output, no camera/location leaks
+}
+
+fn default_quality_search_max_encodes() -> u32 {
+    8 // The reachability check, then ceil(log2(99)) steps over quality 2-100
+}
+
 fn default_progressive() -> bool {
     true // Default ON - progressive JPEGs load blurry to sharp
 }
@@ -90,6 +421,123 @@ pub fn init_panic_hook() {
     console_error_panic_hook::set_once();
 }
 
+/// Read-only summary of what the pipeline did, returned by `process_image_info`.
+#[derive(Serialize, Default)]
+pub struct ProcessInfo {
+    pub width: u32,
+    pub height: u32,
+    /// True when `never_enlarge` returned the original file instead of the encoded output
+    pub kept_original: bool,
+    /// Region (x, y, width, height) of the input kept by auto-trim, if it trimmed anything
+    pub trim_rect: Option<resize::CropRegion>,
+    /// Ordered log of per-stage decisions (only populated when `config.diagnostics` is set)
+    pub diagnostics: Vec<String>,
+    /// Milliseconds spent per stage: trim, crop, resize, filters, encode (only when `config.timing` is set)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timing: Option<std::collections::BTreeMap<String, f64>>,
+    /// Quality picked by `target_bytes`/`target_ssim` and the encodes it took
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quality_search: Option<QualitySearch>,
+}
+
+/// Decoded RGBA pixels with their dimensions, returned by the `decode_*_info`
+/// exports instead of the 8-byte width/height prefix.
+#[derive(Serialize)]
+pub struct DecodedImage {
+    pub width: u32,
+    pub height: u32,
+    #[serde(with = "serde_bytes")]
+    pub pixels: Vec<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gamma: Option<f32>, // Decoding exponent when the source isn't sRGB (pass as Config::input_gamma)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pixel_aspect: Option<f32>, // Pixel width / height when not square (pass as Config::pixel_aspect)
+}
+
+impl From<(Vec<u8>, u32, u32)> for DecodedImage {
+    fn from((pixels, width, height): (Vec<u8>, u32, u32)) -> Self {
+        DecodedImage { width, height, pixels, gamma: None, pixel_aspect: None }
+    }
+}
+
+/// Composited animation frames at the logical screen size, returned by
+/// `decode_gif_frames`.
+#[derive(Serialize)]
+pub struct DecodedFrames {
+    pub width: u32,
+    pub height: u32,
+    pub frames: Vec<codecs::gif::Frame>,
+}
+
+#[derive(Serialize)]
+pub struct ProcessResult {
+    #[serde(with = "serde_bytes")]
+    pub data: Vec<u8>,
+    pub info: ProcessInfo,
+}
+
+/// Collects diagnostics entries; formatting is skipped entirely when disabled.
+/// Stage timings, likewise, are only measured when enabled.
+#[derive(Clone)]
+struct Diagnostics {
+    enabled: bool,
+    entries: Vec<String>,
+    timings: Option<std::collections::BTreeMap<String, f64>>,
+    quality_search: Option<QualitySearch>,
+}
+
+impl Diagnostics {
+    fn new(config: &Config) -> Self {
+        Diagnostics {
+            enabled: config.diagnostics,
+            entries: Vec::new(),
+            timings: config.timing.then(std::collections::BTreeMap::new),
+            quality_search: None,
+        }
+    }
+
+    fn record(&mut self, entry: impl FnOnce() -> String) {
+        if self.enabled {
+            self.entries.push(entry());
+        }
+    }
+
+    /// Start of a stage for `lap`, or None when timing is off.
+    fn clock(&self) -> Option<f64> {
+        self.timings.as_ref().map(|_| now_ms())
+    }
+
+    /// Add the time since `start` to `stage` and restart the clock from now.
+    fn lap(&mut self, stage: &str, start: &mut Option<f64>) {
+        if let (Some(timings), Some(begin)) = (&mut self.timings, *start) {
+            let now = now_ms();
+            *timings.entry(stage.to_string()).or_default() += (now - begin).max(0.0);
+            *start = Some(now);
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(js_namespace = performance, js_name = now)]
+    fn performance_now() -> f64;
+}
+
+/// Monotonic milliseconds: `performance.now()` in wasm, where std's clock
+/// panics, and an Instant since first use natively.
+fn now_ms() -> f64 {
+    #[cfg(target_arch = "wasm32")]
+    {
+        performance_now()
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        static START: std::sync::OnceLock<std::time::Instant> = std::sync::OnceLock::new();
+        START.get_or_init(std::time::Instant::now).elapsed().as_secs_f64() * 1000.0
+    }
+}
+
 #[wasm_bindgen]
 pub fn process_image(
     data_mut: &mut [u8],
@@ -98,13 +546,325 @@ pub fn process_image(
     config_val: JsValue,
 ) -> Result<Vec<u8>, JsValue> {
     let config: Config = serde_wasm_bindgen::from_value(config_val)?;
+    process(data_mut, width, height, &config)
+        .map(|result| result.data)
+        .map_err(|e| JsValue::from_str(&e))
+}
+
+/// Same as `process_image`, but also returns a `ProcessInfo` describing the run.
+#[wasm_bindgen]
+pub fn process_image_info(
+    data_mut: &mut [u8],
+    width: u32,
+    height: u32,
+    config_val: JsValue,
+) -> Result<JsValue, JsValue> {
+    let config: Config = serde_wasm_bindgen::from_value(config_val)?;
+    let result = process(data_mut, width, height, &config).map_err(|e| JsValue::from_str(&e))?;
+    Ok(serde_wasm_bindgen::to_value(&result)?)
+}
+
+/// Upload normalization: decode any supported file, apply its EXIF
+/// orientation, desqueeze non-square pixels, downscale so neither side exceeds `max_dim` (0 = no limit),
+/// and re-encode without metadata.
+pub fn normalize_upload_data(input: &[u8], max_dim: u32, format: Format, quality: u8) -> Result<ProcessResult, String> {
+    let (pixels, width, height) = codecs::decode_image(input)?;
+    let orientation = if codecs::jpeg::is_jpeg(input) {
+        codecs::jpeg::exif_orientation(input).unwrap_or(1)
+    } else {
+        1
+    };
+
+    let mut config = Config::new(format, quality);
+    (config.rotate, config.flip_h, config.flip_v) = transform::exif_orientation_transforms(orientation);
+    config.pixel_aspect = codecs::pixel_aspect(input);
+    config.apply_pixel_aspect = true;
+    if max_dim > 0 {
+        config.max_width = Some(max_dim);
+        config.max_height = Some(max_dim);
+    }
+    process(&pixels, width, height, &config)
+}
+
+/// One call for the common upload case: sniff, decode, apply EXIF
+/// orientation, desqueeze, downscale to `max_dim` (0 = keep size), strip metadata, encode.
+#[wasm_bindgen]
+pub fn normalize_upload(input: &[u8], max_dim: u32, format: JsValue, quality: u8) -> Result<Vec<u8>, JsValue> {
+    let format: Format = serde_wasm_bindgen::from_value(format)?;
+    normalize_upload_data(input, max_dim, format, quality)
+        .map(|result| result.data)
+        .map_err(|e| JsValue::from_str(&e))
+}
+
+/// Encode one decoded image as several formats (e.g. AVIF with a JPEG
+/// fallback), running trim/resize/filters once. `formats` is an array like
+/// `["Avif", "Jpeg"]`; returns `{ Avif: Uint8Array, Jpeg: Uint8Array }`.
+#[wasm_bindgen]
+pub fn process_multi_format(
+    data_mut: &mut [u8],
+    width: u32,
+    height: u32,
+    formats: JsValue,
+    config_val: JsValue,
+) -> Result<JsValue, JsValue> {
+    let formats: Vec<Format> = serde_wasm_bindgen::from_value(formats)?;
+    let config: Config = serde_wasm_bindgen::from_value(config_val)?;
+    let results = process_multi_format_data(data_mut, width, height, &formats, &config)
+        .map_err(|e| JsValue::from_str(&e))?;
+    let encoded: std::collections::BTreeMap<String, serde_bytes::ByteBuf> = results
+        .into_iter()
+        .map(|(format, result)| (format!("{:?}", format), serde_bytes::ByteBuf::from(result.data)))
+        .collect();
+    Ok(encoded.serialize(&serde_wasm_bindgen::Serializer::json_compatible())?)
+}
+
+/// Same as `process_image_info`, but with the original encoded file so that
+/// `never_enlarge` can return it unchanged when re-encoding would bloat it.
+#[wasm_bindgen]
+pub fn process_image_with_original(
+    data_mut: &mut [u8],
+    width: u32,
+    height: u32,
+    original: &[u8],
+    config_val: JsValue,
+) -> Result<JsValue, JsValue> {
+    let config: Config = serde_wasm_bindgen::from_value(config_val)?;
+    let result = process_with_original(data_mut, width, height, original, &config)
+        .map_err(|e| JsValue::from_str(&e))?;
+    Ok(serde_wasm_bindgen::to_value(&result)?)
+}
+
+/// Run the pipeline, then apply `never_enlarge` against the original file.
+/// When the original wins, its bytes are returned as-is (in whatever format
+/// they were) and the reported dimensions are the decoded input's.
+pub fn process_with_original(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    original: &[u8],
+    config: &Config,
+) -> Result<ProcessResult, String> {
+    let mut result = process(data, width, height, config)?;
+    // Raw output is the pixels themselves, not a smaller file to prefer
+    let comparable = config.format != Format::Raw;
+    if config.never_enlarge && comparable && !original.is_empty() && result.data.len() >= original.len() {
+        let encoded_len = result.data.len();
+        result.data = original.to_vec();
+        result.info.width = width;
+        result.info.height = height;
+        result.info.kept_original = true;
+        if config.diagnostics {
+            result.info.diagnostics.push(format!(
+                "never_enlarge: kept original ({} bytes <= {} bytes)",
+                original.len(),
+                encoded_len
+            ));
+        }
+    }
+    Ok(result)
+}
+
+impl Config {
+    /// Plain settings for `format` at `quality`, matching the serde defaults
+    /// for every optional field.
+    fn new(format: Format, quality: u8) -> Self {
+        Config {
+            format,
+            quality,
+            quality_preset: None,
+            quality_auto: false,
+            target_bytes: None,
+            target_ssim: None,
+            quality_search_max_encodes: default_quality_search_max_encodes(),
+            quality_floor: 0,
+            transparent: true,
+            lossless: false,
+            png_auto_mode: false,
+            dithering: 1.0,
+            resize: None,
+            chroma_subsampling: true,
+            speed_mode: false,
+            png_liq_speed: None,
+            avif_speed: default_avif_speed(),
+            avif_bit_depth: default_avif_bit_depth(),
+            avif_matrix: default_avif_matrix(),
+            avif_alpha_mode: default_avif_alpha_mode(),
+            avif_progressive: false,
+            progressive: default_progressive(),
+            jpeg_smoothing: None,
+            dpi: None,
+            jpeg_thumbnail: None,
+            odd_size_444: false,
+            rotate: transform::Rotation::None,
+            rotate_angle: None,
+            rotate_fill: None,
+            flip_h: false,
+            flip_v: false,
+            pixel_aspect: None,
+            apply_pixel_aspect: false,
+            auto_trim: false,
+            auto_trim_threshold: default_trim_threshold(),
+            auto_trim_corner_sample: default_corner_sample(),
+            auto_trim_stride: None,
+            auto_trim_mode: default_trim_mode(),
+            auto_trim_coverage: default_trim_coverage(),
+            auto_trim_sides: TrimSides::default(),
+            crop: None,
+            crop_normalized: None,
+            crop_aspect: None,
+            crop_gravity: default_crop_gravity(),
+            max_width: None,
+            max_height: None,
+            max_megapixels: None,
+            equalize: false,
+            sharpen: 0.0,
+            sharpen_linear: false,
+            input_gamma: None,
+            sharpen_edge_aware: false,
+            blur: 0,
+            blur_regions: Vec::new(),
+            blur_region_sigma: 0.0,
+            strip_metadata: default_strip_metadata(),
+            metadata: None,
+            extra: codecs::Extra::new(),
+            gamma_correct_blend: false,
+            flatten_bg: None,
+            pad_to_multiple: None,
+            pad_mode: default_pad_mode(),
+            alpha_bleed: None,
+            mask: None,
+            alpha_threshold: None,
+            never_enlarge: false,
+            deterministic: false,
+            diagnostics: false,
+            timing: false,
+        }
+    }
+}
+
+/// Apply `max_width`/`max_height`, then `max_megapixels` (downscale only).
+fn limit_dimensions(width: u32, height: u32, config: &Config) -> (u32, u32) {
+    let (width, height) = resize::calculate_max_dimensions(width, height, config.max_width, config.max_height);
+    match config.max_megapixels {
+        Some(max_mp) => resize::calculate_megapixel_dimensions(width, height, max_mp),
+        None => (width, height),
+    }
+}
+
+/// Output will be opaque: the format has no alpha or transparency was turned off.
+fn flattens_alpha(config: &Config) -> bool {
+    !config.format.supports_alpha() || !config.transparent
+}
+
+/// Transfer function of the input pixels, for linear-light filters.
+fn transfer(config: &Config) -> color::transfer::Transfer {
+    config.input_gamma.map_or(color::transfer::Transfer::Srgb, color::transfer::Transfer::Gamma)
+}
+
+/// Fill color for padding: transparent when the output keeps alpha,
+/// otherwise the opaque flatten background.
+fn pad_color(config: &Config) -> [u8; 4] {
+    if flattens_alpha(config) {
+        let [r, g, b] = config.flatten_bg.unwrap_or([255, 255, 255]);
+        [r, g, b, 255]
+    } else {
+        [0, 0, 0, 0]
+    }
+}
+
+/// Pixels after every stage before encoding, with the trim that produced them.
+struct Prepared {
+    pixels: Vec<u8>,
+    width: u32,
+    height: u32,
+    trim_rect: Option<resize::CropRegion>,
+}
+
+/// Run the full pipeline (trim, crop, resize, transforms, filters, encode).
+pub fn process(data: &[u8], width: u32, height: u32, config: &Config) -> Result<ProcessResult, String> {
+    let mut diag = Diagnostics::new(config);
+    let prepared = prepare(data, width, height, config, &mut diag)?;
+    let encoded = encode(&prepared, config, &mut diag)?;
+    Ok(finish(&prepared, encoded, diag))
+}
+
+/// Run the pre-encode stages once and encode the result as each of `formats`,
+/// in order. Padding and flattening depend on whether the output keeps alpha,
+/// so opaque and alpha formats each get one shared preparation.
+pub fn process_multi_format_data(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    formats: &[Format],
+    config: &Config,
+) -> Result<Vec<(Format, ProcessResult)>, String> {
+    let mut prepared: Vec<(bool, Prepared, Diagnostics)> = Vec::new();
+    let mut results = Vec::with_capacity(formats.len());
+    for &format in formats {
+        let config = Config { format, ..config.clone() };
+        let flattens = flattens_alpha(&config);
+        let index = match prepared.iter().position(|(f, ..)| *f == flattens) {
+            Some(index) => index,
+            None => {
+                let mut diag = Diagnostics::new(&config);
+                let shared = prepare(data, width, height, &config, &mut diag)?;
+                prepared.push((flattens, shared, diag));
+                prepared.len() - 1
+            }
+        };
+        let (_, shared, prepare_diag) = &prepared[index];
+        let mut diag = prepare_diag.clone();
+        let encoded = encode(shared, &config, &mut diag)?;
+        results.push((format, finish(shared, encoded, diag)));
+    }
+    Ok(results)
+}
+
+/// Trim, crop, resize, transforms and filters: everything before encoding.
+fn prepare(data: &[u8], width: u32, height: u32, config: &Config, diag: &mut Diagnostics) -> Result<Prepared, String> {
+    // Transforms and filters index by dimensions; reject mismatched input up front
+    codecs::check_rgba_len(data, width, height)?;
+
+    diag.record(|| format!("input: {}x{}", width, height));
+    let mut stage = diag.clock();
+
+    // Redact first, so no later step (trim, resize) ever sees the content
+    let redacted = config.blur_regions.iter().fold(None::<Vec<u8>>, |pixels, region| {
+        let rect = (region.x, region.y, region.width, region.height);
+        diag.record(|| format!("blur_region: {}x{} at ({}, {})", rect.2, rect.3, rect.0, rect.1));
+        Some(filters::blur_region(pixels.as_deref().unwrap_or(data), width, height, rect, config.blur_region_sigma))
+    });
+    let data = redacted.as_deref().unwrap_or(data);
+    diag.lap("filters", &mut stage);
 
     // Apply auto-trim if enabled (FIRST, before crop, transform, resize)
-    let (trimmed_data, trimmed_width, trimmed_height) = if config.auto_trim {
-        filters::auto_trim(data_mut, width, height, config.auto_trim_threshold)
+    let trim_rect = if config.auto_trim && config.auto_trim_mode == "alpha_coverage" {
+        filters::alpha_coverage_bounds(data, width, height, config.auto_trim_coverage)
+    } else if config.auto_trim {
+        filters::detect_content_bounds(
+            data,
+            width,
+            height,
+            config.auto_trim_threshold,
+            config.auto_trim_mode == "alpha",
+            config.auto_trim_corner_sample,
+            config.auto_trim_stride.unwrap_or(1),
+        )
     } else {
-        (data_mut.to_vec(), width, height)
+        None
     };
+    let trim_rect = trim_rect.and_then(|rect| config.auto_trim_sides.restrict(rect, width, height));
+    let (trimmed_data, trimmed_width, trimmed_height) = if let Some((x, y, w, h)) = trim_rect {
+        diag.record(|| format!("auto_trim: trimmed to {}x{} at ({}, {})", w, h, x, y));
+        (resize::crop_image(data, width, height, x, y, w, h), w, h)
+    } else {
+        if config.auto_trim {
+            diag.record(|| format!("auto_trim: no trim (threshold {})", config.auto_trim_threshold));
+        }
+        (data.to_vec(), width, height)
+    };
+
+    diag.lap("trim", &mut stage);
 
     // Apply user crop if specified (after auto-trim, before resize)
     let (cropped_data, cropped_width, cropped_height) = if let Some(crop_cfg) = &config.crop {
@@ -117,42 +877,184 @@ pub fn process_image(
             crop_cfg.width,
             crop_cfg.height,
         );
+        diag.record(|| {
+            format!(
+                "crop: {}x{} at ({}, {})",
+                crop_cfg.width, crop_cfg.height, crop_cfg.x, crop_cfg.y
+            )
+        });
         (cropped, crop_cfg.width, crop_cfg.height)
     } else {
         (trimmed_data, trimmed_width, trimmed_height)
     };
 
-    // Now apply resize if specified
-    let current_data: Vec<u8>;
-    let current_width: u32;
-    let current_height: u32;
+    // Fractional crop box from a UI, resolved against the current dimensions
+    let (cropped_data, cropped_width, cropped_height) = if let Some(norm) = &config.crop_normalized {
+        let (x, y, w, h) =
+            resize::normalized_crop_region(cropped_width, cropped_height, norm.x, norm.y, norm.w, norm.h);
+        diag.record(|| format!("crop_normalized: {}x{} at ({}, {})", w, h, x, y));
+        (resize::crop_image(&cropped_data, cropped_width, cropped_height, x, y, w, h), w, h)
+    } else {
+        (cropped_data, cropped_width, cropped_height)
+    };
 
-    if let Some(resize_cfg) = config.resize {
-        // Calculate dimensions and optional crop based on fit mode
-        let (scaled_w, scaled_h, crop_region) = resize::calculate_fit_dimensions(
+    // Crop to a target aspect ratio at full resolution (after user crop, before resize)
+    let (cropped_data, cropped_width, cropped_height) = if let Some((aspect_w, aspect_h)) = config.crop_aspect {
+        let cropped = resize::crop_to_aspect(
+            &cropped_data,
             cropped_width,
             cropped_height,
-            resize_cfg.width,
-            resize_cfg.height,
-            &resize_cfg.fit_mode,
+            aspect_w,
+            aspect_h,
+            &config.crop_gravity,
         );
+        diag.record(|| {
+            format!(
+                "crop_aspect: {}:{} ({}) -> {}x{}",
+                aspect_w, aspect_h, config.crop_gravity, cropped.1, cropped.2
+            )
+        });
+        cropped
+    } else {
+        (cropped_data, cropped_width, cropped_height)
+    };
+
+    diag.lap("crop", &mut stage);
+
+    // Bleed colors under transparent pixels so resampling doesn't pull in their RGB
+    let cropped_data = if let Some(iterations) = config.alpha_bleed {
+        diag.record(|| format!("alpha_bleed: {} iterations", iterations));
+        filters::alpha_bleed(&cropped_data, cropped_width, cropped_height, iterations)
+    } else {
+        cropped_data
+    };
+
+    diag.lap("filters", &mut stage);
+
+    // Square up anamorphic pixels, so crops above stay in source pixels and
+    // the resize below fits the image as it's meant to be seen
+    let (cropped_data, cropped_width, cropped_height) = match config.pixel_aspect {
+        Some(aspect) if config.apply_pixel_aspect => {
+            let (w, h) = resize::desqueeze_dimensions(cropped_width, cropped_height, aspect)?;
+            if (w, h) != (cropped_width, cropped_height) {
+                let filter = config.resize.as_ref().map_or("Lanczos3", |r| r.filter.as_str());
+                diag.record(|| format!("pixel_aspect: {} -> {}x{}", aspect, w, h));
+                (resize::resize_image(&cropped_data, cropped_width, cropped_height, w, h, filter)?, w, h)
+            } else {
+                (cropped_data, cropped_width, cropped_height)
+            }
+        }
+        _ => (cropped_data, cropped_width, cropped_height),
+    };
+
+    diag.lap("resize", &mut stage);
+
+    // Now apply resize if specified
+    let mut current_data: Vec<u8>;
+    let mut current_width: u32;
+    let mut current_height: u32;
+
+    if let Some(resize_cfg) = &config.resize {
+        // Ratio-based scaling is a plain stretch to the scaled source size
+        let scaled = resize_cfg.scale_x.is_some() || resize_cfg.scale_y.is_some();
+        let fit_mode = if scaled { "fill" } else { resize_cfg.fit_mode.as_str() };
+        // Calculate dimensions and optional crop based on fit mode
+        let fit = |target_w, target_h| {
+            let fitted = resize::calculate_fit_dimensions(
+                cropped_width,
+                cropped_height,
+                target_w,
+                target_h,
+                fit_mode,
+                &resize_cfg.rounding,
+            );
+            if resize_cfg.no_upscale {
+                resize::limit_upscale(cropped_width, cropped_height, fitted, fit_mode)
+            } else {
+                fitted
+            }
+        };
+        let pads = |(scaled_w, scaled_h): (u32, u32), (target_w, target_h): (u32, u32)| {
+            (resize_cfg.pad || resize_cfg.fit_mode == "contain_or_crop")
+                && scaled_w <= target_w
+                && scaled_h <= target_h
+                && (scaled_w, scaled_h) != (target_w, target_h)
+        };
+        let (mut target_w, mut target_h) = if scaled {
+            let scale = |size: u32, factor: Option<f32>| -> Result<u32, String> {
+                match factor.unwrap_or(1.0) {
+                    f if f.is_finite() && f > 0.0 => Ok(((size as f64 * f as f64).round() as u32).max(1)),
+                    f => Err(format!("Invalid resize scale {}: must be a positive number", f)),
+                }
+            };
+            (scale(cropped_width, resize_cfg.scale_x)?, scale(cropped_height, resize_cfg.scale_y)?)
+        } else if resize_cfg.width == 0 || resize_cfg.height == 0 {
+            return Err(format!(
+                "Resize target {}x{} must be non-zero (or set scale_x/scale_y)",
+                resize_cfg.width, resize_cfg.height
+            ));
+        } else {
+            (resize_cfg.width, resize_cfg.height)
+        };
+        let (mut scaled_w, mut scaled_h, mut crop_region) = fit(target_w, target_h);
+
+        // Fold the size limits into the fit target so the image is resampled
+        // once, instead of resized to fit and then resized again to the limit
+        let (out_w, out_h) = match crop_region {
+            Some((_, _, w, h)) => (w, h),
+            None if pads((scaled_w, scaled_h), (target_w, target_h)) => (target_w, target_h),
+            None => (scaled_w, scaled_h),
+        };
+        let (limited_w, limited_h) = limit_dimensions(out_w, out_h, config);
+        if (limited_w, limited_h) != (out_w, out_h) {
+            let ratio = (limited_w as f64 / out_w as f64).min(limited_h as f64 / out_h as f64);
+            target_w = ((target_w as f64 * ratio).round() as u32).max(1);
+            target_h = ((target_h as f64 * ratio).round() as u32).max(1);
+            (scaled_w, scaled_h, crop_region) = fit(target_w, target_h);
+            diag.record(|| format!("resize: size limit folds target to {}x{}", target_w, target_h));
+        }
+        diag.record(|| {
+            format!(
+                "resize: fit '{}' {}x{} -> {}x{} ({})",
+                fit_mode, cropped_width, cropped_height, scaled_w, scaled_h, resize_cfg.filter
+            )
+        });
+
+        // Anti-alias the source before a large downscale
+        let prefiltered = resize_cfg.prefilter_blur.and_then(|sigma| {
+            let blurred = resize::prefilter(&cropped_data, cropped_width, cropped_height, scaled_w, scaled_h, sigma);
+            if blurred.is_some() {
+                diag.record(|| format!("resize: prefilter blur sigma {}", sigma));
+            }
+            blurred
+        });
 
         // First resize to calculated dimensions
         let resized_data = resize::resize_image(
-            &cropped_data, // src (use cropped data)
+            prefiltered.as_deref().unwrap_or(&cropped_data), // src (use cropped data)
             cropped_width,
             cropped_height,
             scaled_w,
             scaled_h,
             &resize_cfg.filter,
-        )
-        .map_err(|e| JsValue::from_str(&e))?;
+        )?;
 
         // Apply crop if needed (for cover mode)
-        if let Some((crop_x, crop_y, crop_w, crop_h)) = crop_region {
+        if let Some(region) = crop_region {
+            let (crop_x, crop_y, crop_w, crop_h) = resize::clamp_crop_region(region, scaled_w, scaled_h);
             current_data = resize::crop_image(&resized_data, scaled_w, scaled_h, crop_x, crop_y, crop_w, crop_h);
             current_width = crop_w;
             current_height = crop_h;
+            diag.record(|| format!("resize: cover crop {}x{} at ({}, {})", crop_w, crop_h, crop_x, crop_y));
+        } else if pads((scaled_w, scaled_h), (target_w, target_h)) {
+            // Letterbox to the exact target box, centered
+            let color = pad_color(config);
+            let x = (target_w - scaled_w) / 2;
+            let y = (target_h - scaled_h) / 2;
+            current_data = resize::pad_image(&resized_data, scaled_w, scaled_h, target_w, target_h, x, y, color);
+            current_width = target_w;
+            current_height = target_h;
+            diag.record(|| format!("resize: padded to {}x{} with {:?}", current_width, current_height, color));
         } else {
             current_data = resized_data;
             current_width = scaled_w;
@@ -164,59 +1066,269 @@ pub fn process_image(
         current_height = cropped_height;
     }
 
-    // Apply transforms (rotate, flip)
-    let (transformed_data, transformed_width, transformed_height) = transform::apply_transforms(
-        &current_data,
+    // Enforce max dimensions and megapixel limit (downscale only). A fit resize
+    // already folded these in, so this only resamples when there was none.
+    let (limited_w, limited_h) = limit_dimensions(current_width, current_height, config);
+    if (limited_w, limited_h) != (current_width, current_height) {
+        let filter = config.resize.as_ref().map_or("Lanczos3", |r| r.filter.as_str());
+        current_data = resize::resize_image(&current_data, current_width, current_height, limited_w, limited_h, filter)?;
+        diag.record(|| format!("size_limit: {}x{} -> {}x{}", current_width, current_height, limited_w, limited_h));
+        current_width = limited_w;
+        current_height = limited_h;
+    }
+
+    diag.lap("resize", &mut stage);
+
+    // Stretch the mask over the image as it now stands, so transforms apply to both
+    if let Some(mask) = &config.mask {
+        codecs::check_rgba_len(&mask.data, mask.width, mask.height).map_err(|e| format!("Mask: {}", e))?;
+        let filter = config.resize.as_ref().map_or("Lanczos3", |r| r.filter.as_str());
+        let resized_mask = if (mask.width, mask.height) == (current_width, current_height) {
+            mask.data.clone()
+        } else {
+            resize::resize_image(&mask.data, mask.width, mask.height, current_width, current_height, filter)?
+        };
+        current_data = filters::apply_alpha_mask(&current_data, &resized_mask, current_width, current_height);
+        diag.record(|| format!("mask: {}x{} -> {}x{}", mask.width, mask.height, current_width, current_height));
+    }
+
+    // Apply transforms (rotate, flip)
+    let (transformed_data, transformed_width, transformed_height) = transform::apply_transforms(
+        &current_data,
         current_width,
         current_height,
         config.rotate,
         config.flip_h,
         config.flip_v,
-    );
+    )?;
+    if config.rotate != transform::Rotation::None || config.flip_h || config.flip_v {
+        diag.record(|| {
+            format!(
+                "transform: rotate {}, flip_h {}, flip_v {}",
+                u16::from(config.rotate), config.flip_h, config.flip_v
+            )
+        });
+    }
+    let (transformed_data, transformed_width, transformed_height) = match config.rotate_angle {
+        Some(degrees) if degrees % 360.0 != 0.0 => {
+            // Transparent corners only survive in formats that keep alpha
+            let fill = config.rotate_fill.unwrap_or_else(|| pad_color(config));
+            diag.record(|| format!("transform: rotate_angle {} fill {:?}", degrees, fill));
+            transform::rotate_arbitrary(&transformed_data, transformed_width, transformed_height, degrees, fill)?
+        }
+        _ => (transformed_data, transformed_width, transformed_height),
+    };
+
+    // Equalize luminance (after resize/transforms, before sharpen)
+    let transformed_data = if config.equalize {
+        diag.record(|| "equalize: luma histogram".to_string());
+        filters::equalize(&transformed_data, transformed_width, transformed_height)
+    } else {
+        transformed_data
+    };
 
     // Apply sharpen if specified (after resize/transforms, before encoding)
     let sharpened_data = if config.sharpen > 0.0 {
-        filters::sharpen(&transformed_data, transformed_width, transformed_height, config.sharpen)
+        diag.record(|| format!("sharpen: amount {}", config.sharpen));
+        filters::sharpen(
+            &transformed_data,
+            transformed_width,
+            transformed_height,
+            config.sharpen,
+            config.sharpen_linear.then_some(transfer(config)),
+            config.sharpen_edge_aware,
+        )
     } else {
         transformed_data
     };
 
     // Apply blur if specified (after sharpen, before encoding)
-    let final_data = if config.blur > 0 {
+    let blurred_data = if config.blur > 0 {
+        diag.record(|| format!("blur: radius {}", config.blur));
         filters::blur(&sharpened_data, transformed_width, transformed_height, config.blur)
     } else {
         sharpened_data
     };
 
-    match config.format {
-        Format::Jpeg => codecs::jpeg::encode_jpeg(
-            &final_data,
+    // Pad to aligned dimensions last, so filters don't bleed into the padding
+    let (blurred_data, transformed_width, transformed_height) = if let Some(multiple) = config.pad_to_multiple {
+        let padded = resize::pad_to_multiple(
+            &blurred_data,
             transformed_width,
             transformed_height,
-            config.quality,
-            config.chroma_subsampling,
-            config.progressive,
-        )
-        .map_err(|e| JsValue::from_str(&e)),
-        Format::Png => codecs::png::encode_png(
-            &final_data,
+            multiple,
+            &config.pad_mode,
+            pad_color(config),
+        );
+        diag.record(|| format!("pad_to_multiple: {} ({}) -> {}x{}", multiple, config.pad_mode, padded.1, padded.2));
+        padded
+    } else {
+        (blurred_data, transformed_width, transformed_height)
+    };
+
+    // Binarize alpha last so earlier filters can't reintroduce soft edges
+    let thresholded_data = if let Some(cutoff) = config.alpha_threshold {
+        diag.record(|| format!("alpha_threshold: cutoff {}", cutoff));
+        filters::threshold_alpha(&blurred_data, transformed_width, transformed_height, cutoff)
+    } else {
+        blurred_data
+    };
+
+    // Flatten onto the same background used for padding, so letterbox bars and
+    // formerly-transparent areas match exactly in opaque output
+    let final_data = if flattens_alpha(config) {
+        let bg = config.flatten_bg.unwrap_or([255, 255, 255]);
+        diag.record(|| format!("flatten: onto {:?}", bg));
+        filters::flatten(
+            &thresholded_data,
             transformed_width,
             transformed_height,
-            config.lossless,
-            config.dithering,
-            config.speed_mode,
-            config.quality,
+            bg,
+            config.gamma_correct_blend.then_some(transfer(config)),
         )
-        .map_err(|e| JsValue::from_str(&e)),
-        Format::Avif => codecs::avif::encode_avif(
-            &final_data,
-            transformed_width,
-            transformed_height,
-            config.quality,
-            config.avif_speed,
-            config.avif_bit_depth,
+    } else {
+        thresholded_data
+    };
+
+    diag.lap("filters", &mut stage);
+
+    Ok(Prepared {
+        pixels: final_data,
+        width: transformed_width,
+        height: transformed_height,
+        trim_rect,
+    })
+}
+
+/// Encode prepared pixels as `config.format`.
+fn encode(prepared: &Prepared, config: &Config, diag: &mut Diagnostics) -> Result<Vec<u8>, String> {
+    let (final_data, transformed_width, transformed_height) = (&prepared.pixels, prepared.width, prepared.height);
+    let mut stage = diag.clock();
+
+    // A quality preset overrides the raw per-format knobs
+    let preset = config.quality_preset.map(|p| p.settings_for(config.format));
+    let mut quality = preset.map_or(config.quality, |p| p.quality);
+    if config.quality_auto {
+        let complexity = filters::complexity(final_data, transformed_width, transformed_height);
+        quality = auto_quality(quality, config.format, complexity);
+        diag.record(|| format!("quality_auto: complexity {:.3} -> quality {}", complexity, quality));
+    }
+    let mut chroma_subsampling = preset.map_or(config.chroma_subsampling, |p| p.chroma_subsampling);
+    if chroma_subsampling && config.odd_size_444 && (transformed_width % 2 == 1 || transformed_height % 2 == 1) {
+        chroma_subsampling = false;
+        diag.record(|| format!("chroma: odd size {}x{}, 4:4:4", transformed_width, transformed_height));
+    }
+    let avif_speed = preset.map_or(config.avif_speed, |p| p.avif_speed);
+    let metadata = if config.strip_metadata { None } else { config.metadata.as_ref() };
+
+    let jpeg = codecs::jpeg::JpegOptions {
+        quality,
+        chroma_subsampling,
+        progressive: config.progressive,
+        smoothing: config.jpeg_smoothing,
+        dpi: config.dpi,
+        thumbnail: config.jpeg_thumbnail.as_ref(),
+        metadata,
+        extra: Some(&config.extra),
+    };
+    let png = codecs::png::PngOptions {
+        lossless: config.lossless,
+        dithering_level: config.dithering,
+        speed_mode: config.speed_mode,
+        liq_speed: config.png_liq_speed,
+        quality,
+        deterministic: config.deterministic,
+        metadata,
+        extra: Some(&config.extra),
+    };
+    let avif = codecs::avif::AvifOptions {
+        quality,
+        speed: avif_speed,
+        bit_depth: config.avif_bit_depth,
+        deterministic: config.deterministic,
+        progressive: config.avif_progressive,
+        matrix: &config.avif_matrix,
+        alpha_mode: &config.avif_alpha_mode,
+        metadata,
+        extra: Some(&config.extra),
+    };
+    let (width, height) = (transformed_width, transformed_height);
+    let mut encode_at = |quality: u8| match config.format {
+        Format::Jpeg => codecs::jpeg::encode_jpeg(final_data, width, height, &codecs::jpeg::JpegOptions { quality, ..jpeg }),
+        Format::Png if config.png_auto_mode => {
+            codecs::png::encode_png_auto(final_data, width, height, &codecs::png::PngOptions { quality, ..png }).map(
+                |(encoded, exact)| {
+                    diag.record(|| format!("png_auto: {}", if exact { "exact palette" } else { "quantized" }));
+                    encoded
+                },
+            )
+        }
+        Format::Png => codecs::png::encode_png(final_data, width, height, &codecs::png::PngOptions { quality, ..png }),
+        Format::Avif => codecs::avif::encode_avif(final_data, width, height, &codecs::avif::AvifOptions { quality, ..avif }),
+        Format::Raw => Ok(final_data.clone()),
+    };
+
+    let encoded = match (config.target_bytes, config.target_ssim) {
+        (Some(_), Some(_)) => return Err("Set either target_bytes or target_ssim, not both".to_string()),
+        (Some(target), None) if config.format != Format::Raw => {
+            let (encoded, search) = search_quality(config.quality_floor, config.quality_search_max_encodes, true, |q| {
+                encode_at(q).map(|encoded| {
+                    let fits = encoded.len() <= target;
+                    (encoded, fits)
+                })
+            })?;
+            (quality, diag.quality_search) = (search.quality, Some(search));
+            encoded
+        }
+        (None, Some(target)) if config.format != Format::Raw => {
+            let (encoded, search) = search_quality(config.quality_floor, config.quality_search_max_encodes, false, |q| {
+                let encoded = encode_at(q)?;
+                let (decoded, _, _) = codecs::decode_image(&encoded)
+                    .map_err(|e| format!("target_ssim can't score {:?} output: {}", config.format, e))?;
+                let score = filters::ssim(final_data, &decoded, transformed_width, transformed_height);
+                Ok((encoded, score >= target))
+            })?;
+            (quality, diag.quality_search) = (search.quality, Some(search));
+            encoded
+        }
+        _ => encode_at(quality)?,
+    };
+    if let Some(search) = diag.quality_search {
+        diag.record(|| {
+            format!(
+                "quality_search: quality {} after {} encodes{}",
+                search.quality,
+                search.encodes,
+                if search.met { "" } else { " (target not met)" }
+            )
+        });
+    }
+    diag.lap("encode", &mut stage);
+    diag.record(|| {
+        format!(
+            "encode: {:?} quality {}{} -> {} bytes",
+            config.format,
+            quality,
+            if config.lossless { " lossless" } else { "" },
+            encoded.len()
         )
-        .map_err(|e| JsValue::from_str(&e)),
+    });
+
+    Ok(encoded)
+}
+
+fn finish(prepared: &Prepared, encoded: Vec<u8>, diag: Diagnostics) -> ProcessResult {
+    ProcessResult {
+        data: encoded,
+        info: ProcessInfo {
+            width: prepared.width,
+            height: prepared.height,
+            kept_original: false,
+            trim_rect: prepared.trim_rect,
+            diagnostics: diag.entries,
+            timing: diag.timings,
+            quality_search: diag.quality_search,
+        },
     }
 }
 
@@ -233,18 +1345,225 @@ pub fn resize_only(
         .map_err(|e| JsValue::from_str(&e))
 }
 
+/// Debug API: score every resize filter for this image and target size.
+/// Returns `[[filter, score], ...]` where higher scores preserve more detail.
 #[wasm_bindgen]
-pub fn decode_gif(data: &[u8]) -> Result<Vec<u8>, JsValue> {
-    let (pixels, width, height) = codecs::gif::decode_gif(data)
+pub fn compare_resize_filters(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    target_width: u32,
+    target_height: u32,
+) -> Result<JsValue, JsValue> {
+    let scores = resize::compare_filters(data, width, height, target_width, target_height)
+        .map_err(|e| JsValue::from_str(&e))?;
+    Ok(serde_wasm_bindgen::to_value(&scores)?)
+}
+
+/// Content bounds auto-trim would keep, as `[x, y, width, height]`,
+/// or null when there is nothing to trim. Lets callers apply the same crop
+/// to a related image or mask.
+#[wasm_bindgen]
+pub fn detect_trim_bounds(data: &[u8], width: u32, height: u32, threshold: u8) -> Result<JsValue, JsValue> {
+    codecs::check_rgba_len(data, width, height).map_err(|e| JsValue::from_str(&e))?;
+    let bounds = filters::detect_content_bounds(data, width, height, threshold, false, default_corner_sample(), 1);
+    Ok(serde_wasm_bindgen::to_value(&bounds)?)
+}
+
+/// Box blur RGBA pixels by `radius` (0-50; 0 returns them unchanged).
+#[wasm_bindgen]
+pub fn blur_only(data_mut: &mut [u8], width: u32, height: u32, radius: u32) -> Result<Vec<u8>, JsValue> {
+    codecs::check_rgba_len(data_mut, width, height).map_err(|e| JsValue::from_str(&e))?;
+    Ok(filters::blur(data_mut, width, height, radius))
+}
+
+/// Redact a rect of RGBA pixels with a Gaussian of at least
+/// `filters::REDACT_MIN_SIGMA`; the rest of the image is unchanged.
+#[wasm_bindgen]
+#[allow(clippy::too_many_arguments)]
+pub fn blur_region(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    x: u32,
+    y: u32,
+    region_width: u32,
+    region_height: u32,
+    sigma: f32,
+) -> Result<Vec<u8>, JsValue> {
+    codecs::check_rgba_len(data, width, height).map_err(|e| JsValue::from_str(&e))?;
+    Ok(filters::blur_region(data, width, height, (x, y, region_width, region_height), sigma))
+}
+
+/// Alpha channel of an RGBA image as an opaque grayscale RGBA image.
+#[wasm_bindgen]
+pub fn extract_alpha(data: &[u8], width: u32, height: u32) -> Result<Vec<u8>, JsValue> {
+    codecs::check_rgba_len(data, width, height).map_err(|e| JsValue::from_str(&e))?;
+    Ok(filters::extract_alpha(data, width, height))
+}
+
+/// Replace the alpha of `rgb_data` with the luma of `mask_data` (same size,
+/// both RGBA), e.g. a mask from `extract_alpha` or an external matte.
+#[wasm_bindgen]
+pub fn apply_alpha_mask(rgb_data: &[u8], mask_data: &[u8], width: u32, height: u32) -> Result<Vec<u8>, JsValue> {
+    codecs::check_rgba_len(rgb_data, width, height).map_err(|e| JsValue::from_str(&e))?;
+    codecs::check_rgba_len(mask_data, width, height).map_err(|e| JsValue::from_str(&e))?;
+    Ok(filters::apply_alpha_mask(rgb_data, mask_data, width, height))
+}
+
+/// Recover transparency from an image flattened over a known `matte` color
+/// (3 RGB bytes), e.g. a logo exported over white.
+#[wasm_bindgen]
+pub fn unmatte(data: &[u8], width: u32, height: u32, matte: &[u8]) -> Result<Vec<u8>, JsValue> {
+    codecs::check_rgba_len(data, width, height).map_err(|e| JsValue::from_str(&e))?;
+    let matte: [u8; 3] = matte
+        .try_into()
+        .map_err(|_| JsValue::from_str("Matte must be 3 RGB bytes"))?;
+    Ok(filters::unmatte(data, width, height, matte))
+}
+
+/// 64-bit perceptual hash for near-duplicate detection (a BigInt in JS).
+#[wasm_bindgen]
+pub fn perceptual_hash(data: &[u8], width: u32, height: u32) -> Result<u64, JsValue> {
+    codecs::check_rgba_len(data, width, height).map_err(|e| JsValue::from_str(&e))?;
+    Ok(filters::phash(data, width, height))
+}
+
+/// Differing bits between two `perceptual_hash` values.
+#[wasm_bindgen]
+pub fn hamming_distance(a: u64, b: u64) -> u32 {
+    filters::hamming_distance(a, b)
+}
+
+/// Analysis API: the palette lossy PNG would reduce this image to.
+/// Returns flat RGBA bytes, 4 per palette entry.
+#[wasm_bindgen]
+pub fn preview_palette(data: &[u8], width: u32, height: u32, max_colors: u32) -> Result<Vec<u8>, JsValue> {
+    let palette = codecs::png::preview_palette(data, width, height, max_colors)
         .map_err(|e| JsValue::from_str(&e))?;
+    Ok(palette.concat())
+}
 
-    // Return pixels with width and height encoded in first 8 bytes
+/// Prepend width and height as two little-endian u32s, the layout every
+/// `decode_*` export returns.
+fn with_size_prefix(pixels: &[u8], width: u32, height: u32) -> Vec<u8> {
     let mut result = Vec::with_capacity(8 + pixels.len());
     result.extend_from_slice(&width.to_le_bytes());
     result.extend_from_slice(&height.to_le_bytes());
-    result.extend_from_slice(&pixels);
+    result.extend_from_slice(pixels);
+    result
+}
 
-    Ok(result)
+/// Inverse of `with_size_prefix`: returns (pixels, width, height).
+fn split_size_prefix(data: &[u8]) -> Result<(&[u8], u32, u32), String> {
+    if data.len() < 8 {
+        return Err(format!("Decoded buffer too short for size prefix: {} bytes", data.len()));
+    }
+    let width = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+    let height = u32::from_le_bytes([data[4], data[5], data[6], data[7]]);
+    Ok((&data[8..], width, height))
+}
+
+/// Lay out thumbnails in a grid for previews and sprite sheets. `images` is
+/// any number of `decode_*`-style buffers (8-byte size prefix + pixels)
+/// back to back; `bg` is RGBA. Returns the sheet with the same size prefix.
+#[wasm_bindgen]
+pub fn make_contact_sheet(
+    images: &[u8],
+    cols: u32,
+    cell_width: u32,
+    cell_height: u32,
+    gap: u32,
+    bg: &[u8],
+) -> Result<Vec<u8>, JsValue> {
+    let bg: [u8; 4] = bg
+        .try_into()
+        .map_err(|_| JsValue::from_str("Background must be 4 RGBA bytes"))?;
+    let mut cells = Vec::new();
+    let mut rest = images;
+    while !rest.is_empty() {
+        let (pixels, width, height) = split_size_prefix(rest).map_err(|e| JsValue::from_str(&e))?;
+        let len = width as usize * height as usize * 4;
+        if pixels.len() < len {
+            return Err(JsValue::from_str("Contact sheet image is shorter than its size prefix"));
+        }
+        cells.push((pixels[..len].to_vec(), width, height));
+        rest = &pixels[len..];
+    }
+    let (sheet, width, height) = resize::make_grid(&cells, cols, cell_width, cell_height, gap, bg)
+        .map_err(|e| JsValue::from_str(&e))?;
+    Ok(with_size_prefix(&sheet, width, height))
+}
+
+/// Run the pipeline on a buffer exactly as returned by a `decode_*` export.
+pub fn process_prefixed(data: &[u8], config: &Config) -> Result<ProcessResult, String> {
+    let (pixels, width, height) = split_size_prefix(data)?;
+    process(pixels, width, height, config)
+}
+
+/// Same as `process_image`, but takes the `decode_*` output (8-byte size prefix +
+/// pixels) directly, so JS doesn't have to slice off the prefix.
+#[wasm_bindgen]
+pub fn process_decoded(data_with_prefix: &mut [u8], config_val: JsValue) -> Result<Vec<u8>, JsValue> {
+    let config: Config = serde_wasm_bindgen::from_value(config_val)?;
+    process_prefixed(data_with_prefix, &config)
+        .map(|result| result.data)
+        .map_err(|e| JsValue::from_str(&e))
+}
+
+#[wasm_bindgen]
+pub fn decode_gif(data: &[u8]) -> Result<Vec<u8>, JsValue> {
+    let (pixels, width, height) = codecs::gif::decode_gif(data)
+        .map_err(|e| JsValue::from_str(&e))?;
+
+    Ok(with_size_prefix(&pixels, width, height))
+}
+
+/// Decode one composited frame of an animated GIF (0-based), returned with the
+/// same 8-byte size prefix as `decode_gif`.
+#[wasm_bindgen]
+pub fn decode_gif_frame_at(data: &[u8], index: usize) -> Result<Vec<u8>, JsValue> {
+    let (pixels, width, height) = codecs::gif::decode_gif_frame_at(data, index)
+        .map_err(|e| JsValue::from_str(&e))?;
+
+    Ok(with_size_prefix(&pixels, width, height))
+}
+
+/// Every composited GIF frame with its delay, for re-encoding an animation:
+/// returns `{ width, height, frames: [{ pixels, delay }] }` (delay in centiseconds).
+#[wasm_bindgen]
+pub fn decode_gif_frames(data: &[u8]) -> Result<JsValue, JsValue> {
+    let (frames, width, height) = codecs::gif::decode_gif_frames(data).map_err(|e| JsValue::from_str(&e))?;
+    Ok(serde_wasm_bindgen::to_value(&DecodedFrames { width, height, frames })?)
+}
+
+/// Every composited GIF frame side by side (`frame_width * frames` x
+/// `frame_height`), with the same 8-byte size prefix as `decode_gif`, ready
+/// to pass to `process_decoded` for encoding.
+#[wasm_bindgen]
+pub fn gif_to_sprite_strip(data: &[u8]) -> Result<Vec<u8>, JsValue> {
+    let (pixels, width, height) = codecs::gif::decode_gif_sprite_strip(data)
+        .map_err(|e| JsValue::from_str(&e))?;
+
+    Ok(with_size_prefix(&pixels, width, height))
+}
+
+#[wasm_bindgen]
+pub fn decode_jpeg(data: &[u8]) -> Result<Vec<u8>, JsValue> {
+    let (pixels, width, height) = codecs::jpeg::decode_jpeg(data)
+        .map_err(|e| JsValue::from_str(&e))?;
+
+    Ok(with_size_prefix(&pixels, width, height))
+}
+
+/// Decode a JPEG at 1/2, 1/4 or 1/8 size via DCT scaling (`scale_denom` 2, 4
+/// or 8; 1 is a full decode), with the same size prefix as `decode_jpeg`.
+#[wasm_bindgen]
+pub fn decode_jpeg_scaled(data: &[u8], scale_denom: u8) -> Result<Vec<u8>, JsValue> {
+    let (pixels, width, height) = codecs::jpeg::decode_jpeg_scaled(data, scale_denom)
+        .map_err(|e| JsValue::from_str(&e))?;
+
+    Ok(with_size_prefix(&pixels, width, height))
 }
 
 #[wasm_bindgen]
@@ -252,13 +1571,7 @@ pub fn decode_bmp(data: &[u8]) -> Result<Vec<u8>, JsValue> {
     let (pixels, width, height) = codecs::bmp::decode_bmp(data)
         .map_err(|e| JsValue::from_str(&e))?;
 
-    // Return pixels with width and height encoded in first 8 bytes
-    let mut result = Vec::with_capacity(8 + pixels.len());
-    result.extend_from_slice(&width.to_le_bytes());
-    result.extend_from_slice(&height.to_le_bytes());
-    result.extend_from_slice(&pixels);
-
-    Ok(result)
+    Ok(with_size_prefix(&pixels, width, height))
 }
 
 #[wasm_bindgen]
@@ -266,11 +1579,767 @@ pub fn decode_tiff(data: &[u8]) -> Result<Vec<u8>, JsValue> {
     let (pixels, width, height) = codecs::tiff::decode_tiff(data)
         .map_err(|e| JsValue::from_str(&e))?;
 
-    // Return pixels with width and height encoded in first 8 bytes
-    let mut result = Vec::with_capacity(8 + pixels.len());
-    result.extend_from_slice(&width.to_le_bytes());
-    result.extend_from_slice(&height.to_le_bytes());
-    result.extend_from_slice(&pixels);
+    Ok(with_size_prefix(&pixels, width, height))
+}
 
-    Ok(result)
+/// Structured alternative to `decode_gif`: returns `{ width, height, pixels }`.
+#[wasm_bindgen]
+pub fn decode_gif_info(data: &[u8]) -> Result<JsValue, JsValue> {
+    let decoded: DecodedImage = codecs::gif::decode_gif(data)
+        .map_err(|e| JsValue::from_str(&e))?
+        .into();
+    Ok(serde_wasm_bindgen::to_value(&decoded)?)
+}
+
+/// Structured alternative to `decode_png`: returns `{ width, height, pixels }`,
+/// plus `gamma` and `pixel_aspect` when the file declares them.
+#[wasm_bindgen]
+pub fn decode_png_info(data: &[u8]) -> Result<JsValue, JsValue> {
+    let (pixels, width, height, transfer) =
+        codecs::png::decode_png_with_transfer(data).map_err(|e| JsValue::from_str(&e))?;
+    let gamma = match transfer {
+        color::transfer::Transfer::Gamma(exponent) => Some(exponent),
+        color::transfer::Transfer::Srgb => None,
+    };
+    let pixel_aspect = codecs::png::pixel_aspect(data);
+    Ok(serde_wasm_bindgen::to_value(&DecodedImage { width, height, pixels, gamma, pixel_aspect })?)
+}
+
+/// Structured alternative to `decode_jpeg`: returns `{ width, height, pixels }`,
+/// plus `pixel_aspect` for a non-square JFIF density.
+#[wasm_bindgen]
+pub fn decode_jpeg_info(data: &[u8]) -> Result<JsValue, JsValue> {
+    let mut decoded: DecodedImage = codecs::jpeg::decode_jpeg(data)
+        .map_err(|e| JsValue::from_str(&e))?
+        .into();
+    decoded.pixel_aspect = codecs::jpeg::pixel_aspect(data);
+    Ok(serde_wasm_bindgen::to_value(&decoded)?)
+}
+
+/// Structured alternative to `decode_bmp`: returns `{ width, height, pixels }`.
+#[wasm_bindgen]
+pub fn decode_bmp_info(data: &[u8]) -> Result<JsValue, JsValue> {
+    let decoded: DecodedImage = codecs::bmp::decode_bmp(data)
+        .map_err(|e| JsValue::from_str(&e))?
+        .into();
+    Ok(serde_wasm_bindgen::to_value(&decoded)?)
+}
+
+/// Same as `decode_tiff`, but maps float samples from a fixed [min, max] range
+/// instead of the image's own min/max.
+#[wasm_bindgen]
+pub fn decode_tiff_range(data: &[u8], min: f32, max: f32) -> Result<Vec<u8>, JsValue> {
+    let (pixels, width, height) = codecs::tiff::decode_tiff_normalized(data, Some((min, max)))
+        .map_err(|e| JsValue::from_str(&e))?;
+
+    Ok(with_size_prefix(&pixels, width, height))
+}
+
+/// Structured alternative to `decode_tiff`: returns `{ width, height, pixels }`,
+/// plus `pixel_aspect` when the X and Y resolutions differ.
+#[wasm_bindgen]
+pub fn decode_tiff_info(data: &[u8]) -> Result<JsValue, JsValue> {
+    let mut decoded: DecodedImage = codecs::tiff::decode_tiff(data)
+        .map_err(|e| JsValue::from_str(&e))?
+        .into();
+    decoded.pixel_aspect = codecs::tiff::pixel_aspect(data);
+    Ok(serde_wasm_bindgen::to_value(&decoded)?)
+}
+
+/// Round-trip a tiny synthetic image through every output format.
+/// Returns `"ok"` or the error message per format name.
+pub fn self_test_report() -> std::collections::BTreeMap<String, String> {
+    let (width, height) = (8u32, 8u32);
+    let pixels: Vec<u8> = (0..width * height)
+        .flat_map(|i| [(i % width * 32) as u8, (i / width * 32) as u8, 128, 255])
+        .collect();
+
+    let check = |format: Format| -> Result<(), String> {
+        let encoded = process(&pixels, width, height, &Config::new(format, 80))?.data;
+        if format == Format::Avif {
+            // No AVIF decoder in this crate: check the container brand instead
+            return match encoded.get(4..12) {
+                Some(b"ftypavif") => Ok(()),
+                _ => Err("AVIF output is missing its ftyp box".to_string()),
+            };
+        }
+        let (_, w, h) = codecs::decode_image(&encoded)?;
+        if (w, h) != (width, height) {
+            return Err(format!("Decoded {}x{}, expected {}x{}", w, h, width, height));
+        }
+        Ok(())
+    };
+
+    [Format::Jpeg, Format::Png, Format::Avif]
+        .into_iter()
+        .map(|format| {
+            let status = check(format).err().unwrap_or_else(|| "ok".to_string());
+            (format!("{:?}", format), status)
+        })
+        .collect()
+}
+
+/// Startup health check: `{ Jpeg: "ok", Png: "ok", Avif: "<error>" }`, so a
+/// broken codec build shows up before the first real request.
+#[wasm_bindgen]
+pub fn self_test() -> Result<JsValue, JsValue> {
+    let report = self_test_report();
+    Ok(report.serialize(&serde_wasm_bindgen::Serializer::json_compatible())?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build a Config from the defaults below, overridden by the fields in `overrides`.
+    fn config(overrides: &str) -> Config {
+        let mut value: serde_json::Value = serde_json::from_str(
+            r#"{"format": "Png", "quality": 80, "transparent": true, "lossless": true,
+                "dithering": 1.0, "chroma_subsampling": true}"#,
+        )
+        .unwrap();
+        let overrides: serde_json::Value = serde_json::from_str(overrides).expect("valid test config");
+        for (key, field) in overrides.as_object().unwrap() {
+            value[key] = field.clone();
+        }
+        serde_json::from_value(value).expect("valid test config")
+    }
+
+    fn gradient(width: u32, height: u32) -> Vec<u8> {
+        let mut data = Vec::with_capacity((width * height * 4) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                data.extend_from_slice(&[(x * 255 / width) as u8, (y * 255 / height) as u8, 128, 255]);
+            }
+        }
+        data
+    }
+
+    #[test]
+    fn test_self_test_reports_every_format_ok() {
+        let report = self_test_report();
+        assert_eq!(report.keys().collect::<Vec<_>>(), ["Avif", "Jpeg", "Png"]);
+        for (format, status) in &report {
+            assert_eq!(status, "ok", "{}", format);
+        }
+    }
+
+    #[test]
+    fn test_diagnostics_disabled_by_default() {
+        let cfg = config("{}");
+        let result = process(&gradient(8, 8), 8, 8, &cfg).unwrap();
+        assert!(result.info.diagnostics.is_empty());
+    }
+
+    #[test]
+    fn test_diagnostics_records_stages_in_order() {
+        let cfg = config(r#"{"diagnostics": true, "resize": {"width": 4, "height": 4, "filter": "Lanczos3"}}"#);
+        let result = process(&gradient(16, 8), 16, 8, &cfg).unwrap();
+        let log = &result.info.diagnostics;
+        assert!(!log.is_empty());
+        assert!(log[0].starts_with("input: 16x8"));
+        let resize_pos = log.iter().position(|e| e.starts_with("resize:")).unwrap();
+        let encode_pos = log.iter().position(|e| e.starts_with("encode:")).unwrap();
+        assert!(resize_pos < encode_pos);
+        assert!(log[resize_pos].contains("-> 4x2"));
+        assert_eq!((result.info.width, result.info.height), (4, 2));
+    }
+
+    #[test]
+    fn test_deterministic_output_is_byte_identical() {
+        for format in ["Png", "Avif"] {
+            let cfg = config(&format!(
+                r#"{{"format": "{}", "lossless": false, "quality": 90, "avif_speed": 10, "deterministic": true}}"#,
+                format
+            ));
+            let first = process(&gradient(16, 16), 16, 16, &cfg).unwrap();
+            let second = process(&gradient(16, 16), 16, 16, &cfg).unwrap();
+            assert_eq!(first.data, second.data, "{} output differs between runs", format);
+        }
+    }
+
+    #[test]
+    fn test_avif_progressive_is_rejected_not_ignored() {
+        let cfg = config(r#"{"format": "Avif", "avif_progressive": true}"#);
+        let err = process(&gradient(8, 8), 8, 8, &cfg).err().unwrap();
+        assert!(err.contains("Progressive AVIF"), "{}", err);
+    }
+
+    #[test]
+    fn test_padded_jpeg_shares_one_background() {
+        // 40x20: transparent (garbage green RGB) left half, opaque blue right half
+        let mut data = Vec::new();
+        for _y in 0..20 {
+            for x in 0..40 {
+                data.extend_from_slice(if x < 20 { &[0, 255, 0, 0] } else { &[0, 0, 255, 255] });
+            }
+        }
+        let cfg = config(
+            r#"{"format": "Jpeg", "quality": 95, "flatten_bg": [200, 100, 50],
+                "resize": {"width": 100, "height": 100, "filter": "Lanczos3", "pad": true}}"#,
+        );
+        let result = process(&data, 40, 20, &cfg).unwrap();
+        assert_eq!((result.info.width, result.info.height), (100, 100));
+
+        let (pixels, _, _) = codecs::jpeg::decode_jpeg(&result.data).unwrap();
+        let at = |x: usize, y: usize| &pixels[(y * 100 + x) * 4..(y * 100 + x) * 4 + 3];
+        // Padding bar, formerly-transparent area, and the seam between them
+        for (x, y) in [(50, 5), (20, 50), (20, 25), (5, 74)] {
+            let px = at(x, y);
+            for (c, expected) in [200i16, 100, 50].iter().enumerate() {
+                assert!((px[c] as i16 - expected).abs() <= 8, "({}, {}): {:?}", x, y, px);
+            }
+        }
+        assert!(at(80, 50)[2] > 200, "content should stay blue");
+    }
+
+    #[test]
+    fn test_strip_metadata_per_format() {
+        let data = gradient(16, 16);
+        let exif = b"MM\0*\0\0\0\x08nanopng-exif-marker";
+        let with_metadata = |overrides: &str| {
+            let mut cfg = config(overrides);
+            cfg.metadata = Some(codecs::Metadata { exif: Some(exif.to_vec()), icc_profile: None });
+            cfg
+        };
+        let contains = |haystack: &[u8]| haystack.windows(exif.len()).any(|w| w == exif);
+
+        for format in ["Jpeg", "Png", "Avif"] {
+            let stripped = process(&data, 16, 16, &with_metadata(&format!(r#"{{"format": "{}", "avif_speed": 10}}"#, format)))
+                .unwrap()
+                .data;
+            let kept = process(
+                &data,
+                16,
+                16,
+                &with_metadata(&format!(r#"{{"format": "{}", "avif_speed": 10, "strip_metadata": false}}"#, format)),
+            )
+            .unwrap()
+            .data;
+            assert!(!contains(&stripped), "{} stripped output has EXIF", format);
+            assert!(contains(&kept), "{} lost EXIF", format);
+            assert!(kept.len() > stripped.len());
+        }
+
+        // AVIF has no way to carry an ICC profile; stripping it is still fine
+        let mut avif = config(r#"{"format": "Avif", "avif_speed": 10, "strip_metadata": false}"#);
+        avif.metadata = Some(codecs::Metadata { exif: None, icc_profile: Some(vec![0; 128]) });
+        assert!(process(&data, 16, 16, &avif).err().unwrap().contains("ICC"));
+        avif.strip_metadata = true;
+        assert!(process(&data, 16, 16, &avif).is_ok());
+    }
+
+    #[test]
+    fn test_trim_rect_reported_in_info() {
+        // White 20x10 with a dark 6x4 block at (5, 3)
+        let mut data = vec![255u8; 20 * 10 * 4];
+        for y in 3..7 {
+            for x in 5..11 {
+                let idx = (y * 20 + x) * 4;
+                data[idx..idx + 3].copy_from_slice(&[10, 10, 10]);
+            }
+        }
+        let result = process(&data, 20, 10, &config(r#"{"auto_trim": true}"#)).unwrap();
+        assert_eq!(result.info.trim_rect, Some((5, 3, 6, 4)));
+        assert_eq!((result.info.width, result.info.height), (6, 4));
+        assert_eq!(result.info.trim_rect, filters::detect_content_bounds(&data, 20, 10, 25, false, 3, 1));
+
+        let untrimmed = process(&data, 20, 10, &config("{}")).unwrap();
+        assert_eq!(untrimmed.info.trim_rect, None);
+    }
+
+    #[test]
+    fn test_decoded_image_is_structured() {
+        let data = gradient(12, 7);
+        let jpeg = codecs::jpeg::encode_jpeg(&data, 12, 7, &codecs::jpeg::JpegOptions { quality: 90, ..Default::default() }).unwrap();
+        let decoded: DecodedImage = codecs::jpeg::decode_jpeg(&jpeg).unwrap().into();
+        assert_eq!((decoded.width, decoded.height), (12, 7));
+        assert_eq!(decoded.pixels.len(), 12 * 7 * 4);
+
+        let json = serde_json::to_value(&decoded).unwrap();
+        assert_eq!(json["width"], 12);
+        assert_eq!(json["height"], 7);
+        assert_eq!(json["pixels"].as_array().unwrap().len(), 12 * 7 * 4);
+    }
+
+    #[test]
+    fn test_unsupported_rotation_is_rejected() {
+        let mut value: serde_json::Value = serde_json::to_value(config("{}")).unwrap();
+        value["rotate"] = 45.into();
+        let err = serde_json::from_value::<Config>(value.clone()).err().unwrap().to_string();
+        assert!(err.contains("Unsupported rotation 45"), "{}", err);
+
+        value["rotate"] = 270.into();
+        let cfg: Config = serde_json::from_value(value).unwrap();
+        assert_eq!(cfg.rotate, transform::Rotation::Cw270);
+    }
+
+    #[test]
+    fn test_never_enlarge_keeps_tiny_original() {
+        // Noisy content: a low-quality JPEG is far smaller than lossless PNG of it
+        let data: Vec<u8> = (0..32u32 * 32)
+            .flat_map(|i| {
+                let v = (i.wrapping_mul(2654435761) >> 24) as u8;
+                [v, v / 2, 255 - v, 255]
+            })
+            .collect();
+        let options = codecs::jpeg::JpegOptions { quality: 30, ..Default::default() };
+        let original = codecs::jpeg::encode_jpeg(&data, 32, 32, &options).unwrap();
+        let (pixels, _, _) = codecs::jpeg::decode_jpeg(&original).unwrap();
+
+        let cfg = config(r#"{"never_enlarge": true}"#);
+        let result = process_with_original(&pixels, 32, 32, &original, &cfg).unwrap();
+        assert!(result.info.kept_original);
+        assert_eq!(result.data, original);
+
+        let cfg = config("{}");
+        let result = process_with_original(&pixels, 32, 32, &original, &cfg).unwrap();
+        assert!(!result.info.kept_original);
+        assert!(result.data.len() > original.len());
+
+        // Raw pixels are always returned, however much bigger than the file
+        let cfg = config(r#"{"format": "Raw", "never_enlarge": true}"#);
+        let result = process_with_original(&pixels, 32, 32, &original, &cfg).unwrap();
+        assert!(!result.info.kept_original);
+        assert_eq!(result.data, pixels);
+    }
+
+    #[test]
+    fn test_process_prefixed_accepts_decoder_output() {
+        // 2x2 BMP: bottom row blue, green; top row red, white (BGR)
+        let bmp = codecs::bmp::tests::make_bmp(2, 2, 24, &[255, 0, 0, 0, 255, 0, 0, 0, 255, 255, 255, 255]);
+        let (pixels, width, height) = codecs::bmp::decode_bmp(&bmp).unwrap();
+        let prefixed = with_size_prefix(&pixels, width, height);
+
+        let result = process_prefixed(&prefixed, &config("{}")).unwrap();
+        assert_eq!((result.info.width, result.info.height), (2, 2));
+        assert_eq!(result.data, process(&pixels, 2, 2, &config("{}")).unwrap().data);
+
+        assert!(process_prefixed(&prefixed[..5], &config("{}")).is_err());
+        assert!(process_prefixed(&prefixed[..prefixed.len() - 1], &config("{}")).is_err());
+    }
+
+    #[test]
+    fn test_normalize_upload_applies_exif_orientation() {
+        // 64x32 landscape, red left / blue right, tagged orientation 6 (rotate 90 CW)
+        let data: Vec<u8> = (0..32)
+            .flat_map(|_| (0..64).flat_map(|x| if x < 32 { [255, 0, 0, 255] } else { [0, 0, 255, 255] }))
+            .collect();
+        let exif = [
+            b'M', b'M', 0, 42, 0, 0, 0, 8, // Big-endian TIFF header, IFD0 at 8
+            0, 1, // One entry
+            0x01, 0x12, 0, 3, 0, 0, 0, 1, 0, 6, 0, 0, // Orientation = 6
+            0, 0, 0, 0,
+        ];
+        let metadata = codecs::Metadata { exif: Some(exif.to_vec()), icc_profile: None };
+        let options = codecs::jpeg::JpegOptions {
+            quality: 95,
+            chroma_subsampling: false,
+            progressive: true,
+            metadata: Some(&metadata),
+            ..Default::default()
+        };
+        let upload = codecs::jpeg::encode_jpeg(&data, 64, 32, &options).unwrap();
+        assert_eq!(codecs::jpeg::exif_orientation(&upload), Some(6));
+
+        let result = normalize_upload_data(&upload, 32, Format::Jpeg, 90).unwrap();
+        assert_eq!((result.info.width, result.info.height), (16, 32));
+        assert!(result.data.len() < upload.len());
+        assert_eq!(codecs::jpeg::exif_orientation(&result.data), None);
+
+        // Upright: the left (red) half is now on top
+        let (pixels, _, _) = codecs::jpeg::decode_jpeg(&result.data).unwrap();
+        let top = &pixels[(4 * 16 + 8) * 4..][..3];
+        let bottom = &pixels[(28 * 16 + 8) * 4..][..3];
+        assert!(top[0] > 200 && top[2] < 50, "top {:?}", top);
+        assert!(bottom[2] > 200 && bottom[0] < 50, "bottom {:?}", bottom);
+    }
+
+    #[test]
+    fn test_anamorphic_tiff_is_desqueezed() {
+        use tiff::encoder::{colortype, Rational, TiffEncoder};
+
+        // 40x20 pixels at 72x144 dpi: each pixel is twice as wide as it is tall
+        let samples = vec![128u8; 40 * 20 * 3];
+        let mut tiff = std::io::Cursor::new(Vec::new());
+        let mut encoder = TiffEncoder::new(&mut tiff).unwrap();
+        let mut image = encoder.new_image::<colortype::RGB8>(40, 20).unwrap();
+        image.x_resolution(Rational { n: 72, d: 1 });
+        image.y_resolution(Rational { n: 144, d: 1 });
+        image.write_data(&samples).unwrap();
+        let tiff = tiff.into_inner();
+        assert_eq!(codecs::pixel_aspect(&tiff), Some(2.0));
+
+        let result = normalize_upload_data(&tiff, 0, Format::Png, 90).unwrap();
+        assert_eq!((result.info.width, result.info.height), (80, 20));
+
+        // Off by default: size is untouched
+        let (pixels, width, height) = codecs::decode_image(&tiff).unwrap();
+        let mut config = Config::new(Format::Raw, 100);
+        config.pixel_aspect = Some(2.0);
+        let result = process(&pixels, width, height, &config).unwrap();
+        assert_eq!((result.info.width, result.info.height), (40, 20));
+
+        // Tall pixels stretch the height instead
+        config.apply_pixel_aspect = true;
+        config.pixel_aspect = Some(0.5);
+        let result = process(&pixels, width, height, &config).unwrap();
+        assert_eq!((result.info.width, result.info.height), (40, 40));
+    }
+
+    #[test]
+    fn test_cover_crop_stays_inside_rounded_scale() {
+        // Region a pixel wider than a floor-rounded scale, and one past the edge
+        assert_eq!(resize::clamp_crop_region((0, 3, 101, 50), 100, 60), (0, 3, 100, 50));
+        assert_eq!(resize::clamp_crop_region((4, 12, 10, 50), 12, 60), (2, 10, 10, 50));
+
+        for (width, height) in [(333, 77), (77, 333), (1001, 999)] {
+            let cfg = config(
+                r#"{"format": "Png", "resize": {"width": 97, "height": 31, "filter": "Lanczos3",
+                    "fit_mode": "cover", "rounding": "floor"}}"#,
+            );
+            let result = process(&gradient(width, height), width, height, &cfg).unwrap();
+            assert!(result.info.width <= 97 && result.info.height <= 31);
+            assert!(result.info.width >= 96 && result.info.height >= 30);
+        }
+    }
+
+    #[test]
+    fn test_cover_with_size_limit_resamples_once() {
+        // 400x200 with fine horizontal detail, cover-cropped to 100x100, then capped at 50
+        let data: Vec<u8> = (0..200)
+            .flat_map(|y| {
+                (0..400).flat_map(move |x| {
+                    let v = ((x as f32 / 3.0).sin() * 100.0 + 128.0) as u8;
+                    [v, (y / 2) as u8, 255 - v, 255]
+                })
+            })
+            .collect();
+        let cfg = config(
+            r#"{"max_width": 50, "diagnostics": true,
+                "resize": {"width": 100, "height": 100, "filter": "Lanczos3", "fit_mode": "cover"}}"#,
+        );
+        let result = process(&data, 400, 200, &cfg).unwrap();
+        assert_eq!((result.info.width, result.info.height), (50, 50));
+        assert!(!result.info.diagnostics.iter().any(|d| d.starts_with("size_limit")));
+
+        // Reference: crop the center square of the source, then one resize to 50x50
+        let square = resize::crop_image(&data, 400, 200, 100, 0, 200, 200);
+        let reference = resize::resize_image(&square, 200, 200, 50, 50, "Lanczos3").unwrap();
+        let (pixels, _, _) = codecs::png::decode_png(&result.data).unwrap();
+        let total: u32 = pixels.iter().zip(&reference).map(|(&a, &b)| (a as i32 - b as i32).unsigned_abs()).sum();
+        let mean = total as f64 / pixels.len() as f64;
+        assert!(mean < 1.0, "mean difference {}", mean);
+    }
+
+    #[test]
+    fn test_multi_format_shares_one_preparation() {
+        let cfg = config(
+            r#"{"diagnostics": true, "resize": {"width": 20, "height": 20, "filter": "Lanczos3", "pad": true}}"#,
+        );
+        let results = process_multi_format_data(&gradient(40, 30), 40, 30, &[Format::Png, Format::Jpeg], &cfg).unwrap();
+        assert_eq!(results.iter().map(|(f, _)| *f).collect::<Vec<_>>(), [Format::Png, Format::Jpeg]);
+        for (format, result) in &results {
+            let (_, w, h) = codecs::decode_image(&result.data).unwrap();
+            assert_eq!((w, h, result.info.width, result.info.height), (20, 20, 20, 20), "{:?}", format);
+        }
+        // JPEG flattens its letterbox bars, so it matches a single-format run exactly
+        let jpeg = process(&gradient(40, 30), 40, 30, &config(
+            r#"{"format": "Jpeg", "diagnostics": true, "resize": {"width": 20, "height": 20, "filter": "Lanczos3", "pad": true}}"#,
+        ))
+        .unwrap();
+        assert_eq!(results[1].1.data, jpeg.data);
+        assert_eq!(results[1].1.info.diagnostics, jpeg.info.diagnostics);
+    }
+
+    #[test]
+    fn test_odd_size_444_only_applies_to_odd_jpeg() {
+        let odd = config(r#"{"format": "Jpeg", "quality": 80, "odd_size_444": true, "diagnostics": true}"#);
+        let fell_back = |w: u32, h: u32| {
+            let result = process(&gradient(w, h), w, h, &odd).unwrap();
+            let (_, dw, dh) = codecs::decode_image(&result.data).unwrap();
+            assert_eq!((dw, dh), (w, h));
+            result.info.diagnostics.iter().any(|d| d.starts_with("chroma: odd size"))
+        };
+        assert!(fell_back(101, 101));
+        assert!(fell_back(100, 33));
+        assert!(!fell_back(100, 32));
+    }
+
+    #[test]
+    fn test_resize_scale_stretches_axes_independently() {
+        let cfg = config(r#"{"resize": {"filter": "Lanczos3", "scale_x": 2.0, "scale_y": 1.0}}"#);
+        let result = process(&gradient(30, 30), 30, 30, &cfg).unwrap();
+        assert_eq!((result.info.width, result.info.height), (60, 30));
+
+        let bad = config(r#"{"resize": {"filter": "Lanczos3", "scale_x": 0.0}}"#);
+        assert!(process(&gradient(30, 30), 30, 30, &bad).err().unwrap().contains("Invalid resize scale"));
+    }
+
+    #[test]
+    fn test_png_auto_mode_picks_path_by_color_count() {
+        let auto = config(r#"{"png_auto_mode": true, "diagnostics": true}"#);
+        let colors = [[255, 0, 0, 255], [0, 128, 0, 255], [0, 0, 0, 0]];
+        let flat: Vec<u8> = (0..64 * 64).flat_map(|i| colors[i / 64 % 3]).collect();
+
+        let result = process(&flat, 64, 64, &auto).unwrap();
+        assert!(result.info.diagnostics.contains(&"png_auto: exact palette".to_string()));
+        assert_eq!(codecs::png::decode_png(&result.data).unwrap().0, flat);
+        let lossless = process(&flat, 64, 64, &config("{}")).unwrap();
+        assert!(result.data.len() < lossless.data.len());
+
+        let photo = process(&gradient(64, 64), 64, 64, &auto).unwrap();
+        assert!(photo.info.diagnostics.contains(&"png_auto: quantized".to_string()));
+    }
+
+    #[test]
+    fn test_gif_transparent_index_becomes_png_alpha() {
+        // 4x4 GIF: transparent background (index 1, drawn as solid magenta if
+        // the key were lost) with an opaque 2x2 black center
+        let palette = [0, 0, 0, 255, 0, 255];
+        let indices: Vec<u8> = (0..16).map(|i| if (1..3).contains(&(i % 4)) && (1..3).contains(&(i / 4)) { 0 } else { 1 }).collect();
+        let mut gif_data = Vec::new();
+        {
+            let mut encoder = gif::Encoder::new(&mut gif_data, 4, 4, &palette).unwrap();
+            encoder.write_frame(&gif::Frame::from_palette_pixels(4, 4, indices, palette.to_vec(), Some(1))).unwrap();
+        }
+
+        let (pixels, width, height) = codecs::gif::decode_gif(&gif_data).unwrap();
+        let png = process(&pixels, width, height, &config("{}")).unwrap();
+        let (decoded, _, _) = codecs::png::decode_png(&png.data).unwrap();
+        for (i, px) in decoded.chunks(4).enumerate() {
+            let center = (1..3).contains(&(i % 4)) && (1..3).contains(&(i / 4));
+            assert_eq!(px[3], if center { 255 } else { 0 }, "pixel {}", i);
+        }
+    }
+
+    #[test]
+    fn test_quality_preset_maps_per_format() {
+        let jpeg = QualityPreset::High.settings_for(Format::Jpeg);
+        let avif = QualityPreset::High.settings_for(Format::Avif);
+        let png = QualityPreset::High.settings_for(Format::Png);
+        assert_ne!(jpeg.quality, avif.quality);
+        assert_ne!(avif.quality, png.quality);
+        assert!(QualityPreset::Low.settings_for(Format::Jpeg).quality < jpeg.quality);
+    }
+
+    #[test]
+    fn test_quality_auto_lowers_flat_and_raises_busy_images() {
+        let flat = vec![128u8; 32 * 32 * 4];
+        // Hash noise: a plain checkerboard cancels out in the Sobel kernels
+        let busy: Vec<u8> = (0..32 * 32u32).flat_map(|i| [(i.wrapping_mul(2654435761) >> 24) as u8, 90, 40, 255]).collect();
+        let quality_for = |pixels: &[u8]| {
+            let cfg = config(r#"{"format": "Jpeg", "quality": 80, "quality_auto": true, "diagnostics": true}"#);
+            let result = process(pixels, 32, 32, &cfg).unwrap();
+            let encode = result.info.diagnostics.last().unwrap().clone();
+            encode.split("quality ").nth(1).unwrap().split(' ').next().unwrap().parse::<u8>().unwrap()
+        };
+        assert!(quality_for(&flat) < 80);
+        assert!(quality_for(&busy) > 80);
+        assert_eq!(auto_quality(80, Format::Jpeg, AUTO_QUALITY_PIVOT), 80);
+    }
+
+    #[test]
+    fn test_quality_search_is_bounded() {
+        for cap in 0..=10 {
+            let mut calls = 0;
+            let searched = search_quality(1, cap, true, |q| {
+                calls += 1;
+                Ok((vec![q], q <= 63))
+            });
+            let Ok((data, search)) = searched else {
+                assert_eq!((cap, calls), (0, 0), "{}", searched.err().unwrap());
+                continue;
+            };
+            assert_eq!(search.encodes, calls);
+            assert!(calls <= cap, "cap {}: {} encodes", cap, calls);
+            if search.met {
+                assert!(data[0] <= 63);
+            }
+        }
+        // The default cap is enough to land exactly on the boundary
+        let max = default_quality_search_max_encodes();
+        let (_, search) = search_quality(1, max, true, |q| Ok((vec![], q <= 63))).unwrap();
+        assert_eq!((search.quality, search.met), (63, true));
+        let (_, search) = search_quality(1, max, false, |q| Ok((vec![], q >= 37))).unwrap();
+        assert_eq!((search.quality, search.met), (37, true));
+        // Infeasible: quality 1 comes back after one encode, flagged
+        let (_, search) = search_quality(1, max, true, |_| Ok((vec![], false))).unwrap();
+        assert_eq!((search.quality, search.encodes, search.met), (1, 1, false));
+    }
+
+    #[test]
+    fn test_target_bytes_and_ssim_pick_quality() {
+        let data = gradient(64, 64);
+        let reference = process(&data, 64, 64, &config(r#"{"format": "Jpeg", "quality": 50}"#)).unwrap();
+
+        let cfg = config(&format!(r#"{{"format": "Jpeg", "target_bytes": {}}}"#, reference.data.len()));
+        let result = process(&data, 64, 64, &cfg).unwrap();
+        let search = result.info.quality_search.unwrap();
+        assert!(search.met && search.encodes <= default_quality_search_max_encodes(), "{:?}", search);
+        assert!(result.data.len() <= reference.data.len());
+        assert!(search.quality >= 50, "{:?}", search);
+
+        let cfg = config(r#"{"format": "Jpeg", "target_ssim": 0.95, "quality_search_max_encodes": 4}"#);
+        let result = process(&data, 64, 64, &cfg).unwrap();
+        let search = result.info.quality_search.unwrap();
+        assert!(search.met && search.encodes <= 4, "{:?}", search);
+        let (decoded, _, _) = codecs::jpeg::decode_jpeg(&result.data).unwrap();
+        assert!(filters::ssim(&data, &decoded, 64, 64) >= 0.95);
+
+        let both = config(r#"{"format": "Jpeg", "target_bytes": 1000, "target_ssim": 0.9}"#);
+        assert!(process(&data, 64, 64, &both).err().unwrap().contains("not both"));
+    }
+
+    #[test]
+    fn test_unreachable_budget_returns_floor_result() {
+        let data = gradient(64, 64);
+        let cfg = config(r#"{"format": "Jpeg", "target_bytes": 100, "quality_floor": 50}"#);
+        let result = process(&data, 64, 64, &cfg).unwrap();
+        let search = result.info.quality_search.unwrap();
+        assert_eq!((search.quality, search.met), (50, false));
+        assert!(result.data.len() > 100);
+
+        let at_floor = process(&data, 64, 64, &config(r#"{"format": "Jpeg", "quality": 50}"#)).unwrap();
+        assert_eq!(result.data, at_floor.data);
+    }
+
+    #[test]
+    fn test_extra_encoder_options() {
+        let data = gradient(32, 32);
+        let encode = |extra: &str| {
+            let cfg = config(&format!(r#"{{"format": "Jpeg", "quality": 80, "extra": {}}}"#, extra));
+            process(&data, 32, 32, &cfg).map(|result| result.data)
+        };
+        let plain = encode("{}").unwrap();
+        // Restart markers (0xFFD0-D7) appear only when the interval is set
+        let restarts = |jpeg: &[u8]| jpeg.windows(2).filter(|w| w[0] == 0xFF && (0xD0..=0xD7).contains(&w[1])).count();
+        let with_restarts = encode(r#"{"jpeg.restart_interval": "2"}"#).unwrap();
+        assert_eq!(restarts(&plain), 0);
+        assert!(restarts(&with_restarts) > 0);
+        // Unknown and other-format keys are ignored
+        assert_eq!(encode(r#"{"jpeg.no_such_knob": "1", "avif.alpha_quality": "50"}"#).unwrap(), plain);
+        assert!(encode(r#"{"jpeg.restart_interval": "often"}"#).err().unwrap().contains("jpeg.restart_interval"));
+    }
+
+    #[test]
+    fn test_blur_radius_alias() {
+        let data = gradient(16, 16);
+        let mut unblurred = data.clone();
+        assert_eq!(blur_only(&mut unblurred, 16, 16, 0).unwrap(), data);
+
+        let cfg = config(r#"{"format": "Png", "blur_radius": 3, "diagnostics": true}"#);
+        assert_eq!(cfg.blur, 3);
+        let result = process(&data, 16, 16, &cfg).unwrap();
+        assert!(result.info.diagnostics.iter().any(|entry| entry == "blur: radius 3"));
+        assert_ne!(filters::blur(&data, 16, 16, 3), data);
+    }
+
+    #[test]
+    fn test_rotate_angle_fill_follows_format() {
+        let data = gradient(40, 20);
+        let rotate = |json: &str| process(&data, 40, 20, &config(json)).unwrap();
+
+        // PNG keeps the uncovered corners transparent
+        let png = rotate(r#"{"format": "Png", "rotate_angle": 30}"#);
+        let (pixels, w, h) = codecs::png::decode_png(&png.data).unwrap();
+        assert_eq!((w, h), (45, 38));
+        assert_eq!(pixels[3], 0);
+        assert_eq!(pixels[((h / 2 * w + w / 2) * 4 + 3) as usize], 255);
+
+        // JPEG corners take flatten_bg, or rotate_fill when set
+        let corner = |json: &str| {
+            let jpeg = rotate(json);
+            let (pixels, _, _) = codecs::jpeg::decode_jpeg(&jpeg.data).unwrap();
+            [pixels[0], pixels[1], pixels[2]]
+        };
+        let near = |px: [u8; 3], expected: [u8; 3]| px.iter().zip(expected).all(|(&a, b)| a.abs_diff(b) <= 8);
+        let white = corner(r#"{"format": "Jpeg", "quality": 90, "rotate_angle": 30}"#);
+        assert!(near(white, [255, 255, 255]), "{:?}", white);
+        let red = corner(r#"{"format": "Jpeg", "quality": 90, "rotate_angle": 30, "rotate_fill": [200, 0, 0, 255]}"#);
+        assert!(near(red, [200, 0, 0]), "{:?}", red);
+    }
+
+    #[test]
+    fn test_auto_trim_sides_keeps_full_width() {
+        // 40x30 frame: black bars top and bottom, and a black margin at the sides
+        let data: Vec<u8> = (0..30u32)
+            .flat_map(|y| (0..40u32).map(move |x| if (5..25).contains(&y) && (3..37).contains(&x) { [200, 120, 60, 255] } else { [0, 0, 0, 255] }))
+            .flatten()
+            .collect();
+        let trim = |sides: &str| {
+            let cfg = config(&format!(r#"{{"format": "Png", "auto_trim": true, "auto_trim_sides": {}}}"#, sides));
+            process(&data, 40, 30, &cfg).unwrap().info.trim_rect
+        };
+        assert_eq!(trim("{}"), Some((3, 5, 34, 20)));
+        assert_eq!(trim(r#"{"left": false, "right": false}"#), Some((0, 5, 40, 20)));
+        assert_eq!(trim(r#"{"top": false, "right": false, "bottom": false, "left": false}"#), None);
+    }
+
+    #[test]
+    fn test_raw_output_is_the_processed_pixels() {
+        let data = gradient(40, 30);
+        let cfg = config(
+            r#"{"format": "Raw", "sharpen": 0.6, "resize": {"width": 20, "height": 20, "filter": "Lanczos3"}}"#,
+        );
+        let result = process(&data, 40, 30, &cfg).unwrap();
+        assert_eq!((result.info.width, result.info.height), (20, 15));
+
+        let resized = resize::resize_image(&data, 40, 30, 20, 15, "Lanczos3").unwrap();
+        assert_eq!(result.data, filters::sharpen(&resized, 20, 15, 0.6, None, false));
+    }
+
+    #[test]
+    fn test_mask_alpha_follows_resized_mask() {
+        // 64x32 mask: black on the left fading to white on the right
+        let mask: Vec<u8> = (0..64 * 32).flat_map(|i| [(i % 64 * 4) as u8; 3].into_iter().chain([255])).collect();
+        let cfg = Config {
+            mask: Some(AlphaMask { data: mask, width: 64, height: 32 }),
+            ..config(r#"{"format": "Raw", "resize": {"width": 16, "height": 16, "filter": "Bilinear"}}"#)
+        };
+        let result = process(&gradient(40, 20), 40, 20, &cfg).unwrap();
+        assert_eq!((result.info.width, result.info.height), (16, 8));
+
+        let row: Vec<u8> = result.data[..16 * 4].chunks_exact(4).map(|px| px[3]).collect();
+        assert!(row.windows(2).all(|pair| pair[0] < pair[1]), "{:?}", row);
+        assert!(row[0] < 16 && row[15] > 235, "{:?}", row);
+        // Every row matches the first: the mask has no vertical variation
+        assert!(result.data.chunks_exact(16 * 4).all(|r| r.chunks_exact(4).map(|px| px[3]).eq(row.iter().copied())));
+    }
+
+    #[test]
+    fn test_no_upscale_per_fit_mode() {
+        let size = |resize: &str| {
+            let cfg = config(&format!(r#"{{"format": "Raw", "resize": {}}}"#, resize));
+            let result = process(&gradient(40, 30), 40, 30, &cfg).unwrap();
+            (result.info.width, result.info.height)
+        };
+        // fill clamps each axis on its own: only the width shrinks
+        assert_eq!(size(r#"{"width": 20, "height": 90, "filter": "Bilinear", "fit_mode": "fill", "no_upscale": true}"#), (20, 30));
+        assert_eq!(size(r#"{"width": 80, "height": 90, "filter": "Bilinear", "fit_mode": "fill", "no_upscale": true}"#), (40, 30));
+        assert_eq!(size(r#"{"width": 80, "height": 90, "filter": "Bilinear", "fit_mode": "fill"}"#), (80, 90));
+        assert_eq!(size(r#"{"width": 80, "height": 90, "filter": "Bilinear", "no_upscale": true}"#), (40, 30));
+        // cover keeps the source scale and crops what fits of the target box
+        assert_eq!(size(r#"{"width": 35, "height": 60, "filter": "Bilinear", "fit_mode": "cover", "no_upscale": true}"#), (35, 30));
+    }
+
+    #[test]
+    fn test_timing_covers_every_stage() {
+        let data = gradient(64, 48);
+        let untimed = process(&data, 64, 48, &config(r#"{"format": "Png"}"#)).unwrap();
+        assert!(untimed.info.timing.is_none());
+
+        let cfg = config(r#"{"format": "Avif", "timing": true, "resize": {"width": 32, "height": 32, "filter": "Lanczos3"}}"#);
+        let timing = process(&data, 64, 48, &cfg).unwrap().info.timing.unwrap();
+        assert_eq!(timing.keys().collect::<Vec<_>>(), ["crop", "encode", "filters", "resize", "trim"]);
+        assert!(timing.values().all(|&ms| ms >= 0.0 && ms.is_finite()), "{:?}", timing);
+    }
+
+    #[test]
+    fn test_quality_preset_overrides_raw_quality() {
+        let cfg = config(r#"{"format": "Jpeg", "quality": 10, "quality_preset": "Max", "diagnostics": true}"#);
+        let result = process(&gradient(8, 8), 8, 8, &cfg).unwrap();
+        let encode = result.info.diagnostics.last().unwrap();
+        assert!(encode.contains("quality 95"), "{}", encode);
+    }
 }
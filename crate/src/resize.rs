@@ -2,6 +2,13 @@ use fast_image_resize::{
     images::Image, FilterType, MulDiv, PixelType, ResizeAlg, ResizeOptions, Resizer,
 };
 
+/// Crop rectangle as (x, y, width, height).
+pub type CropRegion = (u32, u32, u32, u32);
+
+/// Largest fraction of the scaled image "contain_or_crop" will crop away
+/// before falling back to contain + pad.
+pub const CONTAIN_OR_CROP_MAX_LOSS: f64 = 0.10;
+
 /// Calculate dimensions based on fit mode.
 /// Returns (final_width, final_height, optional_crop_region)
 /// crop_region is (x, y, crop_width, crop_height) for cover mode
@@ -11,7 +18,8 @@ pub fn calculate_fit_dimensions(
     target_width: u32,
     target_height: u32,
     fit_mode: &str,
-) -> (u32, u32, Option<(u32, u32, u32, u32)>) {
+    rounding: &str, // "round", "floor" or "ceil" for the scaled dimensions
+) -> (u32, u32, Option<CropRegion>) {
     match fit_mode {
         "fill" => {
             // Stretch to exact dimensions
@@ -22,34 +30,136 @@ pub fn calculate_fit_dimensions(
             let scale_x = target_width as f64 / src_width as f64;
             let scale_y = target_height as f64 / src_height as f64;
             let scale = scale_x.max(scale_y);
-            let scaled_w = (src_width as f64 * scale).round() as u32;
-            let scaled_h = (src_height as f64 * scale).round() as u32;
+            let scaled_w = round_dimension(src_width as f64 * scale, rounding);
+            let scaled_h = round_dimension(src_height as f64 * scale, rounding);
             // Center crop
             let crop_x = scaled_w.saturating_sub(target_width) / 2;
             let crop_y = scaled_h.saturating_sub(target_height) / 2;
-            (scaled_w.max(1), scaled_h.max(1), Some((crop_x, crop_y, target_width, target_height)))
+            (scaled_w, scaled_h, Some((crop_x, crop_y, target_width, target_height)))
+        }
+        "contain_or_crop" => {
+            // Crop like cover when that discards at most CONTAIN_OR_CROP_MAX_LOSS
+            // of the scaled image along the overflowing axis; otherwise contain
+            // (the caller pads the short side to the exact target)
+            let scale = (target_width as f64 / src_width as f64).max(target_height as f64 / src_height as f64);
+            let cover_w = (src_width as f64 * scale).round().max(1.0);
+            let cover_h = (src_height as f64 * scale).round().max(1.0);
+            let loss = 1.0 - (target_width as f64 / cover_w).min(target_height as f64 / cover_h);
+            let mode = if loss <= CONTAIN_OR_CROP_MAX_LOSS { "cover" } else { "contain" };
+            calculate_fit_dimensions(src_width, src_height, target_width, target_height, mode, rounding)
         }
         "outside" => {
             // Scale to cover minimum dimension
             let scale_x = target_width as f64 / src_width as f64;
             let scale_y = target_height as f64 / src_height as f64;
             let scale = scale_x.max(scale_y);
-            let new_w = (src_width as f64 * scale).round() as u32;
-            let new_h = (src_height as f64 * scale).round() as u32;
-            (new_w.max(1), new_h.max(1), None)
+            let new_w = round_dimension(src_width as f64 * scale, rounding);
+            let new_h = round_dimension(src_height as f64 * scale, rounding);
+            (new_w, new_h, None)
         }
         _ => {
             // "contain" or "inside" - fit within bounds
             let scale_x = target_width as f64 / src_width as f64;
             let scale_y = target_height as f64 / src_height as f64;
             let scale = scale_x.min(scale_y);
-            let new_w = (src_width as f64 * scale).round() as u32;
-            let new_h = (src_height as f64 * scale).round() as u32;
-            (new_w.max(1), new_h.max(1), None)
+            let new_w = round_dimension(src_width as f64 * scale, rounding);
+            let new_h = round_dimension(src_height as f64 * scale, rounding);
+            (new_w, new_h, None)
         }
     }
 }
 
+/// Undo any enlargement in a `calculate_fit_dimensions` result.
+/// "fill" clamps each axis to the source on its own (so the aspect may change
+/// less than asked, or not at all); the uniform modes keep the source size,
+/// with cover's crop shrunk to fit inside it, still centered.
+pub fn limit_upscale(
+    src_width: u32,
+    src_height: u32,
+    (width, height, crop): (u32, u32, Option<CropRegion>),
+    fit_mode: &str,
+) -> (u32, u32, Option<CropRegion>) {
+    if width <= src_width && height <= src_height {
+        return (width, height, crop);
+    }
+    if fit_mode == "fill" {
+        return (width.min(src_width), height.min(src_height), None);
+    }
+    let crop = crop.map(|(_, _, crop_w, crop_h)| {
+        let (crop_w, crop_h) = (crop_w.min(src_width), crop_h.min(src_height));
+        ((src_width - crop_w) / 2, (src_height - crop_h) / 2, crop_w, crop_h)
+    });
+    (src_width, src_height, crop)
+}
+
+/// Round a scaled dimension ("round", "floor" or "ceil"), at least 1px.
+fn round_dimension(value: f64, rounding: &str) -> u32 {
+    // Snap float noise first (e.g. 3.0 / 7.0 * 7.0), or floor/ceil land a pixel off
+    let nearest = value.round();
+    let value = if (value - nearest).abs() < 1e-9 { nearest } else { value };
+    let rounded = match rounding {
+        "floor" => value.floor(),
+        "ceil" => value.ceil(),
+        _ => nearest,
+    };
+    (rounded as u32).max(1)
+}
+
+/// Dimensions with square pixels for a source whose pixels are `aspect`
+/// (width / height) wide. Only ever enlarges: wide pixels stretch the width,
+/// tall pixels the height.
+pub fn desqueeze_dimensions(width: u32, height: u32, aspect: f32) -> Result<(u32, u32), String> {
+    if !aspect.is_finite() || aspect <= 0.0 {
+        return Err(format!("Invalid pixel aspect {}: must be a positive number", aspect));
+    }
+    let stretch = |size: u32, factor: f64| ((size as f64 * factor).round() as u32).max(1);
+    Ok(if aspect >= 1.0 {
+        (stretch(width, aspect as f64), height)
+    } else {
+        (width, stretch(height, 1.0 / aspect as f64))
+    })
+}
+
+/// Scale down proportionally so neither dimension exceeds its limit.
+/// Returns the source dimensions unchanged when they already fit (never upscales).
+pub fn calculate_max_dimensions(
+    src_width: u32,
+    src_height: u32,
+    max_width: Option<u32>,
+    max_height: Option<u32>,
+) -> (u32, u32) {
+    let scale_x = max_width.map_or(1.0, |m| m as f64 / src_width as f64);
+    let scale_y = max_height.map_or(1.0, |m| m as f64 / src_height as f64);
+    let scale = scale_x.min(scale_y);
+    if scale >= 1.0 {
+        return (src_width, src_height);
+    }
+    let new_w = ((src_width as f64 * scale).round() as u32).min(max_width.unwrap_or(u32::MAX));
+    let new_h = ((src_height as f64 * scale).round() as u32).min(max_height.unwrap_or(u32::MAX));
+    (new_w.max(1), new_h.max(1))
+}
+
+/// Scale down so `width * height <= max_megapixels * 1_000_000`, preserving aspect ratio.
+/// Returns the source dimensions unchanged when already under the limit.
+pub fn calculate_megapixel_dimensions(src_width: u32, src_height: u32, max_megapixels: f32) -> (u32, u32) {
+    let limit = max_megapixels as f64 * 1_000_000.0;
+    let pixels = src_width as f64 * src_height as f64;
+    if pixels <= limit || limit <= 0.0 {
+        return (src_width, src_height);
+    }
+    let scale = (limit / pixels).sqrt();
+    let (mut new_w, mut new_h) = (
+        (src_width as f64 * scale).round(),
+        (src_height as f64 * scale).round(),
+    );
+    // Rounding up can push us just over the limit
+    if new_w * new_h > limit {
+        new_w = (src_width as f64 * scale).floor();
+        new_h = (src_height as f64 * scale).floor();
+    }
+    ((new_w as u32).max(1), (new_h as u32).max(1))
+}
+
 /// Crop an RGBA image to the specified region.
 pub fn crop_image(
     data: &[u8],
@@ -69,6 +179,242 @@ pub fn crop_image(
     result
 }
 
+/// Shrink and shift `region` so it lies within a `width` x `height` image.
+/// Guards the cover crop against a scaled size that rounded a pixel short
+/// of the target.
+pub fn clamp_crop_region((x, y, crop_w, crop_h): CropRegion, width: u32, height: u32) -> CropRegion {
+    let (crop_w, crop_h) = (crop_w.min(width), crop_h.min(height));
+    (x.min(width - crop_w), y.min(height - crop_h), crop_w, crop_h)
+}
+
+/// Convert a crop box in normalized 0-1 coordinates to a pixel region.
+/// Edges are rounded independently (so adjacent boxes tile without gaps) and
+/// clamped to the image; the region is always at least 1x1.
+pub fn normalized_crop_region(width: u32, height: u32, x: f32, y: f32, w: f32, h: f32) -> CropRegion {
+    let edges = |start: f32, len: f32, size: u32| {
+        let to_px = |v: f32| ((v.clamp(0.0, 1.0) as f64) * size as f64).round() as u32;
+        let lo = to_px(start).min(size.saturating_sub(1));
+        let hi = to_px(start + len).clamp(lo + 1, size.max(1));
+        (lo, hi - lo)
+    };
+    let (left, crop_w) = edges(x, w, width);
+    let (top, crop_h) = edges(y, h, height);
+    (left, top, crop_w, crop_h)
+}
+
+/// Largest region matching `aspect_w:aspect_h` that fits in the image,
+/// positioned by gravity ("center", "top", "bottom", "left", "right",
+/// or combinations like "top_left"). Returns the full image for a zero aspect.
+pub fn aspect_crop_region(
+    width: u32,
+    height: u32,
+    aspect_w: u32,
+    aspect_h: u32,
+    gravity: &str,
+) -> CropRegion {
+    if aspect_w == 0 || aspect_h == 0 {
+        return (0, 0, width, height);
+    }
+    let (crop_w, crop_h) = if width as u64 * aspect_h as u64 > height as u64 * aspect_w as u64 {
+        // Too wide: keep full height
+        ((height as u64 * aspect_w as u64 / aspect_h as u64) as u32, height)
+    } else {
+        // Too tall (or exact): keep full width
+        (width, (width as u64 * aspect_h as u64 / aspect_w as u64) as u32)
+    };
+    let (crop_w, crop_h) = (crop_w.max(1), crop_h.max(1));
+
+    let x = if gravity.contains("left") {
+        0
+    } else if gravity.contains("right") {
+        width - crop_w
+    } else {
+        (width - crop_w) / 2
+    };
+    let y = if gravity.contains("top") {
+        0
+    } else if gravity.contains("bottom") {
+        height - crop_h
+    } else {
+        (height - crop_h) / 2
+    };
+    (x, y, crop_w, crop_h)
+}
+
+/// Crop to a target aspect ratio at full resolution (no resampling).
+pub fn crop_to_aspect(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    aspect_w: u32,
+    aspect_h: u32,
+    gravity: &str,
+) -> (Vec<u8>, u32, u32) {
+    let (x, y, crop_w, crop_h) = aspect_crop_region(width, height, aspect_w, aspect_h, gravity);
+    (crop_image(data, width, height, x, y, crop_w, crop_h), crop_w, crop_h)
+}
+
+/// Filters accepted by `resize_image`, in order from fastest to best quality.
+pub const FILTERS: [&str; 5] = ["Nearest", "Bilinear", "CatmullRom", "Mitchell", "Lanczos3"];
+
+/// Debug/analysis helper: resize with every filter and score how much of the
+/// source survives. Each result is scaled back to the source size (Bilinear)
+/// and compared to the original with SSIM, so higher is better.
+/// Returns (filter name, score) in `FILTERS` order.
+pub fn compare_filters(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    target_width: u32,
+    target_height: u32,
+) -> Result<Vec<(String, f64)>, String> {
+    FILTERS
+        .iter()
+        .map(|&filter| {
+            let resized = resize_image(data, width, height, target_width, target_height, filter)?;
+            let restored = resize_image(&resized, target_width, target_height, width, height, "Bilinear")?;
+            Ok((filter.to_string(), crate::filters::ssim(data, &restored, width, height)))
+        })
+        .collect()
+}
+
+/// Place an RGBA image onto a `canvas_width` x `canvas_height` canvas filled
+/// with `color`, with its top-left corner at (x, y). Pixels falling outside
+/// the canvas are clipped.
+#[allow(clippy::too_many_arguments)]
+pub fn pad_image(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    canvas_width: u32,
+    canvas_height: u32,
+    x: u32,
+    y: u32,
+    color: [u8; 4],
+) -> Vec<u8> {
+    let mut canvas: Vec<u8> = color
+        .iter()
+        .copied()
+        .cycle()
+        .take(canvas_width as usize * canvas_height as usize * 4)
+        .collect();
+    let copy_w = width.min(canvas_width.saturating_sub(x)) as usize;
+    for row in 0..height.min(canvas_height.saturating_sub(y)) as usize {
+        let src = row * width as usize * 4;
+        let dst = ((y as usize + row) * canvas_width as usize + x as usize) * 4;
+        canvas[dst..dst + copy_w * 4].copy_from_slice(&data[src..src + copy_w * 4]);
+    }
+    canvas
+}
+
+/// Downscale factor at or above which `prefilter_blur` is applied.
+pub const PREFILTER_MIN_FACTOR: f32 = 2.0;
+
+/// Anti-aliasing prefilter: Gaussian-blur the source before a large downscale
+/// to suppress moire from detail finer than the output can represent.
+/// Returns None when the resize isn't a large enough downscale to need it.
+pub fn prefilter(
+    data: &[u8],
+    src_width: u32,
+    src_height: u32,
+    dst_width: u32,
+    dst_height: u32,
+    sigma: f32,
+) -> Option<Vec<u8>> {
+    let factor = (src_width as f32 / dst_width.max(1) as f32).min(src_height as f32 / dst_height.max(1) as f32);
+    if sigma <= 0.0 || factor < PREFILTER_MIN_FACTOR {
+        return None;
+    }
+    Some(crate::filters::gaussian_blur(data, src_width, src_height, sigma))
+}
+
+/// Pad (never scale) the right and bottom edges up to the next multiple of
+/// `multiple` in each dimension, e.g. for ML tiling or block-based codecs.
+/// mode: "constant" fills with `color`, "edge" repeats the last row/column,
+/// "reflect" mirrors content across the edge (falling back to edge when the
+/// image is too small to mirror).
+pub fn pad_to_multiple(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    multiple: u32,
+    mode: &str,
+    color: [u8; 4],
+) -> (Vec<u8>, u32, u32) {
+    if multiple <= 1 || width == 0 || height == 0 {
+        return (data.to_vec(), width, height);
+    }
+    let new_w = width.div_ceil(multiple) * multiple;
+    let new_h = height.div_ceil(multiple) * multiple;
+    if (new_w, new_h) == (width, height) {
+        return (data.to_vec(), width, height);
+    }
+
+    let mut padded = pad_image(data, width, height, new_w, new_h, 0, 0, color);
+    if mode == "constant" {
+        return (padded, new_w, new_h);
+    }
+
+    // Source coordinate for a position past the edge
+    let source = |pos: u32, size: u32| -> u32 {
+        if mode == "reflect" && pos <= 2 * (size - 1) {
+            2 * (size - 1) - pos
+        } else {
+            size - 1
+        }
+    };
+    let (w, nw) = (width as usize, new_w as usize);
+    for y in 0..new_h {
+        let sy = if y < height { y } else { source(y, height) } as usize;
+        for x in 0..new_w {
+            if x < width && y < height {
+                continue;
+            }
+            let sx = if x < width { x } else { source(x, width) } as usize;
+            let src = (sy * w + sx) * 4;
+            let dst = (y as usize * nw + x as usize) * 4;
+            padded[dst..dst + 4].copy_from_slice(&data[src..src + 4]);
+        }
+    }
+    (padded, new_w, new_h)
+}
+
+/// Contact sheet: fit each (pixels, width, height) image inside a
+/// `cell_width` x `cell_height` cell (contain, centered) and lay the cells out
+/// left to right in rows of `cols`, `gap` pixels apart, on a `bg` canvas.
+/// Returns (pixels, width, height) of the sheet.
+pub fn make_grid(
+    cells: &[(Vec<u8>, u32, u32)],
+    cols: u32,
+    cell_width: u32,
+    cell_height: u32,
+    gap: u32,
+    bg: [u8; 4],
+) -> Result<(Vec<u8>, u32, u32), String> {
+    if cols == 0 || cell_width == 0 || cell_height == 0 {
+        return Err("Grid needs at least one column and a non-empty cell size".to_string());
+    }
+    let cols = cols.min(cells.len().max(1) as u32);
+    let rows = (cells.len() as u32).div_ceil(cols).max(1);
+    let sheet_width = cols * cell_width + (cols - 1) * gap;
+    let sheet_height = rows * cell_height + (rows - 1) * gap;
+    let mut sheet = bg.repeat(sheet_width as usize * sheet_height as usize);
+
+    for (i, (data, width, height)) in cells.iter().enumerate() {
+        let (fit_w, fit_h, _) = calculate_fit_dimensions(*width, *height, cell_width, cell_height, "contain", "round");
+        let fitted = resize_image(data, *width, *height, fit_w, fit_h, "Lanczos3")?;
+        let (col, row) = (i as u32 % cols, i as u32 / cols);
+        let x = (col * (cell_width + gap) + (cell_width - fit_w) / 2) as usize;
+        let y = (row * (cell_height + gap) + (cell_height - fit_h) / 2) as usize;
+        let row_bytes = fit_w as usize * 4;
+        for (dy, src) in fitted.chunks_exact(row_bytes).enumerate() {
+            let dst = ((y + dy) * sheet_width as usize + x) * 4;
+            sheet[dst..dst + row_bytes].copy_from_slice(src);
+        }
+    }
+    Ok((sheet, sheet_width, sheet_height))
+}
+
 pub fn resize_image(
     data: &[u8],
     src_width: u32,
@@ -77,24 +423,134 @@ pub fn resize_image(
     dst_height: u32,
     filter: &str,
 ) -> Result<Vec<u8>, String> {
+    let mut output = vec![0u8; dst_width as usize * dst_height as usize * 4];
+    resize_into(data, src_width, src_height, &mut output, dst_width, dst_height, filter)?;
+    Ok(output)
+}
+
+/// Same as `resize_image`, but writes the RGBA result into a caller-provided
+/// buffer, which must be exactly `dst_width * dst_height * 4` bytes.
+pub fn resize_into(
+    data: &[u8],
+    src_width: u32,
+    src_height: u32,
+    dst: &mut [u8],
+    dst_width: u32,
+    dst_height: u32,
+    filter: &str,
+) -> Result<(), String> {
     if src_width == 0 || src_height == 0 || dst_width == 0 || dst_height == 0 {
-        return Err("Invalid dimensions".to_string());
+        return Err(format!(
+            "Cannot resize {}x{} to {}x{}: dimensions must be non-zero",
+            src_width, src_height, dst_width, dst_height
+        ));
+    }
+
+    crate::codecs::check_rgba_len(data, src_width, src_height)?;
+
+    let expected_len = dst_width as usize * dst_height as usize * 4;
+    if dst.len() != expected_len {
+        return Err(format!(
+            "Output buffer length {} doesn't match expected {} for {}x{} RGBA image",
+            dst.len(),
+            expected_len,
+            dst_width,
+            dst_height
+        ));
+    }
+
+    // Nearest never blends pixels, so skip the premultiply round-trip and copy
+    // source pixels verbatim (keeps the RGB of fully transparent pixels intact)
+    if filter == "Nearest" {
+        nearest_into(data, src_width, src_height, dst, dst_width, dst_height);
+        return Ok(());
+    }
+
+    // A kernel wider than a 1-2px output axis only rings, so box-average
+    // that axis instead and leave the convolution to the other one
+    if dst_width <= 2 || dst_height <= 2 {
+        let (mut current, mut width, mut height) = (data.to_vec(), src_width, src_height);
+        if dst_width <= 2 && dst_width != width {
+            current = area_resize_axis(&current, width, height, dst_width, true);
+            width = dst_width;
+        }
+        if dst_height <= 2 && dst_height != height {
+            current = area_resize_axis(&current, width, height, dst_height, false);
+            height = dst_height;
+        }
+        if (width, height) == (dst_width, dst_height) {
+            dst.copy_from_slice(&current);
+            return Ok(());
+        }
+        return convolve_into(&current, width, height, dst, dst_width, dst_height, filter);
     }
 
+    convolve_into(data, src_width, src_height, dst, dst_width, dst_height, filter)
+}
+
+/// Resize one axis to `len` pixels by exact area coverage, alpha-weighted so
+/// the color of transparent pixels doesn't leak in.
+fn area_resize_axis(data: &[u8], width: u32, height: u32, len: u32, horizontal: bool) -> Vec<u8> {
+    let (w, h) = (width as usize, height as usize);
+    let (src_len, lines) = if horizontal { (w, h) } else { (h, w) };
+    let (out_w, out_h) = if horizontal { (len as usize, h) } else { (w, len as usize) };
+    let scale = src_len as f64 / len as f64;
+    let mut result = vec![0u8; out_w * out_h * 4];
+
+    for line in 0..lines {
+        for i in 0..len as usize {
+            let (start, end) = (i as f64 * scale, (i + 1) as f64 * scale);
+            let mut sums = [0f64; 4];
+            let mut plain = [0f64; 3];
+            for j in start.floor() as usize..(end.ceil() as usize).min(src_len) {
+                let weight = (end.min(j as f64 + 1.0) - start.max(j as f64)).max(0.0);
+                let idx = if horizontal { (line * w + j) * 4 } else { (j * w + line) * 4 };
+                let alpha = data[idx + 3] as f64 * weight;
+                for c in 0..3 {
+                    sums[c] += data[idx + c] as f64 * alpha;
+                    plain[c] += data[idx + c] as f64 * weight;
+                }
+                sums[3] += alpha;
+            }
+            let dst = if horizontal { (line * out_w + i) * 4 } else { (i * out_w + line) * 4 };
+            for c in 0..3 {
+                // Fully transparent spans have no weighted color; keep their plain average
+                let value = if sums[3] > 0.0 { sums[c] / sums[3] } else { plain[c] / (end - start) };
+                result[dst + c] = value.round().clamp(0.0, 255.0) as u8;
+            }
+            result[dst + 3] = (sums[3] / (end - start)).round().clamp(0.0, 255.0) as u8;
+        }
+    }
+    result
+}
+
+/// Premultiplied fast_image_resize convolution into `dst`.
+fn convolve_into(
+    data: &[u8],
+    src_width: u32,
+    src_height: u32,
+    dst: &mut [u8],
+    dst_width: u32,
+    dst_height: u32,
+    filter: &str,
+) -> Result<(), String> {
     // 1. Create source image wrapper
-    // PixelType U8x4 is RGBA8
-    let src_image = Image::from_vec_u8(src_width, src_height, data.to_vec(), PixelType::U8x4)
+    // Work in U16x4 (RGBA16): premultiplied 8-bit values can't represent the
+    // color of low-alpha pixels (red at alpha 3 premultiplies to 3), so after
+    // filtering they de-multiply to black and leave a dark fringe along
+    // transparent edges.
+    let src_image = Image::from_vec_u8(src_width, src_height, widen_to_u16(data), PixelType::U16x4)
         .map_err(|e| format!("Failed to create source image: {:?}", e))?;
 
     // 2. Pre-multiply alpha (critical for correct resizing of transparent images)
     let mul_div = MulDiv::default();
-    let mut src_premultiplied = Image::new(src_width, src_height, PixelType::U8x4);
+    let mut src_premultiplied = Image::new(src_width, src_height, PixelType::U16x4);
     mul_div
         .multiply_alpha(&src_image, &mut src_premultiplied)
         .map_err(|e| format!("Pre-multiply alpha failed: {:?}", e))?;
 
     // 3. Create destination image
-    let mut dst_image = Image::new(dst_width, dst_height, PixelType::U8x4);
+    let mut dst_image = Image::new(dst_width, dst_height, PixelType::U16x4);
 
     // 4. Configure Resizer
     let mut resizer = Resizer::new();
@@ -116,10 +572,291 @@ pub fn resize_image(
         .map_err(|e| format!("Resize failed: {:?}", e))?;
 
     // 6. De-multiply alpha back
-    let mut dst_final = Image::new(dst_width, dst_height, PixelType::U8x4);
+    let mut dst_final = Image::new(dst_width, dst_height, PixelType::U16x4);
     mul_div
         .divide_alpha(&dst_image, &mut dst_final)
         .map_err(|e| format!("De-multiply alpha failed: {:?}", e))?;
 
-    Ok(dst_final.into_vec())
+    narrow_into(dst_final.buffer(), dst);
+    Ok(())
+}
+
+/// Plain nearest-neighbor sampling (pixel centers), exact source values.
+fn nearest_into(data: &[u8], src_width: u32, src_height: u32, dst: &mut [u8], dst_width: u32, dst_height: u32) {
+    let src_x: Vec<usize> = (0..dst_width as u64)
+        .map(|x| ((2 * x + 1) * src_width as u64 / (2 * dst_width as u64)) as usize)
+        .collect();
+    for y in 0..dst_height as u64 {
+        let sy = ((2 * y + 1) * src_height as u64 / (2 * dst_height as u64)) as usize;
+        let src_row = sy * src_width as usize * 4;
+        let dst_row = y as usize * dst_width as usize * 4;
+        for (x, &sx) in src_x.iter().enumerate() {
+            let s = src_row + sx * 4;
+            let d = dst_row + x * 4;
+            dst[d..d + 4].copy_from_slice(&data[s..s + 4]);
+        }
+    }
+}
+
+/// Expand 8-bit samples to native-endian 16-bit (v * 257 maps 255 to 65535).
+fn widen_to_u16(data: &[u8]) -> Vec<u8> {
+    data.iter().flat_map(|&v| (v as u16 * 257).to_ne_bytes()).collect()
+}
+
+/// Round native-endian 16-bit samples back to 8-bit, writing into `dst`.
+fn narrow_into(data: &[u8], dst: &mut [u8]) {
+    for (out, v) in dst.iter_mut().zip(data.chunks_exact(2)) {
+        *out = ((u16::from_ne_bytes([v[0], v[1]]) as u32 * 255 + 32767) / 65535) as u8;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resize_into_matches_resize_image() {
+        let data: Vec<u8> = (0..12u32 * 9)
+            .flat_map(|i| [(i * 7) as u8, (i * 3) as u8, 200, (i * 11) as u8])
+            .collect();
+        let expected = resize_image(&data, 12, 9, 5, 4, "Lanczos3").unwrap();
+
+        let mut buffer = vec![0xAB; 5 * 4 * 4];
+        resize_into(&data, 12, 9, &mut buffer, 5, 4, "Lanczos3").unwrap();
+        assert_eq!(buffer, expected);
+
+        let mut short = vec![0u8; 5 * 4 * 4 - 1];
+        assert!(resize_into(&data, 12, 9, &mut short, 5, 4, "Lanczos3").is_err());
+    }
+
+    #[test]
+    fn test_extreme_downscale_to_single_pixel() {
+        // Resizing is a single pass, so even a 10000:1 reduction is one call
+        let data: Vec<u8> = [40, 120, 200, 255].repeat(10000 * 2);
+        for filter in ["Nearest", "Bilinear", "Lanczos3"] {
+            let out = resize_image(&data, 10000, 2, 1, 1, filter).unwrap();
+            assert_eq!(out, [40, 120, 200, 255], "{}", filter);
+        }
+    }
+
+    #[test]
+    fn test_tiny_targets_area_average_the_short_axis() {
+        // 9x6 horizontal gradient with a transparent (garbage RGB) column
+        let data: Vec<u8> = (0..6)
+            .flat_map(|_| (0..9u8).map(|x| if x == 4 { [255, 0, 255, 0] } else { [x * 30, 100, 200, 255] }))
+            .flatten()
+            .collect();
+        for filter in ["Bilinear", "Lanczos3"] {
+            // 1x6: each row averages its 8 opaque pixels, ignoring the transparent one
+            let column = resize_image(&data, 9, 6, 1, 6, filter).unwrap();
+            let expected_red = ((0..9).filter(|&x| x != 4).map(|x| x * 30).sum::<u32>() as f64 / 8.0).round() as u8;
+            let alpha = (255.0 * 8.0 / 9.0f64).round() as u8;
+            assert_eq!(column, [expected_red, 100, 200, alpha].repeat(6), "{}", filter);
+
+            // 9x1: every column of the gradient survives unchanged
+            let row = resize_image(&data, 9, 6, 9, 1, filter).unwrap();
+            assert_eq!(row, data[..9 * 4], "{}", filter);
+
+            // 2x3: the short axis is split in halves, the other convolved
+            let small = resize_image(&data, 9, 6, 2, 3, filter).unwrap();
+            assert_eq!(small.len(), 2 * 3 * 4);
+            assert!(small.chunks_exact(4).all(|px| px[1] == 100 && px[2] == 200), "{}: {:?}", filter, small);
+        }
+    }
+
+    #[test]
+    fn test_make_grid_places_cells_with_gap() {
+        let cells: Vec<(Vec<u8>, u32, u32)> = [[255, 0, 0, 255], [0, 255, 0, 255], [0, 0, 255, 255], [9, 9, 9, 255]]
+            .iter()
+            .map(|px| (px.to_vec(), 1, 1))
+            .collect();
+        let bg = [0, 0, 0, 0];
+        let (sheet, width, height) = make_grid(&cells, 2, 2, 2, 1, bg).unwrap();
+        assert_eq!((width, height), (5, 5));
+
+        let at = |x: usize, y: usize| &sheet[(y * 5 + x) * 4..(y * 5 + x) * 4 + 4];
+        for (i, (x, y)) in [(0, 0), (3, 0), (0, 3), (3, 3)].into_iter().enumerate() {
+            for (dx, dy) in [(0, 0), (1, 1)] {
+                assert_eq!(at(x + dx, y + dy), &cells[i].0[..], "cell {}", i);
+            }
+        }
+        // The gap row and column stay background
+        for i in 0..5 {
+            assert_eq!(at(2, i), bg);
+            assert_eq!(at(i, 2), bg);
+        }
+    }
+
+    #[test]
+    fn test_prefilter_reduces_moire() {
+        // 1px-wide stripes with period 3, decimated by a non-integer factor
+        let (w, h) = (96u32, 8u32);
+        let data: Vec<u8> = (0..h)
+            .flat_map(|_| (0..w).flat_map(|x| if x % 3 == 0 { [255, 255, 255, 255] } else { [0, 0, 0, 255] }))
+            .collect();
+        let spread = |pixels: &[u8]| {
+            let values: Vec<f64> = pixels.chunks(4).map(|px| px[0] as f64).collect();
+            let mean = values.iter().sum::<f64>() / values.len() as f64;
+            (values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / values.len() as f64).sqrt()
+        };
+
+        let plain = resize_image(&data, w, h, 20, 2, "Nearest").unwrap();
+        let blurred = prefilter(&data, w, h, 20, 2, 1.5).unwrap();
+        let filtered = resize_image(&blurred, w, h, 20, 2, "Nearest").unwrap();
+        assert!(spread(&filtered) < spread(&plain) / 4.0, "{} vs {}", spread(&filtered), spread(&plain));
+
+        assert!(prefilter(&data, w, h, 60, 5, 1.5).is_none(), "mild downscale skips prefilter");
+    }
+
+    #[test]
+    fn test_nearest_upscale_copies_exact_pixels() {
+        // 2x2 sprite with a transparent corner carrying garbage RGB
+        let sprite = [
+            [12, 34, 56, 0], [255, 0, 0, 255], //
+            [0, 255, 0, 128], [0, 0, 255, 255],
+        ];
+        let data: Vec<u8> = sprite.iter().flatten().copied().collect();
+        let out = resize_image(&data, 2, 2, 6, 6, "Nearest").unwrap();
+        for y in 0..6 {
+            for x in 0..6 {
+                let idx = (y * 6 + x) * 4;
+                assert_eq!(out[idx..idx + 4], sprite[(y / 3) * 2 + x / 3], "({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn test_fit_rounding_modes_on_half_pixel() {
+        // 400x100 contained in 6x6: the height scales to exactly 1.5
+        assert_eq!(calculate_fit_dimensions(400, 100, 6, 6, "contain", "round"), (6, 2, None));
+        assert_eq!(calculate_fit_dimensions(400, 100, 6, 6, "contain", "floor"), (6, 1, None));
+        assert_eq!(calculate_fit_dimensions(400, 100, 6, 6, "contain", "ceil"), (6, 2, None));
+        // 100x300 covering 7x7: width lands on 7 only up to float noise, height on 21
+        for rounding in ["round", "floor", "ceil"] {
+            assert_eq!(calculate_fit_dimensions(100, 300, 7, 7, "cover", rounding), (7, 21, Some((0, 7, 7, 7))));
+        }
+        // 500x300 outside 5x5: width scales to 8.33..
+        assert_eq!(calculate_fit_dimensions(500, 300, 5, 5, "outside", "round"), (8, 5, None));
+        assert_eq!(calculate_fit_dimensions(500, 300, 5, 5, "outside", "floor"), (8, 5, None));
+        assert_eq!(calculate_fit_dimensions(500, 300, 5, 5, "outside", "ceil"), (9, 5, None));
+    }
+
+    #[test]
+    fn test_contain_or_crop_crops_small_overflow() {
+        // 1050x1000 into 500x500: cover loses ~5% of the width, so crop
+        assert_eq!(
+            calculate_fit_dimensions(1050, 1000, 500, 500, "contain_or_crop", "round"),
+            (525, 500, Some((12, 0, 500, 500)))
+        );
+    }
+
+    #[test]
+    fn test_contain_or_crop_pads_large_overflow() {
+        // 2000x1000 into 500x500: cover would lose half the width, so contain
+        assert_eq!(calculate_fit_dimensions(2000, 1000, 500, 500, "contain_or_crop", "round"), (500, 250, None));
+    }
+
+    #[test]
+    fn test_pad_to_multiple_keeps_content_and_fills() {
+        let data: Vec<u8> = (0..30u32 * 30).flat_map(|i| [(i % 30) as u8, (i / 30) as u8, 7, 255]).collect();
+        let fill = [1, 2, 3, 4];
+        let (padded, w, h) = pad_to_multiple(&data, 30, 30, 16, "constant", fill);
+        assert_eq!((w, h), (32, 32));
+        let at = |buf: &[u8], x: usize, y: usize| buf[(y * 32 + x) * 4..(y * 32 + x) * 4 + 4].to_vec();
+        assert_eq!(at(&padded, 29, 29), vec![29, 29, 7, 255]); // content unscaled
+        assert_eq!(at(&padded, 30, 5), fill.to_vec());
+        assert_eq!(at(&padded, 31, 31), fill.to_vec());
+
+        let (edge, _, _) = pad_to_multiple(&data, 30, 30, 16, "edge", fill);
+        assert_eq!(at(&edge, 31, 5), vec![29, 5, 7, 255]);
+        let (reflect, _, _) = pad_to_multiple(&data, 30, 30, 16, "reflect", fill);
+        assert_eq!(at(&reflect, 31, 30), vec![27, 28, 7, 255]);
+
+        assert_eq!(pad_to_multiple(&data, 30, 30, 10, "constant", fill).1, 30); // already aligned
+    }
+
+    #[test]
+    fn test_normalized_crop_region_rounds_and_clamps() {
+        assert_eq!(normalized_crop_region(100, 80, 0.0, 0.0, 1.0, 1.0), (0, 0, 100, 80));
+        assert_eq!(normalized_crop_region(100, 80, 0.25, 0.25, 0.5, 0.5), (25, 20, 50, 40));
+        // Out-of-range box is clamped, degenerate box still yields 1px
+        assert_eq!(normalized_crop_region(100, 80, 0.9, -0.5, 0.5, 2.0), (90, 0, 10, 80));
+        assert_eq!(normalized_crop_region(100, 80, 1.0, 0.5, 0.0, 0.0), (99, 40, 1, 1));
+    }
+
+    #[test]
+    fn test_max_dimensions_scales_down_proportionally() {
+        assert_eq!(calculate_max_dimensions(3000, 1000, Some(1500), Some(1500)), (1500, 500));
+        assert_eq!(calculate_max_dimensions(1000, 3000, None, Some(1500)), (500, 1500));
+    }
+
+    #[test]
+    fn test_megapixel_dimensions_preserve_aspect() {
+        let (w, h) = calculate_megapixel_dimensions(6000, 4000, 12.0);
+        assert_eq!((w, h), (4243, 2828));
+        assert!(w as u64 * h as u64 <= 12_000_000);
+        assert_eq!(calculate_megapixel_dimensions(1000, 1000, 12.0), (1000, 1000));
+    }
+
+    #[test]
+    fn test_crop_to_aspect_centers_region() {
+        assert_eq!(aspect_crop_region(1000, 1000, 16, 9, "center"), (0, 219, 1000, 562));
+        assert_eq!(aspect_crop_region(1000, 1000, 16, 9, "top"), (0, 0, 1000, 562));
+        assert_eq!(aspect_crop_region(1600, 400, 1, 1, "right"), (1200, 0, 400, 400));
+
+        let mut data = vec![0u8; 4 * 4 * 4];
+        data[(4 + 1) * 4] = 255; // marker at (1, 1)
+        let (cropped, w, h) = crop_to_aspect(&data, 4, 4, 2, 1, "center");
+        assert_eq!((w, h), (4, 2));
+        assert_eq!(cropped[4], 255); // row 1 became row 0
+    }
+
+    #[test]
+    fn test_compare_filters_scores_each_filter() {
+        // Fine stripes with a period that doesn't divide the scale factor
+        let (w, h) = (48u32, 48u32);
+        let mut data = Vec::with_capacity((w * h * 4) as usize);
+        for _y in 0..h {
+            for x in 0..w {
+                let v = if (x / 2) % 3 == 0 { 255 } else { 0 };
+                data.extend_from_slice(&[v, v, v, 255]);
+            }
+        }
+        let scores = compare_filters(&data, w, h, 20, 20).unwrap();
+        assert_eq!(scores.len(), FILTERS.len());
+        let score = |name: &str| scores.iter().find(|(f, _)| f == name).unwrap().1;
+        assert!(scores.iter().all(|(_, s)| s.is_finite()));
+        assert_ne!(score("Lanczos3"), score("Nearest"));
+    }
+
+    #[test]
+    fn test_resize_transparent_edge_has_no_color_halo() {
+        // Opaque red | faint red (alpha 3) | transparent with garbage green RGB
+        let (w, h) = (17u32, 5u32);
+        let mut data = Vec::with_capacity((w * h * 4) as usize);
+        for _y in 0..h {
+            for x in 0..w {
+                let px = match x {
+                    0..=7 => [255, 0, 0, 255],
+                    8..=11 => [255, 0, 0, 3],
+                    _ => [0, 255, 0, 0],
+                };
+                data.extend_from_slice(&px);
+            }
+        }
+        for filter in FILTERS {
+            for (tw, th) in [(7, 2), (40, 12)] {
+                let out = resize_image(&data, w, h, tw, th, filter).unwrap();
+                for px in out.chunks(4).filter(|px| px[3] > 0) {
+                    assert!(px[0] >= 200 && px[1] <= 5 && px[2] <= 5, "{} {}x{}: {:?}", filter, tw, th, px);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_max_dimensions_noop_when_within_limits() {
+        assert_eq!(calculate_max_dimensions(400, 300, Some(1500), Some(1500)), (400, 300));
+        assert_eq!(calculate_max_dimensions(400, 300, None, None), (400, 300));
+    }
 }
@@ -1,5 +1,7 @@
+use serde::{Deserialize, Serialize};
+
 /// Rotate RGBA image 90 degrees clockwise
-pub fn rotate_90_cw(data: &[u8], width: u32, height: u32) -> (Vec<u8>, u32, u32) {
+fn rotate_90_cw(data: &[u8], width: u32, height: u32) -> (Vec<u8>, u32, u32) {
     let new_width = height;
     let new_height = width;
     let mut result = vec![0u8; (new_width * new_height * 4) as usize];
@@ -17,7 +19,7 @@ pub fn rotate_90_cw(data: &[u8], width: u32, height: u32) -> (Vec<u8>, u32, u32)
 }
 
 /// Rotate RGBA image 180 degrees
-pub fn rotate_180(data: &[u8], width: u32, height: u32) -> Vec<u8> {
+fn rotate_180(data: &[u8], width: u32, height: u32) -> Vec<u8> {
     let mut result = vec![0u8; data.len()];
     let total_pixels = (width * height) as usize;
 
@@ -30,7 +32,7 @@ pub fn rotate_180(data: &[u8], width: u32, height: u32) -> Vec<u8> {
 }
 
 /// Rotate RGBA image 270 degrees clockwise (90 CCW)
-pub fn rotate_270_cw(data: &[u8], width: u32, height: u32) -> (Vec<u8>, u32, u32) {
+fn rotate_270_cw(data: &[u8], width: u32, height: u32) -> (Vec<u8>, u32, u32) {
     let new_width = height;
     let new_height = width;
     let mut result = vec![0u8; (new_width * new_height * 4) as usize];
@@ -48,7 +50,7 @@ pub fn rotate_270_cw(data: &[u8], width: u32, height: u32) -> (Vec<u8>, u32, u32
 }
 
 /// Flip RGBA image horizontally
-pub fn flip_horizontal(data: &[u8], width: u32, height: u32) -> Vec<u8> {
+fn flip_horizontal(data: &[u8], width: u32, height: u32) -> Vec<u8> {
     let mut result = vec![0u8; data.len()];
 
     for y in 0..height {
@@ -62,7 +64,7 @@ pub fn flip_horizontal(data: &[u8], width: u32, height: u32) -> Vec<u8> {
 }
 
 /// Flip RGBA image vertically
-pub fn flip_vertical(data: &[u8], width: u32, height: u32) -> Vec<u8> {
+fn flip_vertical(data: &[u8], width: u32, height: u32) -> Vec<u8> {
     let mut result = vec![0u8; data.len()];
 
     for y in 0..height {
@@ -75,35 +77,162 @@ pub fn flip_vertical(data: &[u8], width: u32, height: u32) -> Vec<u8> {
     result
 }
 
-/// Apply all transforms in order: rotate, then flip
+/// Lossless clockwise rotation in 90-degree steps.
+/// Deserializes from the integer angle and rejects anything else.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(try_from = "u16", into = "u16")]
+pub enum Rotation {
+    #[default]
+    None,
+    Cw90,
+    Cw180,
+    Cw270,
+}
+
+impl TryFrom<u16> for Rotation {
+    type Error = String;
+
+    fn try_from(degrees: u16) -> Result<Self, Self::Error> {
+        match degrees {
+            0 => Ok(Rotation::None),
+            90 => Ok(Rotation::Cw90),
+            180 => Ok(Rotation::Cw180),
+            270 => Ok(Rotation::Cw270),
+            _ => Err(format!(
+                "Unsupported rotation {}: rotate accepts 0, 90, 180 or 270",
+                degrees
+            )),
+        }
+    }
+}
+
+impl From<Rotation> for u16 {
+    fn from(rotation: Rotation) -> u16 {
+        match rotation {
+            Rotation::None => 0,
+            Rotation::Cw90 => 90,
+            Rotation::Cw180 => 180,
+            Rotation::Cw270 => 270,
+        }
+    }
+}
+
+/// Rotation and flips (applied in that order) that display an image stored
+/// with the given EXIF orientation (1-8) upright.
+pub fn exif_orientation_transforms(orientation: u16) -> (Rotation, bool, bool) {
+    match orientation {
+        2 => (Rotation::None, true, false),
+        3 => (Rotation::Cw180, false, false),
+        4 => (Rotation::None, false, true),
+        5 => (Rotation::Cw90, true, false), // Transpose
+        6 => (Rotation::Cw90, false, false),
+        7 => (Rotation::Cw90, false, true), // Transverse
+        8 => (Rotation::Cw270, false, false),
+        _ => (Rotation::None, false, false),
+    }
+}
+
+/// Rotate clockwise by any angle, growing the canvas to the rotated bounds.
+/// Pixels are sampled bilinearly with premultiplied alpha; corners the source
+/// doesn't cover (and its anti-aliased edges) blend with `fill`.
+pub fn rotate_arbitrary(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    degrees: f32,
+    fill: [u8; 4],
+) -> Result<(Vec<u8>, u32, u32), String> {
+    crate::codecs::check_rgba_len(data, width, height)?;
+    if !degrees.is_finite() {
+        return Err(format!("Invalid rotation angle {}", degrees));
+    }
+
+    let (sin, cos) = (degrees as f64).to_radians().sin_cos();
+    let (w, h) = (width as f64, height as f64);
+    // Trim float noise so e.g. 90 degrees doesn't gain a pixel
+    let extent = |size: f64| ((size - 1e-6).ceil() as u32).max(1);
+    let new_width = extent(w * cos.abs() + h * sin.abs());
+    let new_height = extent(w * sin.abs() + h * cos.abs());
+
+    let fetch = |x: i64, y: i64| -> [f64; 4] {
+        let px = if x < 0 || y < 0 || x >= width as i64 || y >= height as i64 {
+            fill
+        } else {
+            let idx = ((y as u32 * width + x as u32) * 4) as usize;
+            [data[idx], data[idx + 1], data[idx + 2], data[idx + 3]]
+        };
+        let alpha = px[3] as f64;
+        [px[0] as f64 * alpha, px[1] as f64 * alpha, px[2] as f64 * alpha, alpha]
+    };
+
+    let mut result = vec![0u8; (new_width * new_height * 4) as usize];
+    for y in 0..new_height {
+        for x in 0..new_width {
+            // Offset from the output center, rotated back into the source
+            let dx = x as f64 + 0.5 - new_width as f64 / 2.0;
+            let dy = y as f64 + 0.5 - new_height as f64 / 2.0;
+            let u = dx * cos + dy * sin + w / 2.0 - 0.5;
+            let v = -dx * sin + dy * cos + h / 2.0 - 0.5;
+            let (x0, y0) = (u.floor() as i64, v.floor() as i64);
+            let (fx, fy) = (u - x0 as f64, v - y0 as f64);
+
+            let mut sum = [0f64; 4];
+            for (sx, sy, weight) in [
+                (x0, y0, (1.0 - fx) * (1.0 - fy)),
+                (x0 + 1, y0, fx * (1.0 - fy)),
+                (x0, y0 + 1, (1.0 - fx) * fy),
+                (x0 + 1, y0 + 1, fx * fy),
+            ] {
+                let px = fetch(sx, sy);
+                for c in 0..4 {
+                    sum[c] += px[c] * weight;
+                }
+            }
+            let idx = ((y * new_width + x) * 4) as usize;
+            if sum[3] > 0.0 {
+                for c in 0..3 {
+                    result[idx + c] = (sum[c] / sum[3]).round().clamp(0.0, 255.0) as u8;
+                }
+            }
+            result[idx + 3] = sum[3].round().clamp(0.0, 255.0) as u8;
+        }
+    }
+    Ok((result, new_width, new_height))
+}
+
+/// Apply all transforms in order: rotate, then flip.
+/// This is the module's only entry point: the buffer is validated once here,
+/// so the index arithmetic in the individual transforms can't go out of bounds.
 pub fn apply_transforms(
     data: &[u8],
     width: u32,
     height: u32,
-    rotate: u16,
+    rotate: Rotation,
     flip_h: bool,
     flip_v: bool,
-) -> (Vec<u8>, u32, u32) {
+) -> Result<(Vec<u8>, u32, u32), String> {
+    crate::codecs::check_rgba_len(data, width, height)?;
+
     let (mut current_data, mut current_w, mut current_h) = (data.to_vec(), width, height);
 
     // Apply rotation
     match rotate {
-        90 => {
+        Rotation::Cw90 => {
             let (rotated, new_w, new_h) = rotate_90_cw(&current_data, current_w, current_h);
             current_data = rotated;
             current_w = new_w;
             current_h = new_h;
         }
-        180 => {
+        Rotation::Cw180 => {
             current_data = rotate_180(&current_data, current_w, current_h);
         }
-        270 => {
+        Rotation::Cw270 => {
             let (rotated, new_w, new_h) = rotate_270_cw(&current_data, current_w, current_h);
             current_data = rotated;
             current_w = new_w;
             current_h = new_h;
         }
-        _ => {} // 0 or invalid - no rotation
+        Rotation::None => {}
     }
 
     // Apply flips
@@ -114,5 +243,44 @@ pub fn apply_transforms(
         current_data = flip_vertical(&current_data, current_w, current_h);
     }
 
-    (current_data, current_w, current_h)
+    Ok((current_data, current_w, current_h))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mismatched_buffers_error_instead_of_panicking() {
+        let rotations = [Rotation::None, Rotation::Cw90, Rotation::Cw180, Rotation::Cw270];
+        for len in [5 * 3 * 4 - 4, 5 * 3 * 4 - 1, 5 * 3 * 4 + 4, 0] {
+            let data = vec![7u8; len];
+            for rotate in rotations {
+                for (flip_h, flip_v) in [(false, false), (true, false), (false, true)] {
+                    let err = apply_transforms(&data, 5, 3, rotate, flip_h, flip_v).unwrap_err();
+                    assert!(err.starts_with("Dimension mismatch"), "{}", err);
+                }
+            }
+        }
+
+        let (rotated, w, h) = apply_transforms(&[1; 5 * 3 * 4], 5, 3, Rotation::Cw90, true, false).unwrap();
+        assert_eq!((rotated.len(), w, h), (60, 3, 5));
+    }
+
+    #[test]
+    fn test_rotate_arbitrary_grows_canvas_and_fills_corners() {
+        let data: Vec<u8> = (0..20 * 10).flat_map(|i| [(i % 20 * 12) as u8, 80, 160, 255]).collect();
+        let (rotated, w, h) = rotate_arbitrary(&data, 20, 10, 30.0, [0, 0, 0, 0]).unwrap();
+        // 20 cos 30 + 10 sin 30 = 22.3, 20 sin 30 + 10 cos 30 = 18.7
+        assert_eq!((w, h), (23, 19));
+        assert_eq!(rotated[3], 0);
+        let center = ((h / 2 * w + w / 2) * 4) as usize;
+        assert_eq!(rotated[center + 3], 255);
+
+        // Quarter turns keep the size
+        let (quarter, w, h) = rotate_arbitrary(&data, 20, 10, 90.0, [0, 0, 0, 0]).unwrap();
+        assert_eq!((w, h), (10, 20));
+        assert!(quarter.chunks_exact(4).all(|px| px[3] == 255));
+        assert!(rotate_arbitrary(&data, 20, 10, f32::NAN, [0; 4]).is_err());
+    }
 }